@@ -0,0 +1,41 @@
+//! 解析流程的基准测试：`cargo bench`
+#![allow(clippy::needless_return)]
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use mdppet::snip::{get_snippet_segments, Snippet};
+
+/// 生成含有 `count` 个 Snippet 的 Markdown 文档
+fn synthesize(count: usize) -> String {
+    let scopes = ["rust", "python", "lua,c"];
+    let mut text = String::new();
+    for i in 0..count {
+        text.push_str(&format!(
+            "# snip{i}/s{i}/{scope}\n\n第 {i} 个 Snippet 的描述\n第二行描述\n\n```{lang}\nfn snip{i}() {{\n    let x = {i};\n}}\n```\n\n",
+            i = i,
+            scope = scopes[i % scopes.len()],
+            lang = scopes[i % scopes.len()].split(',').next().unwrap(),
+        ));
+    }
+    return text;
+}
+
+fn parse(c: &mut Criterion) {
+    let text = synthesize(5000);
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Bytes(text.len() as u64));
+    group.bench_function("segments", |b| {
+        b.iter(|| get_snippet_segments(black_box(&text)).len())
+    });
+    group.bench_function("segments+from_markdown", |b| {
+        b.iter(|| {
+            get_snippet_segments(black_box(&text))
+                .iter()
+                .map(|&md| Snippet::from_markdown(md))
+                .collect::<Vec<Snippet>>()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
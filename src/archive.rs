@@ -0,0 +1,162 @@
+use crate::error::MdppetError;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek};
+use std::path::{Component, Path, PathBuf};
+use zip::ZipArchive;
+
+/// `path` 是否为 zip 压缩包（按扩展名判断）
+pub fn is_archive(path: &Path) -> bool {
+    return path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"));
+}
+
+/// 将压缩包中的路径 `collection.zip/dir/a.md` 拆分为压缩包和其中的条目名 `dir/a.md`
+///
+/// 路径中的 `.` 和 `..` 先按字面消去；不在压缩包中时返回 `None`。
+pub fn split(path: &Path) -> Option<(PathBuf, String)> {
+    let path = normalize(path);
+    for archive in path.ancestors().skip(1) {
+        if is_archive(archive) && archive.is_file() {
+            let member: Vec<String> = path
+                .strip_prefix(archive)
+                .unwrap()
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            return Some((archive.to_path_buf(), member.join("/")));
+        }
+    }
+    return None;
+}
+
+/// 按字面消去路径中的 `.` 和 `..`，不访问文件系统
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if normalized.file_name().is_some() => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    return normalized;
+}
+
+/// 压缩包中所有 `.md` 条目的路径，形如 `collection.zip/dir/a.md`，按条目名排序
+pub fn members(archive: &Path) -> Result<Vec<PathBuf>, MdppetError> {
+    let io_err = |e| MdppetError::Io(archive.to_path_buf(), e);
+    let file = File::open(archive).map_err(io_err)?;
+    let names = markdown_entries(file).map_err(io_err)?;
+    return Ok(names.iter().map(|name| archive.join(name)).collect());
+}
+
+/// 读取压缩包中的条目 `member`
+pub fn read(archive: &Path, member: &str) -> Result<Vec<u8>, MdppetError> {
+    let io_err = |e| MdppetError::Io(archive.join(member), e);
+    let file = File::open(archive).map_err(io_err)?;
+    return read_entry(file, member).map_err(io_err);
+}
+
+/// 列出压缩包中的 Markdown 条目，其他条目和目录被跳过
+///
+/// 含有越出压缩包的 `..` 的条目（如 `../x.md`）和绝对路径的条目同样被跳过，见 `ZipFile::enclosed_name`；
+/// 后者的 `enclosed_name` 去掉了开头的 `/`，与条目名不同。
+fn markdown_entries<R: Read + Seek>(reader: R) -> io::Result<Vec<String>> {
+    let mut archive = ZipArchive::new(reader)?;
+    let mut names: Vec<String> = Vec::new();
+    for index in 0..archive.len() {
+        let entry = archive.by_index_raw(index)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let name = entry.name()?;
+        let enclosed = entry.enclosed_name();
+        if enclosed.as_deref() == Some(Path::new(&*name)) && is_markdown(&name) {
+            names.push(name.into_owned());
+        }
+    }
+    names.sort();
+    return Ok(names);
+}
+
+fn read_entry<R: Read + Seek>(reader: R, member: &str) -> io::Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(reader)?;
+    let mut entry = archive.by_name(member)?;
+    let mut bytes: Vec<u8> = Vec::new();
+    entry.read_to_end(&mut bytes)?;
+    return Ok(bytes);
+}
+
+fn is_markdown(name: &str) -> bool {
+    return Path::new(name)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("md"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    /// 在内存中打包 `entries`（条目名和内容）
+    fn zip(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options =
+            SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        for (name, content) in entries.iter() {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        return writer.finish().unwrap().into_inner();
+    }
+
+    #[test]
+    fn test_markdown_entries() {
+        let bytes = zip(&[
+            ("z.md", "# z/z/rust\n"),
+            ("README.txt", "not a snippet"),
+            ("dir/a.MD", "# a/a/rust\n"),
+            ("../escaped.md", "# e/e/rust\n"),
+            ("/abs.md", "# e/e/rust\n"),
+        ]);
+        let names = markdown_entries(Cursor::new(&bytes)).unwrap();
+        assert_eq!(names, vec!["dir/a.MD", "z.md"]);
+        let content = read_entry(Cursor::new(&bytes), "z.md").unwrap();
+        assert_eq!(content, b"# z/z/rust\n");
+        assert!(read_entry(Cursor::new(&bytes), "missing.md").is_err());
+    }
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            normalize(Path::new("c.zip/./nested/../loops.md")),
+            Path::new("c.zip/loops.md")
+        );
+        assert_eq!(normalize(Path::new("../a.md")), Path::new("../a.md"));
+    }
+    #[test]
+    fn test_load_from_archive() {
+        let bytes = zip(&[
+            ("main.md", "<!-- mdppet:include ./nested/../loops.md -->\n"),
+            ("loops.md", "# loop/lp/rust\n\n```rust\nloop {}\n```\n"),
+            ("notes.txt", "ignored"),
+        ]);
+        let path = std::env::temp_dir().join(format!("mdppet-archive-{}.zip", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+
+        let sources = members(&path).unwrap();
+        assert_eq!(sources, vec![path.join("loops.md"), path.join("main.md")]);
+        assert_eq!(
+            split(&sources[1]),
+            Some((path.clone(), String::from("main.md")))
+        );
+        let source = crate::source::load(&sources[1]).unwrap();
+        assert!(source.text.starts_with("# loop/lp/rust"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,244 @@
+use crate::error::MdppetError;
+use crate::output::Format;
+use crate::snip::Snippet;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// `mdppet build` 默认读取的配置文件
+pub const CONFIG_FILE: &str = "mdppet.toml";
+
+/// `mdppet.toml` 的内容
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+    #[serde(default)]
+    targets: BTreeMap<String, RawTarget>,
+}
+
+/// `[targets.<名称>]` 中的一个构建目标，路径尚未解析
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct RawTarget {
+    sources: Vec<String>,
+    #[serde(default)]
+    format: Option<String>,
+    #[serde(default)]
+    output: Option<String>,
+    #[serde(default)]
+    out_dir: Option<String>,
+    #[serde(default)]
+    include_scopes: Option<Vec<String>>,
+    #[serde(default)]
+    exclude_scopes: Vec<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// 构建目标写入的位置
+#[derive(Debug, Clone, PartialEq)]
+pub enum Dest {
+    /// 合并写入一个文件，对应 `-o`
+    File(PathBuf),
+    /// 按源文件写入目录，对应 `--out-dir`
+    Dir(PathBuf),
+}
+
+/// 一个构建目标：各自的源文件、格式、输出位置和筛选条件
+///
+/// 相对路径以配置文件所在的目录为基准。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub name: String,
+    /// 源文件、目录或压缩包，尚未展开
+    pub sources: Vec<PathBuf>,
+    pub format: Format,
+    pub dest: Dest,
+    pub include_scopes: Option<Vec<String>>,
+    pub exclude_scopes: Vec<String>,
+    /// 只保留元数据 `[tags=...]` 中带有其中任一标签的 Snippet，为空时不筛选
+    pub tags: Vec<String>,
+}
+
+impl Target {
+    /// Snippet 是否通过该目标的作用域和标签筛选
+    pub fn keeps(&self, snip: &Snippet) -> bool {
+        if let Some(include) = &self.include_scopes {
+            if !snip.in_scopes(include) {
+                return false;
+            }
+        }
+        if snip.in_scopes(&self.exclude_scopes) {
+            return false;
+        }
+        return self.tags.is_empty() || snip.has_tag(&self.tags);
+    }
+}
+
+/// 读取 `path` 中定义的构建目标，按名称排序
+pub fn load(path: &Path) -> Result<Vec<Target>, MdppetError> {
+    let text = fs::read_to_string(path).map_err(|e| MdppetError::Io(path.to_path_buf(), e))?;
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    return parse(&text, base).map_err(|message| MdppetError::Config(path.to_path_buf(), message));
+}
+
+/// 解析配置文件的内容，相对路径以 `base` 为基准
+pub fn parse(text: &str, base: &Path) -> Result<Vec<Target>, String> {
+    let raw: RawConfig = toml::from_str(text).map_err(|e| e.to_string().trim().to_string())?;
+    if raw.targets.is_empty() {
+        return Err(String::from("no [targets.<name>] table is defined"));
+    }
+    let mut targets: Vec<Target> = Vec::new();
+    for (name, raw) in raw.targets {
+        if raw.sources.is_empty() {
+            return Err(format!("target `{}` has no sources", name));
+        }
+        let format = match raw.format.as_deref() {
+            Some(format) => Format::from_name(format)
+                .ok_or_else(|| format!("target `{}` has an unknown format `{}`", name, format))?,
+            None => Format::Json,
+        };
+        let dest = match (raw.output, raw.out_dir) {
+            (Some(_), Some(_)) => {
+                return Err(format!(
+                    "target `{}` sets both `output` and `out-dir`",
+                    name
+                ))
+            }
+            (None, Some(dir)) => Dest::Dir(base.join(dir)),
+            (_, None) if format == Format::TmSnippet => {
+                return Err(format!(
+                "target `{}`: format tmsnippet writes one file per snippet and requires `out-dir`",
+                name
+            ))
+            }
+            (Some(file), None) => Dest::File(base.join(file)),
+            (None, None) => Dest::File(base.join(format.default_dest())),
+        };
+        targets.push(Target {
+            sources: raw.sources.iter().map(|src| base.join(src)).collect(),
+            format,
+            dest,
+            include_scopes: raw.include_scopes,
+            exclude_scopes: raw.exclude_scopes,
+            tags: raw.tags,
+            name,
+        });
+    }
+    return Ok(targets);
+}
+
+/// 按名称选出要构建的目标，`names` 为空时构建所有目标
+pub fn select(targets: Vec<Target>, names: &[&str]) -> Result<Vec<Target>, MdppetError> {
+    if names.is_empty() {
+        return Ok(targets);
+    }
+    let mut selected: Vec<Target> = Vec::new();
+    for name in names.iter() {
+        match targets.iter().find(|target| target.name == *name) {
+            Some(target) => selected.push(target.clone()),
+            None => {
+                let defined = targets.iter().map(|target| target.name.clone()).collect();
+                return Err(MdppetError::UnknownTarget(String::from(*name), defined));
+            }
+        }
+    }
+    return Ok(selected);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snip;
+
+    const CONFIG: &str = r#"
+[targets.vscode]
+sources = ["snippets"]
+format = "code-snippets"
+output = "out/rust.code-snippets"
+include-scopes = ["rust"]
+
+[targets.zed]
+sources = ["snippets", "extra.md"]
+format = "zed"
+out-dir = "zed"
+tags = ["core"]
+"#;
+
+    #[test]
+    fn test_parse() {
+        let targets = parse(CONFIG, Path::new("ws")).unwrap();
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[0].name, "vscode");
+        assert_eq!(targets[0].format, Format::CodeSnippets);
+        assert_eq!(
+            targets[0].dest,
+            Dest::File(Path::new("ws").join("out/rust.code-snippets"))
+        );
+        assert_eq!(targets[0].include_scopes, Some(vec![String::from("rust")]));
+        assert_eq!(targets[1].dest, Dest::Dir(Path::new("ws").join("zed")));
+        assert_eq!(
+            targets[1].sources,
+            vec![
+                Path::new("ws").join("snippets"),
+                Path::new("ws").join("extra.md")
+            ]
+        );
+        let defaults = parse("[targets.a]\nsources = [\"a.md\"]\n", Path::new("")).unwrap();
+        assert_eq!(defaults[0].format, Format::Json);
+        assert_eq!(defaults[0].dest, Dest::File(PathBuf::from("out.json")));
+    }
+    #[test]
+    fn test_parse_errors() {
+        let base = Path::new("");
+        assert!(parse("", base).unwrap_err().contains("no [targets.<name>]"));
+        assert!(parse("[targets.a]\nsources = []\n", base)
+            .unwrap_err()
+            .contains("no sources"));
+        assert!(
+            parse("[targets.a]\nsources = [\"a\"]\nformat = \"yaml2\"\n", base)
+                .unwrap_err()
+                .contains("unknown format `yaml2`")
+        );
+        assert!(parse(
+            "[targets.a]\nsources = [\"a\"]\noutput = \"x\"\nout-dir = \"y\"\n",
+            base
+        )
+        .unwrap_err()
+        .contains("both"));
+        assert!(parse(
+            "[targets.a]\nsources = [\"a\"]\nformat = \"tmsnippet\"\n",
+            base
+        )
+        .unwrap_err()
+        .contains("requires `out-dir`"));
+        assert!(parse("[targets.a]\nsources = [\"a\"]\nscopes = []\n", base).is_err());
+    }
+    #[test]
+    fn test_select() {
+        let targets = parse(CONFIG, Path::new("")).unwrap();
+        assert_eq!(select(targets.clone(), &[]).unwrap().len(), 2);
+        let selected = select(targets.clone(), &["zed"]).unwrap();
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].name, "zed");
+        match select(targets, &["nvim"]) {
+            Err(MdppetError::UnknownTarget(name, defined)) => {
+                assert_eq!(name, "nvim");
+                assert_eq!(defined, vec!["vscode", "zed"]);
+            }
+            other => panic!("应当报告未定义的目标，实际为 {:?}", other),
+        }
+    }
+    #[test]
+    fn test_keeps() {
+        let targets = parse(CONFIG, Path::new("")).unwrap();
+        let text = "# fn/fn/rust\n[tags=extra, core]\n\n```rust\nfn\n```\n";
+        let tagged = &snip::parse_snippets(text, &snip::ParseOptions::default()).unwrap()[0];
+        let plain = Snippet::from_text("fn", "fn", "rust", "fn", "").unwrap();
+        assert!(targets[0].keeps(tagged));
+        assert!(!targets[0].keeps(&Snippet::from_text("def", "def", "python", "def", "").unwrap()));
+        assert!(targets[1].keeps(tagged));
+        assert!(!targets[1].keeps(&plain), "没有标签的 Snippet 应当被跳过");
+    }
+}
@@ -0,0 +1,423 @@
+use crate::error::MdppetError;
+use anstyle::{AnsiColor, Style};
+use serde::Serialize;
+use std::fmt;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+
+/// 诊断信息的严重程度
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Severity {
+    /// 消息开头的标签
+    pub fn label(&self) -> &'static str {
+        match self {
+            Severity::Warning => return "warning",
+            Severity::Error => return "error",
+        }
+    }
+}
+
+/// 是否为消息着色：`--color`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    /// 输出到终端且没有设置 `NO_COLOR` 时着色
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// `--color` 可以接受的取值
+    pub const NAMES: &'static [&'static str] = &["auto", "always", "never"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "auto" => return Some(ColorChoice::Auto),
+            "always" => return Some(ColorChoice::Always),
+            "never" => return Some(ColorChoice::Never),
+            _ => return None,
+        }
+    }
+
+    /// 输出到 `terminal`（是否为终端）的消息是否着色
+    pub fn enabled(&self, terminal: bool) -> bool {
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        return self.resolve(terminal, no_color);
+    }
+
+    /// 按 <https://no-color.org> 的约定，`NO_COLOR` 只影响 `auto`
+    fn resolve(&self, terminal: bool, no_color: bool) -> bool {
+        match self {
+            ColorChoice::Auto => return terminal && !no_color,
+            ColorChoice::Always => return true,
+            ColorChoice::Never => return false,
+        }
+    }
+}
+
+/// 一行消息：`mdppet: error: ...`，不含换行符
+///
+/// `color` 时为标签着色：`error` 为红色，`warning` 为黄色，其余标签只加粗。
+pub fn format_message(label: &str, message: &str, color: bool) -> String {
+    if !color {
+        return format!("{}: {}: {}", crate::BIN_NAME, label, message);
+    }
+    let style = match label {
+        "error" => Style::new().bold().fg_color(Some(AnsiColor::Red.into())),
+        "warning" => Style::new().bold().fg_color(Some(AnsiColor::Yellow.into())),
+        _ => Style::new().bold(),
+    };
+    return format!(
+        "{}: {}{}:{} {}",
+        crate::BIN_NAME,
+        style.render(),
+        label,
+        style.render_reset(),
+        message
+    );
+}
+
+/// 向标准错误输出一行消息，按 `color` 和标准错误是否为终端决定是否着色
+pub fn eprint_message(label: &str, message: &str, color: ColorChoice) {
+    let color = color.enabled(io::stderr().is_terminal());
+    eprintln!("{}", format_message(label, message, color));
+}
+
+/// 解析阶段发现的一个问题
+///
+/// 人类可读的输出（`Display`）和 `--message-format json` 的输出都由它生成。
+#[derive(Serialize, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// 机器可读的代码，如 `duplicate-id`、`parse-error`，见 `MdppetError::code`
+    pub code: &'static str,
+    pub file: PathBuf,
+    /// 从 1 开始计数；针对整个文件的问题（如读取失败）为 `None`
+    pub line: Option<usize>,
+    /// 从 1 开始计数；诊断信息都指向 Snippet 标题，因此有行号时总是 1
+    pub column: Option<usize>,
+    /// 涉及的 Snippet 标识符，未知时为 `None`
+    pub identifier: Option<String>,
+    pub message: String,
+    /// 错误对应的退出码，警告为 0
+    #[serde(skip)]
+    pub exit_code: i32,
+}
+
+impl Diagnostic {
+    pub fn warning(file: &Path, line: usize, code: &'static str, message: String) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            code,
+            file: file.to_path_buf(),
+            line: Some(line),
+            column: Some(1),
+            identifier: None,
+            message,
+            exit_code: 0,
+        }
+    }
+
+    /// 位于第 `line` 行的错误，`error` 的说明中不应包含文件名
+    pub fn error(file: &Path, line: usize, error: &MdppetError) -> Self {
+        Diagnostic {
+            line: Some(line),
+            column: Some(1),
+            ..Diagnostic::from_error(file, error)
+        }
+    }
+
+    /// 针对整个文件的错误，`error` 的说明中已经包含文件名
+    pub fn from_error(file: &Path, error: &MdppetError) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code: error.code(),
+            file: file.to_path_buf(),
+            line: None,
+            column: None,
+            identifier: error.identifier().map(String::from),
+            message: error.to_string(),
+            exit_code: error.exit_code(),
+        }
+    }
+
+    /// 附上涉及的 Snippet 标识符
+    pub fn with_identifier(mut self, identifier: &str) -> Self {
+        self.identifier = Some(String::from(identifier));
+        return self;
+    }
+}
+
+/// 诊断信息的输出格式：`--message-format`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageFormat {
+    /// `mdppet: warning: a.md:3: ...`
+    Human,
+    /// 每条诊断信息一行 JSON 对象
+    Json,
+}
+
+impl MessageFormat {
+    /// `--message-format` 可以接受的取值
+    pub const NAMES: &'static [&'static str] = &["human", "json"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "human" => return Some(MessageFormat::Human),
+            "json" => return Some(MessageFormat::Json),
+            _ => return None,
+        }
+    }
+
+    /// 按格式写成一行，不含换行符；`color` 只对人类可读的格式有效
+    pub fn render(&self, diagnostic: &Diagnostic, color: bool) -> String {
+        match self {
+            MessageFormat::Human => {
+                return format_message(
+                    diagnostic.severity.label(),
+                    &diagnostic.located_message(),
+                    color,
+                )
+            }
+            MessageFormat::Json => return serde_json::to_string(diagnostic).unwrap(),
+        }
+    }
+}
+
+impl Diagnostic {
+    /// 带有位置的说明：`a.md:3: ...`，针对整个文件时只有说明
+    fn located_message(&self) -> String {
+        match self.line {
+            Some(line) => return format!("{}:{}: {}", self.file.display(), line, self.message),
+            None => return self.message.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{}: {}", self.severity.label(), self.located_message());
+    }
+}
+
+/// 收集解析阶段的诊断信息
+///
+/// 默认收集所有问题，最后按文件和行号排序后一并输出；
+/// `--fail-fast` 时每条诊断信息立即输出，并在第一个错误处停止。
+#[derive(Debug)]
+pub struct Diagnostics {
+    items: Vec<Diagnostic>,
+    fail_fast: bool,
+    format: MessageFormat,
+    /// 输出到标准输出而不是标准错误：`--messages-on-stdout`
+    stdout: bool,
+    color: ColorChoice,
+    /// 到目前为止记录的警告和错误数，输出之后仍然保留
+    warnings: usize,
+    errors: usize,
+}
+
+impl Diagnostics {
+    pub fn new(fail_fast: bool) -> Self {
+        Diagnostics {
+            items: Vec::new(),
+            fail_fast,
+            format: MessageFormat::Human,
+            stdout: false,
+            color: ColorChoice::Never,
+            warnings: 0,
+            errors: 0,
+        }
+    }
+
+    /// 记录过的警告数和错误数
+    pub fn counts(&self) -> (usize, usize) {
+        return (self.warnings, self.errors);
+    }
+
+    /// 按 `format` 输出诊断信息，`stdout` 时写入标准输出
+    pub fn with_format(mut self, format: MessageFormat, stdout: bool) -> Self {
+        self.format = format;
+        self.stdout = stdout;
+        return self;
+    }
+
+    /// 按 `color` 为人类可读的诊断信息着色
+    pub fn with_color(mut self, color: ColorChoice) -> Self {
+        self.color = color;
+        return self;
+    }
+
+    fn emit(&self, diagnostic: &Diagnostic) {
+        if self.stdout {
+            let color = self.color.enabled(io::stdout().is_terminal());
+            println!("{}", self.format.render(diagnostic, color));
+        } else {
+            let color = self.color.enabled(io::stderr().is_terminal());
+            eprintln!("{}", self.format.render(diagnostic, color));
+        }
+    }
+
+    /// 向标准错误输出一条不属于诊断信息的提示
+    pub fn note(&self, message: &str) {
+        eprint_message("note", message, self.color);
+    }
+
+    /// 记录一条诊断信息，`fail_fast` 时遇到错误返回 `Err`
+    pub fn push(&mut self, diagnostic: Diagnostic) -> Result<(), MdppetError> {
+        match diagnostic.severity {
+            Severity::Warning => self.warnings += 1,
+            Severity::Error => self.errors += 1,
+        }
+        if !self.fail_fast {
+            self.items.push(diagnostic);
+            return Ok(());
+        }
+        self.emit(&diagnostic);
+        match diagnostic.severity {
+            Severity::Warning => return Ok(()),
+            Severity::Error => {
+                return Err(MdppetError::Reported {
+                    errors: 1,
+                    exit_code: diagnostic.exit_code,
+                })
+            }
+        }
+    }
+
+    /// 取出已收集、尚未输出的诊断信息，用于合并到另一个 `Diagnostics` 中
+    pub fn take(&mut self) -> Vec<Diagnostic> {
+        return self.items.drain(..).collect();
+    }
+
+    /// 按文件和行号排序，输出并清空已收集的诊断信息
+    ///
+    /// 存在错误时返回汇总的错误，其退出码取排序后第一个错误的退出码。
+    pub fn report(&mut self) -> Option<MdppetError> {
+        self.items
+            .sort_by(|a, b| (&a.file, a.line).cmp(&(&b.file, b.line)));
+        let mut errors: usize = 0;
+        let mut exit_code: i32 = 0;
+        let items: Vec<Diagnostic> = self.items.drain(..).collect();
+        for diagnostic in items {
+            self.emit(&diagnostic);
+            if diagnostic.severity == Severity::Error {
+                if errors == 0 {
+                    exit_code = diagnostic.exit_code;
+                }
+                errors += 1;
+            }
+        }
+        if errors == 0 {
+            return None;
+        }
+        return Some(MdppetError::Reported { errors, exit_code });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let warning = Diagnostic::warning(Path::new("a.md"), 3, "odd", String::from("looks odd"));
+        assert_eq!(warning.to_string(), "warning: a.md:3: looks odd");
+        let error = Diagnostic::from_error(
+            Path::new("a.md"),
+            &MdppetError::FrontMatter(PathBuf::from("a.md"), String::from("bad")),
+        );
+        assert_eq!(error.to_string(), "error: a.md: front matter: bad");
+        assert_eq!(error.exit_code, 2);
+    }
+    #[test]
+    fn test_render_json() {
+        let error = MdppetError::UndefinedVariable {
+            identifier: String::from("greet"),
+            name: String::from("who"),
+        };
+        let diagnostic = Diagnostic::error(Path::new("a.md"), 7, &error);
+        let value: serde_json::Value =
+            serde_json::from_str(&MessageFormat::Json.render(&diagnostic, true)).unwrap();
+        assert_eq!(value["severity"], "error");
+        assert_eq!(value["code"], "undefined-variable");
+        assert_eq!(value["file"], "a.md");
+        assert_eq!(
+            (value["line"].as_u64(), value["column"].as_u64()),
+            (Some(7), Some(1))
+        );
+        assert_eq!(value["identifier"], "greet");
+        assert_eq!(value["message"], error.to_string());
+        assert!(value.get("exit_code").is_none());
+        assert_eq!(
+            MessageFormat::Human.render(&diagnostic, false),
+            format!("mdppet: error: a.md:7: {}", error)
+        );
+    }
+    #[test]
+    fn test_color() {
+        assert!(
+            !ColorChoice::Auto.resolve(true, true),
+            "设置了 NO_COLOR 时不应着色"
+        );
+        assert!(ColorChoice::Auto.resolve(true, false));
+        assert!(!ColorChoice::Auto.resolve(false, false));
+        assert!(ColorChoice::Always.resolve(false, true));
+        assert!(!ColorChoice::Never.resolve(true, false));
+        assert_eq!(format_message("error", "bad", false), "mdppet: error: bad");
+        assert_eq!(
+            format_message("warning", "odd", true),
+            "mdppet: \x1b[1m\x1b[33mwarning:\x1b[0m odd"
+        );
+    }
+    #[test]
+    fn test_report_sorted() {
+        let mut diagnostics = Diagnostics::new(false);
+        let conflict = MdppetError::ConflictingSnippet {
+            identifier: String::from("x"),
+            sources: (String::from("a.md"), String::from("b.md")),
+            diff: Vec::new(),
+        };
+        let syntax = MdppetError::Syntax(String::from("bad"));
+        let items = vec![
+            Diagnostic::error(Path::new("b.md"), 1, &conflict),
+            Diagnostic::error(Path::new("a.md"), 9, &syntax),
+            Diagnostic::warning(Path::new("a.md"), 2, "odd", String::new()),
+        ];
+        for diagnostic in items {
+            diagnostics.push(diagnostic).unwrap();
+        }
+        assert_eq!(
+            diagnostics
+                .items
+                .iter()
+                .map(|d| d.line.unwrap())
+                .collect::<Vec<usize>>(),
+            vec![1, 9, 2]
+        );
+        match diagnostics.report() {
+            Some(MdppetError::Reported { errors, exit_code }) => {
+                assert_eq!(errors, 2);
+                assert_eq!(exit_code, 2);
+            }
+            other => panic!("应当报告错误，实际为 {:?}", other),
+        }
+        assert_eq!(diagnostics.counts(), (1, 2));
+        assert!(diagnostics.report().is_none());
+    }
+    #[test]
+    fn test_fail_fast() {
+        let mut diagnostics = Diagnostics::new(true);
+        let warning = Diagnostic::warning(Path::new("a.md"), 1, "odd", String::new());
+        assert!(diagnostics.push(warning).is_ok());
+        let error = Diagnostic::error(Path::new("a.md"), 2, &MdppetError::Syntax(String::new()));
+        assert!(diagnostics.push(error).is_err());
+        assert!(diagnostics.items.is_empty());
+    }
+}
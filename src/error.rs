@@ -0,0 +1,325 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// mdppet 运行过程中可能出现的错误
+#[derive(Debug)]
+pub enum MdppetError {
+    /// 读写文件时发生的 IO 错误
+    Io(PathBuf, io::Error),
+    /// 指定了 `--no-clobber`，但目标文件已存在
+    DestinationExists(PathBuf),
+    /// 目标文件已存在，但不是 mdppet 生成的，需要 `--force` 才能覆盖
+    ForeignDestination(PathBuf, &'static str),
+    /// 同一个标识符有多个内容不同的定义
+    ConflictingSnippet {
+        identifier: String,
+        /// 两处定义所在的文件
+        sources: (String, String),
+        /// 不同的字段
+        diff: Vec<String>,
+    },
+    /// `mdppet.toml` 有误
+    Config(PathBuf, String),
+    /// `mdppet build` 指定的目标没有在配置文件中定义，附带已定义的目标
+    UnknownTarget(String, Vec<String>),
+    /// 源文件的 front matter 有误
+    FrontMatter(PathBuf, String),
+    /// 源文件中的 Snippet 写法有误，所在的位置由诊断信息给出
+    Syntax(String),
+    /// Snippet 的代码块没有结尾，延伸到了下一个 Snippet 标题，附带该 Snippet 的标题行
+    UnterminatedFence(String),
+    /// `mdppet:extends` 指定的基础 Snippet 不存在
+    UnknownBase { identifier: String, base: String },
+    /// 循环继承，附带继承链
+    ExtendsCycle(Vec<String>),
+    /// 循环包含，附带包含链
+    IncludeCycle(Vec<PathBuf>),
+    /// include 嵌套超过了上限
+    IncludeDepth(PathBuf, usize),
+    /// `--preview` 或 `mdppet expand` 指定的标识符或前缀不存在，附带字段名和已解析的 Snippet 数量
+    SnippetNotFound(&'static str, String, usize),
+    /// 生成的结果不符合 VSCode 的 snippet schema 或输出格式的要求，附带所有违例
+    Validation(PathBuf, Vec<String>),
+    /// Snippet 中使用了未定义的构建时变量
+    UndefinedVariable { identifier: String, name: String },
+    /// 补全体中含有 NUL 字节，附带补全体中的行号和列号（字节偏移，从 1 开始）
+    NulByte {
+        identifier: String,
+        line: usize,
+        column: usize,
+    },
+    /// Snippet 的前缀为空，见 `Snippet::check_fields`
+    EmptyPrefix(String),
+    /// Snippet 的补全体去掉空白之后为空
+    EmptyBody(String),
+    /// `mdppet:output` 或 `@file` 指定的文件是绝对路径或含有 `..`，会写到输出目录之外
+    UnsafeOutput { identifier: String, output: String },
+    /// 输入文件不是合法的 UTF-8，附带第一个非法字节的偏移量
+    InvalidUtf8(PathBuf, usize),
+    /// `--out-dir` 时两个不同目录下的同名源文件会写入同一个输出文件，附带输出文件和这两个源文件
+    OutputCollision(PathBuf, PathBuf, PathBuf),
+    /// `--install` 找不到编辑器的代码片段目录，附带编辑器名称
+    NoInstallDir(&'static str),
+    /// `mdppet fmt` 无法在不改变解析结果的前提下整理该文件
+    Unformattable(PathBuf, String),
+    /// `mdppet fmt --check` 发现需要整理的文件，附带文件数量
+    Unformatted(usize),
+    /// `--check` 发现与重新生成的结果不一致的输出文件，附带文件数量
+    Outdated(usize),
+    /// `mdppet rename` 的旧名称没有出现在任何标题中，附带字段名
+    RenameNotFound(&'static str, String),
+    /// `mdppet rename` 的新名称已被其他 Snippet 使用，附带字段名
+    RenameCollision(&'static str, String),
+    /// 解析阶段的错误已经作为诊断信息输出，附带错误的数量和第一个错误的退出码
+    Reported { errors: usize, exit_code: i32 },
+}
+
+impl MdppetError {
+    /// 进程退出码：1 为读写错误，2 为输入有误（包括找不到 `--preview` 的 Snippet），3 为校验失败
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            MdppetError::Io(..)
+            | MdppetError::DestinationExists(_)
+            | MdppetError::ForeignDestination(..)
+            | MdppetError::NoInstallDir(_) => return 1,
+            MdppetError::Config(..)
+            | MdppetError::UnknownTarget(..)
+            | MdppetError::FrontMatter(..)
+            | MdppetError::Syntax(..)
+            | MdppetError::UnterminatedFence(_)
+            | MdppetError::UnknownBase { .. }
+            | MdppetError::ExtendsCycle(_)
+            | MdppetError::IncludeCycle(_)
+            | MdppetError::IncludeDepth(..)
+            | MdppetError::UndefinedVariable { .. }
+            | MdppetError::NulByte { .. }
+            | MdppetError::EmptyPrefix(_)
+            | MdppetError::EmptyBody(_)
+            | MdppetError::UnsafeOutput { .. }
+            | MdppetError::OutputCollision(..)
+            | MdppetError::SnippetNotFound(..)
+            | MdppetError::InvalidUtf8(..)
+            | MdppetError::Unformattable(..)
+            | MdppetError::RenameNotFound(..) => return 2,
+            MdppetError::ConflictingSnippet { .. }
+            | MdppetError::Validation(..)
+            | MdppetError::Unformatted(_)
+            | MdppetError::Outdated(_)
+            | MdppetError::RenameCollision(..) => return 3,
+            MdppetError::Reported { exit_code, .. } => return *exit_code,
+        }
+    }
+
+    /// 机器可读的错误代码，用于 `--message-format json`
+    pub fn code(&self) -> &'static str {
+        match self {
+            MdppetError::Io(..) => return "io-error",
+            MdppetError::DestinationExists(_) | MdppetError::ForeignDestination(..) => {
+                return "destination-exists"
+            }
+            MdppetError::ConflictingSnippet { .. } => return "duplicate-id",
+            MdppetError::Config(..) => return "config",
+            MdppetError::FrontMatter(..) => return "front-matter",
+            MdppetError::Syntax(_) => return "parse-error",
+            MdppetError::UnterminatedFence(_) => return "unterminated-fence",
+            MdppetError::UnknownBase { .. } => return "unknown-base",
+            MdppetError::ExtendsCycle(_) => return "extends-cycle",
+            MdppetError::IncludeCycle(_) => return "include-cycle",
+            MdppetError::IncludeDepth(..) => return "include-depth",
+            MdppetError::SnippetNotFound(..)
+            | MdppetError::UnknownTarget(..)
+            | MdppetError::RenameNotFound(..) => return "not-found",
+            MdppetError::Validation(..) => return "validation",
+            MdppetError::UndefinedVariable { .. } => return "undefined-variable",
+            MdppetError::NulByte { .. } => return "nul-byte",
+            MdppetError::EmptyPrefix(_) => return "empty-prefix",
+            MdppetError::EmptyBody(_) => return "empty-body",
+            MdppetError::UnsafeOutput { .. } => return "unsafe-output",
+            MdppetError::InvalidUtf8(..) => return "invalid-utf8",
+            MdppetError::OutputCollision(..) => return "output-collision",
+            MdppetError::NoInstallDir(_) => return "no-install-dir",
+            MdppetError::Unformattable(..) | MdppetError::Unformatted(_) => return "unformatted",
+            MdppetError::Outdated(_) => return "outdated",
+            MdppetError::RenameCollision(..) => return "rename-collision",
+            MdppetError::Reported { .. } => return "reported",
+        }
+    }
+
+    /// 错误涉及的 Snippet 标识符
+    pub fn identifier(&self) -> Option<&str> {
+        match self {
+            MdppetError::ConflictingSnippet { identifier, .. }
+            | MdppetError::UndefinedVariable { identifier, .. }
+            | MdppetError::NulByte { identifier, .. }
+            | MdppetError::UnknownBase { identifier, .. }
+            | MdppetError::UnsafeOutput { identifier, .. }
+            | MdppetError::EmptyPrefix(identifier)
+            | MdppetError::EmptyBody(identifier) => return Some(identifier),
+            _ => return None,
+        }
+    }
+
+    /// 是否为字段检查的错误，`--lenient` 时降级为警告并丢弃该 Snippet
+    pub fn is_field_error(&self) -> bool {
+        return matches!(
+            self,
+            MdppetError::EmptyPrefix(_) | MdppetError::EmptyBody(_)
+        );
+    }
+}
+
+impl fmt::Display for MdppetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MdppetError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            MdppetError::DestinationExists(path) => write!(
+                f,
+                "{}: destination already exists, refusing to overwrite (--no-clobber)",
+                path.display()
+            ),
+            MdppetError::ForeignDestination(path, reason) => write!(
+                f,
+                "{}: destination already exists and {}, use --force to overwrite it",
+                path.display(),
+                reason
+            ),
+            MdppetError::ConflictingSnippet {
+                identifier,
+                sources,
+                diff,
+            } => {
+                write!(
+                    f,
+                    "conflicting definitions of snippet `{}` in {} and {}:",
+                    identifier, sources.0, sources.1
+                )?;
+                for field in diff.iter() {
+                    write!(f, "\n    {}", field)?;
+                }
+                return Ok(());
+            }
+            MdppetError::Config(path, message) => write!(f, "{}: {}", path.display(), message),
+            MdppetError::UnknownTarget(name, defined) => write!(
+                f,
+                "no target named `{}`, the defined targets are: {}",
+                name,
+                defined.join(", ")
+            ),
+            MdppetError::FrontMatter(path, message) => {
+                write!(f, "{}: front matter: {}", path.display(), message)
+            }
+            MdppetError::Syntax(message) => write!(f, "{}", message),
+            MdppetError::UnterminatedFence(heading) => write!(
+                f,
+                "the code block under `{}` has no closing ``` before the next snippet heading",
+                heading
+            ),
+            MdppetError::UnknownBase { identifier, base } => write!(
+                f,
+                "snippet `{}` extends `{}`, but no snippet with that identifier is defined in this file",
+                identifier, base
+            ),
+            MdppetError::ExtendsCycle(chain) => {
+                write!(f, "extends cycle: {}", chain.join(" -> "))
+            }
+            MdppetError::IncludeCycle(chain) => {
+                let chain: Vec<String> = chain.iter().map(|p| p.display().to_string()).collect();
+                write!(f, "include cycle: {}", chain.join(" -> "))
+            }
+            MdppetError::IncludeDepth(path, limit) => write!(
+                f,
+                "{}: includes are nested more than {} levels deep",
+                path.display(),
+                limit
+            ),
+            MdppetError::SnippetNotFound(field, name, count) => write!(
+                f,
+                "no snippet with {} `{}` among the {} parsed snippet(s), use --list to see them",
+                field, name, count
+            ),
+            MdppetError::Validation(path, violations) => {
+                write!(
+                    f,
+                    "{}: output is not valid:",
+                    path.display()
+                )?;
+                for violation in violations.iter() {
+                    write!(f, "\n    {}", violation)?;
+                }
+                return Ok(());
+            }
+            MdppetError::UndefinedVariable { identifier, name } if name == "date" => write!(
+                f,
+                "snippet `{}` uses `{{{{date}}}}`, which is only defined with --date-var",
+                identifier
+            ),
+            MdppetError::UndefinedVariable { identifier, name } => write!(
+                f,
+                "snippet `{}` uses undefined variable `{{{{{}}}}}`, define it with --define {}=... or pass --allow-undefined-vars",
+                identifier, name, name
+            ),
+            MdppetError::NulByte {
+                identifier,
+                line,
+                column,
+            } => write!(
+                f,
+                "snippet `{}` has a NUL byte (0x00) in line {} of its body at byte {}; the source file is probably corrupted",
+                identifier, line, column
+            ),
+            MdppetError::EmptyPrefix(identifier) => {
+                write!(f, "snippet `{}` has an empty prefix", identifier)
+            }
+            MdppetError::EmptyBody(identifier) => {
+                write!(f, "snippet `{}` has an empty body", identifier)
+            }
+            MdppetError::UnsafeOutput { identifier, output } => write!(
+                f,
+                "snippet `{}` is routed to `{}`, which is outside the output directory; use a relative path without `..`",
+                identifier, output
+            ),
+            MdppetError::InvalidUtf8(path, offset) => write!(
+                f,
+                "{}: input is not valid UTF-8 (first invalid byte at offset {}): consider converting the file encoding",
+                path.display(),
+                offset
+            ),
+            MdppetError::OutputCollision(dest, first, second) => write!(
+                f,
+                "{} and {} would both be written to {}; rename one of them or build them separately",
+                first.display(),
+                second.display(),
+                dest.display()
+            ),
+            MdppetError::NoInstallDir(editor) => write!(
+                f,
+                "cannot locate the {} snippet directory: neither XDG_CONFIG_HOME nor HOME (APPDATA on Windows) is set",
+                editor
+            ),
+            MdppetError::Unformattable(path, message) => {
+                write!(f, "{}: cannot reformat: {}", path.display(), message)
+            }
+            MdppetError::Unformatted(count) => {
+                write!(f, "{} file(s) would be reformatted", count)
+            }
+            MdppetError::Outdated(count) => {
+                write!(f, "{} output file(s) are out of date", count)
+            }
+            MdppetError::RenameNotFound(field, name) => {
+                write!(f, "no snippet heading has the {} `{}`", field, name)
+            }
+            MdppetError::RenameCollision(field, name) => write!(
+                f,
+                "cannot rename: the {} `{}` is already used by another snippet",
+                field, name
+            ),
+            MdppetError::Reported { errors, .. } => {
+                write!(f, "found {} error(s) in the sources", errors)
+            }
+        }
+    }
+}
+
+impl Error for MdppetError {}
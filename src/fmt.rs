@@ -0,0 +1,376 @@
+use crate::snip::{self, ParseOptions, Snippet};
+use regex::Regex;
+use std::collections::BTreeMap;
+
+/// 文档中的一块：一个 Snippet 片段，或片段之间的其他内容
+enum Block<'a> {
+    /// 原样保留的正文，已去掉前后的空行
+    Prose(&'a str),
+    /// 按解析出的字段重新生成的片段，`start` 为标题在正文中的位置，`directives` 表示标题上方有指令，
+    /// `fences` 为标题之后的代码块个数
+    Section {
+        identifier: &'a str,
+        text: String,
+        start: usize,
+        directives: bool,
+        fences: usize,
+    },
+}
+
+/// 将 Markdown 源文件整理为规范的写法，对应 `mdppet fmt`
+///
+/// - 各块之间恰好隔一个空行，文件以一个换行结尾；
+/// - 标题、元数据、描述和第一个代码块由 `Snippet::to_markdown` 按原文重新写出（`#` 之后一个空格，标记之间一个空格）；
+/// - 元数据行与描述之间隔一个空行；
+/// - 只有一个代码块时，信息字符串中的语言改为第一个作用域；
+/// - `sort` 时按标识符排序：片段之间的其他内容保持原位，只在相邻的片段之间排序，
+///   以免改变分组标题对其后 Snippet 的作用；标题上方的 `mdppet:output` 等指令随片段移动。
+///
+/// 整理前后解析出的 Snippet 必须完全相同，否则返回错误。
+pub fn format(text: &str, options: &ParseOptions, sort: bool) -> Result<String, String> {
+    let (front_matter, body) = split_raw_front_matter(text);
+    let options = options
+        .with_front_matter(&snip::split_front_matter(text).0)
+        .map_err(|message| format!("front matter: {}", message))?;
+    let mut blocks = scan(body, &options);
+    if sort {
+        for run in blocks.split_mut(|block| matches!(block, Block::Prose(_))) {
+            run.sort_by(|a, b| identifier(a).cmp(identifier(b)));
+        }
+    }
+
+    let mut chunks: Vec<&str> = Vec::new();
+    if !front_matter.is_empty() {
+        chunks.push(front_matter.trim_end());
+    }
+    for block in blocks.iter() {
+        match block {
+            Block::Prose(prose) => chunks.push(prose),
+            Block::Section { text, .. } => chunks.push(text),
+        }
+    }
+    let mut formatted = chunks.join("\n\n");
+    if !formatted.is_empty() {
+        formatted.push('\n');
+    }
+
+    if parse(text, &options) != parse(&formatted, &options) {
+        return Err(String::from(
+            "reformatting would change the parsed snippets, the file is left unchanged",
+        ));
+    }
+    return Ok(formatted);
+}
+
+/// 按解析出的 Snippet 重新生成 Markdown 源文件，对应 `mdppet normalize`
+///
+/// 与 `format` 相同，片段之间的正文、HTML 注释和 front matter 原样保留，各块之间恰好隔一个空行；
+/// 不同的是每个片段都由 `Snippet::to_markdown` 按解析出的 Snippet 重新写出：
+/// 按语言拆分的代码块写作各自的 Snippet，标题中的作用域由分组标题补全。
+/// 标题上方有 `mdppet:output` 等指令的片段不展开，只按 `format` 的方式整理，指令保持原样。
+///
+/// 源文件有错误，或重新生成的结果解析出的 Snippet 不同时返回错误。
+pub fn normalize(text: &str, options: &ParseOptions) -> Result<String, String> {
+    let (front_matter, _) = split_raw_front_matter(text);
+    let options = options
+        .with_front_matter(&snip::split_front_matter(text).0)
+        .map_err(|message| format!("front matter: {}", message))?;
+    // front matter 替换为空行，行号与解析结果一致
+    let body = snip::split_front_matter(text).1;
+    let (stripped, origins) = snip::strip_html_comments_mapped(&body);
+    let (snips, errors) = snip::parse_snippets_collecting(&stripped, &options);
+    let origin = |line: usize| *origins.get(line - 1).unwrap_or(&line);
+    if let Some((line, e)) = errors.first() {
+        return Err(format!("line {}: {}", origin(*line), e));
+    }
+    let mut by_line: BTreeMap<usize, Vec<&Snippet>> = BTreeMap::new();
+    for (line, snip) in snips.iter() {
+        by_line.entry(origin(*line)).or_default().push(snip);
+    }
+    let render = snip::RenderOptions {
+        heading_level: options.heading_level,
+        field_sep: options.field_sep.clone(),
+        ..snip::RenderOptions::default()
+    };
+    let mut chunks: Vec<String> = Vec::new();
+    if !front_matter.is_empty() {
+        chunks.push(String::from(front_matter.trim_end()));
+    }
+    for block in scan(&body, &options) {
+        match block {
+            Block::Prose(prose) => chunks.push(String::from(prose)),
+            Block::Section {
+                text,
+                start,
+                directives,
+                fences,
+                ..
+            } => {
+                // 其后的代码块没有按语言拆分时不属于 Snippet，整段按 `format` 的方式保留
+                let line = body[..start].matches('\n').count() + 1;
+                match by_line
+                    .get(&line)
+                    .filter(|snips| !directives && snips.len() == fences)
+                {
+                    Some(snips) => {
+                        for snip in snips {
+                            chunks.push(String::from(snip.to_markdown(&render).trim_end()));
+                        }
+                    }
+                    None => chunks.push(text),
+                }
+            }
+        }
+    }
+    let mut normalized = chunks.join("\n\n");
+    if !normalized.is_empty() {
+        normalized.push('\n');
+    }
+
+    if parse(text, &options) != parse(&normalized, &options) {
+        return Err(String::from(
+            "normalizing would change the parsed snippets, the file is left unchanged",
+        ));
+    }
+    return Ok(normalized);
+}
+
+fn identifier<'a>(block: &Block<'a>) -> &'a str {
+    match block {
+        Block::Prose(_) => return "",
+        Block::Section { identifier, .. } => return identifier,
+    }
+}
+
+/// 拆分出原样的 front matter（含结束的 `---` 行）和其后的正文
+fn split_raw_front_matter(text: &str) -> (&str, &str) {
+    let mut end = 0;
+    for (index, line) in text.split_inclusive('\n').enumerate() {
+        end += line.len();
+        if index == 0 && line.trim_end() != "---" {
+            return ("", text);
+        }
+        if index > 0 && line.trim_end() == "---" {
+            return text.split_at(end);
+        }
+    }
+    return ("", text);
+}
+
+/// 将正文切分为片段和片段之间的其他内容
+///
+/// 与 `parse_snippets_collecting` 一样，片段包括标题之后紧跟的所有代码块。
+fn scan<'a>(text: &'a str, options: &ParseOptions) -> Vec<Block<'a>> {
+    let fence_re = Regex::new(snip::FOLLOWING_FENCE_RE).unwrap();
+    let directive_re = Regex::new(snip::DIRECTIVE_RE).unwrap();
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut consumed = 0;
+    for m in snip::split_segments(text, options).0 {
+        let whole = m.get(0).unwrap();
+        // 位于上一个片段的后续代码块之中，交给解析结果的比对去发现
+        if whole.start() < consumed {
+            continue;
+        }
+        let mut prose = &text[consumed..whole.start()];
+        // 紧挨在标题上方的连续几行指令
+        let mut directives: Vec<&str> = Vec::new();
+        let mut trimmed = prose.trim_end();
+        while !trimmed.is_empty() {
+            let (rest, last_line) = trimmed.rsplit_once('\n').unwrap_or(("", trimmed));
+            if !directive_re.is_match(last_line) {
+                break;
+            }
+            directives.insert(0, last_line.trim());
+            trimmed = rest;
+            prose = rest;
+        }
+        push_prose(&mut blocks, prose);
+
+        let mut fences = vec![(
+            snip::fence_group(&m, "lang").map(|lang| lang.as_str()),
+            snip::fence_group(&m, "attrs").map_or("", |attrs| attrs.as_str()),
+            snip::fence_group(&m, "body").unwrap().as_str(),
+        )];
+        consumed = whole.end();
+        while let Some(f) = fence_re.captures(&text[consumed..]) {
+            fences.push((
+                snip::fence_group(&f, "lang").map(|lang| lang.as_str()),
+                snip::fence_group(&f, "attrs").map_or("", |attrs| attrs.as_str()),
+                snip::fence_group(&f, "body").unwrap().as_str(),
+            ));
+            consumed += f.get(0).unwrap().end();
+        }
+
+        // 标题和第一个代码块由 `to_markdown` 写出，其后的代码块保持各自的语言
+        let render = snip::RenderOptions {
+            heading_level: options.heading_level,
+            field_sep: options.field_sep.clone(),
+            info_string: if fences.len() == 1 {
+                snip::InfoString::FirstScope
+            } else {
+                snip::InfoString::Fence
+            },
+            ..snip::RenderOptions::default()
+        };
+        let section = Snippet::from_segment(&m).to_markdown(&render);
+        let mut parts: Vec<String> = Vec::new();
+        if !directives.is_empty() {
+            parts.push(format!("{}\n{}", directives.join("\n"), section.trim_end()));
+        } else {
+            parts.push(String::from(section.trim_end()));
+        }
+        let fences_count = fences.len();
+        for (lang, attrs, body) in fences.into_iter().skip(1) {
+            parts.push(snip::render_fence(
+                snip::FenceStyle::Backtick,
+                lang.unwrap_or(""),
+                attrs,
+                body,
+            ));
+        }
+        blocks.push(Block::Section {
+            identifier: m.name("id").unwrap().as_str(),
+            text: parts.join("\n\n"),
+            start: whole.start(),
+            directives: !directives.is_empty(),
+            fences: fences_count,
+        });
+    }
+    push_prose(&mut blocks, &text[consumed..]);
+    return blocks;
+}
+
+/// 去掉前后的空行后加入非空的正文
+fn push_prose<'a>(blocks: &mut Vec<Block<'a>>, prose: &'a str) {
+    let prose = prose.trim_end();
+    let start = prose
+        .find(|c: char| !c.is_whitespace())
+        .map_or(prose.len(), |first| {
+            prose[..first].rfind('\n').map_or(0, |newline| newline + 1)
+        });
+    if start < prose.len() {
+        blocks.push(Block::Prose(&prose[start..]));
+    }
+}
+
+/// 解析出的 Snippet 及其元数据和错误，按标识符排序以便比较
+///
+/// 元数据不参与 Snippet 的比较，整理时同样不能改变，因此单独列出。
+#[allow(clippy::type_complexity)]
+fn parse(
+    text: &str,
+    options: &ParseOptions,
+) -> (Vec<(Snippet, BTreeMap<String, String>)>, Vec<String>) {
+    let text = snip::strip_html_comments(&snip::split_front_matter(text).1);
+    let (snips, errors) = snip::parse_snippets_collecting(&text, options);
+    let mut snips: Vec<(Snippet, BTreeMap<String, String>)> = snips
+        .into_iter()
+        .map(|(_, snip)| {
+            let metadata = snip.get_metadata().clone();
+            (snip, metadata)
+        })
+        .collect();
+    snips.sort_by(|a, b| a.0.get_identifier().cmp(b.0.get_identifier()));
+    let mut errors: Vec<String> = errors.into_iter().map(|(_, e)| e.to_string()).collect();
+    errors.sort();
+    return (snips, errors);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let text = "#   b/bb/rust   @dedent  #\n\n\ndesc\n\n\n```\n  x\n```\n# a/aa/python,rust\n\n```  keep-trailing-newline\ny\n\n```\n";
+        let formatted = format(text, &ParseOptions::default(), true).unwrap();
+        assert_eq!(
+            formatted,
+            "# a/aa/python,rust\n\n```python keep-trailing-newline\ny\n\n```\n\n# b/bb/rust @dedent\n\ndesc\n\n```rust\n  x\n```\n"
+        );
+        let unsorted = format(text, &ParseOptions::default(), false).unwrap();
+        assert!(unsorted.starts_with("# b/bb/rust @dedent\n"));
+        assert_eq!(
+            format(&formatted, &ParseOptions::default(), true).unwrap(),
+            formatted
+        );
+    }
+    #[test]
+    fn test_format_keeps_prose() {
+        let text = "---\nheading-level: 2\n---\nintro\n\n## d/d/c\n\n```c\nd\n```\n<!-- mdppet:output c.json -->\n## c/c/c\n\n```c\nc\n```\n\n\nmiddle\n\n## b/b/c\n```c\nb\n```\n## a/a/c\n\n```c\na\n```";
+        let formatted = format(text, &ParseOptions::default(), true).unwrap();
+        assert_eq!(
+            formatted,
+            "---\nheading-level: 2\n---\n\nintro\n\n<!-- mdppet:output c.json -->\n## c/c/c\n\n```c\nc\n```\n\n## d/d/c\n\n```c\nd\n```\n\nmiddle\n\n## a/a/c\n\n```c\na\n```\n\n## b/b/c\n\n```c\nb\n```\n"
+        );
+    }
+    #[test]
+    fn test_format_metadata() {
+        let text = "# a/a/rust\n[author=me]  \ndesc\n\n```rust\nx\n```\n";
+        let formatted = format(text, &ParseOptions::default(), true).unwrap();
+        assert_eq!(
+            formatted,
+            "# a/a/rust\n\n[author=me]\n\ndesc\n\n```rust\nx\n```\n"
+        );
+    }
+    #[test]
+    fn test_format_round_trip() {
+        let text = std::fs::read_to_string("tests/test_markdown_fmt.md").unwrap();
+        let options = ParseOptions::default();
+        let formatted = format(&text, &options, true).unwrap();
+        assert_ne!(formatted, text, "测试文件应当不是规范的写法");
+        assert_eq!(parse(&formatted, &options), parse(&text, &options));
+        assert!(formatted.contains("Notes between snippets stay where they are."));
+        assert_eq!(format(&formatted, &options, true).unwrap(), formatted);
+    }
+    #[test]
+    fn test_normalize() {
+        let text = "---\nheading-level: 2\n---\nintro\n<!-- note -->\n\n##   b:bb:rs\n[author=me]\n\n\ndesc\n\n```\nx\n```\n<!-- mdppet:output c.json -->\n## c:cc:rs\n```\nw\n```\n## a:aa:python,rust\n\n```rust\ny\n```\n```python\nz\n```\n";
+        let options = ParseOptions {
+            field_sep: String::from(":"),
+            ..ParseOptions::default()
+        };
+        let normalized = normalize(text, &options).unwrap();
+        assert_eq!(
+            normalized,
+            "---\nheading-level: 2\n---\n\nintro\n<!-- note -->\n\n## b:bb:rs\n\n[author=me]\n\ndesc\n\n```rs\nx\n```\n\n<!-- mdppet:output c.json -->\n## c:cc:rs\n\n```rs\nw\n```\n\n## a-python:aa:python\n\n```python\nz\n```\n\n## a-rust:aa:rust\n\n```rust\ny\n```\n"
+        );
+        let leveled = options
+            .with_front_matter(&snip::split_front_matter(text).0)
+            .unwrap();
+        assert_eq!(parse(&normalized, &leveled), parse(text, &leveled));
+        let options = ParseOptions::default();
+        assert!(normalize("# a/a/rust\n\n```rust\n  \n```\n", &options)
+            .unwrap_err()
+            .contains("empty body"));
+    }
+    #[test]
+    fn test_normalize_keeps_example_fences() {
+        let text = "# a/a/rust\n\n```rust\nx\n```\n\n```json\n{}\n```\n";
+        let normalized = normalize(text, &ParseOptions::default()).unwrap();
+        assert_eq!(normalized, text);
+    }
+    #[test]
+    fn test_normalize_idempotent() {
+        let options = ParseOptions::default();
+        for path in [
+            "tests/test_markdown_fmt.md",
+            "tests/test_markdown_variants.md",
+            "tests/test_markdown_commentify.md",
+            "tests/test_markdown_front_matter.md",
+        ]
+        .iter()
+        {
+            let text = std::fs::read_to_string(path).unwrap();
+            let once = normalize(&text, &options).unwrap();
+            assert_eq!(
+                once.matches("mdppet:").count(),
+                text.matches("mdppet:").count(),
+                "{} 中的指令应当保留",
+                path
+            );
+            let twice = normalize(&once, &options).unwrap();
+            assert_eq!(twice, once, "{} 第二次整理应当不再改变", path);
+        }
+    }
+}
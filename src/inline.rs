@@ -0,0 +1,81 @@
+use regex::Regex;
+
+/// 行内代码 `` `code` ``
+const CODE_RE: &str = r"`([^`]+)`";
+
+/// 其余的行内标记，每一项都只保留第一个捕获组
+///
+/// - 图片 `![alt](url)` 和链接 `[text](url)` 只保留文字
+/// - 加粗、删除线、斜体去掉标记；`_` 只在单词边界处生效，以免破坏 `snake_case`
+const INLINE_RES: &[&str] = &[
+    r"!\[([^\]]*)\]\([^)]*\)",
+    r"\[([^\]]+)\]\([^)]*\)",
+    r"\*\*(.+?)\*\*",
+    r"\b__(.+?)__\b",
+    r"~~(.+?)~~",
+    r"\*([^*\s](?:[^*]*[^*\s])?)\*",
+    r"\b_([^_\s](?:[^_]*[^_\s])?)_\b",
+];
+
+/// 将一行中简单的行内 Markdown 转换为纯文本
+///
+/// 行内代码中的内容原样保留。
+pub fn strip_inline_markdown(line: &str) -> String {
+    let code_re = Regex::new(CODE_RE).unwrap();
+    let mut plain = String::with_capacity(line.len());
+    let mut last = 0;
+    for m in code_re.captures_iter(line) {
+        let whole = m.get(0).unwrap();
+        plain.push_str(&strip_outside_code(&line[last..whole.start()]));
+        plain.push_str(m.get(1).unwrap().as_str());
+        last = whole.end();
+    }
+    plain.push_str(&strip_outside_code(&line[last..]));
+    return plain;
+}
+
+fn strip_outside_code(text: &str) -> String {
+    let mut text = String::from(text);
+    for re in INLINE_RES.iter() {
+        let re = Regex::new(re).unwrap();
+        text = re.replace_all(&text, "$1").into_owned();
+    }
+    return text;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_emphasis() {
+        assert_eq!(strip_inline_markdown("a **bold** word"), "a bold word");
+        assert_eq!(
+            strip_inline_markdown("an *italic* and _em_ word"),
+            "an italic and em word"
+        );
+        assert_eq!(strip_inline_markdown("__strong__ ~~gone~~"), "strong gone");
+        assert_eq!(
+            strip_inline_markdown("keep snake_case_name"),
+            "keep snake_case_name"
+        );
+        assert_eq!(strip_inline_markdown("2 * 3 * 4"), "2 * 3 * 4");
+    }
+    #[test]
+    fn test_strip_code() {
+        assert_eq!(
+            strip_inline_markdown("call `foo()` here"),
+            "call foo() here"
+        );
+        assert_eq!(strip_inline_markdown("`**not bold**`"), "**not bold**");
+    }
+    #[test]
+    fn test_strip_links() {
+        assert_eq!(
+            strip_inline_markdown("see [the docs](https://example.com) now"),
+            "see the docs now"
+        );
+        assert_eq!(strip_inline_markdown("![logo](logo.png)"), "logo");
+        assert_eq!(strip_inline_markdown("**[bold link](x)**"), "bold link");
+    }
+}
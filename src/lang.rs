@@ -0,0 +1,306 @@
+/// VSCode 的语言标识符及其常见别名
+pub struct Language {
+    /// VSCode 使用的语言标识符，即 snippet 中 `scope` 的取值
+    pub id: &'static str,
+    /// 其他常见写法，比较时不区分大小写
+    pub aliases: &'static [&'static str],
+    /// TextMate 系编辑器使用的作用域选择器，如 `source.rust`
+    pub textmate: &'static str,
+}
+
+pub const LANGUAGES: &[Language] = &[
+    Language {
+        id: "c",
+        aliases: &["c"],
+        textmate: "source.c",
+    },
+    Language {
+        id: "cpp",
+        aliases: &["c++", "cxx"],
+        textmate: "source.c++",
+    },
+    Language {
+        id: "csharp",
+        aliases: &["c#", "cs"],
+        textmate: "source.cs",
+    },
+    Language {
+        id: "css",
+        aliases: &[],
+        textmate: "source.css",
+    },
+    Language {
+        id: "go",
+        aliases: &["golang"],
+        textmate: "source.go",
+    },
+    Language {
+        id: "html",
+        aliases: &["htm"],
+        textmate: "text.html.basic",
+    },
+    Language {
+        id: "java",
+        aliases: &[],
+        textmate: "source.java",
+    },
+    Language {
+        id: "javascript",
+        aliases: &["js", "node"],
+        textmate: "source.js",
+    },
+    Language {
+        id: "json",
+        aliases: &[],
+        textmate: "source.json",
+    },
+    Language {
+        id: "latex",
+        aliases: &["tex"],
+        textmate: "text.tex.latex",
+    },
+    Language {
+        id: "lua",
+        aliases: &[],
+        textmate: "source.lua",
+    },
+    Language {
+        id: "markdown",
+        aliases: &["md"],
+        textmate: "text.html.markdown",
+    },
+    Language {
+        id: "php",
+        aliases: &[],
+        textmate: "source.php",
+    },
+    Language {
+        id: "plaintext",
+        aliases: &["text", "txt"],
+        textmate: "text.plain",
+    },
+    Language {
+        id: "powershell",
+        aliases: &["ps1", "pwsh"],
+        textmate: "source.powershell",
+    },
+    Language {
+        id: "python",
+        aliases: &["py", "python3"],
+        textmate: "source.python",
+    },
+    Language {
+        id: "ruby",
+        aliases: &["rb"],
+        textmate: "source.ruby",
+    },
+    Language {
+        id: "rust",
+        aliases: &["rs"],
+        textmate: "source.rust",
+    },
+    Language {
+        id: "shellscript",
+        aliases: &["sh", "bash", "shell", "zsh"],
+        textmate: "source.shell",
+    },
+    Language {
+        id: "sql",
+        aliases: &[],
+        textmate: "source.sql",
+    },
+    Language {
+        id: "toml",
+        aliases: &[],
+        textmate: "source.toml",
+    },
+    Language {
+        id: "typescript",
+        aliases: &["ts"],
+        textmate: "source.ts",
+    },
+    Language {
+        id: "yaml",
+        aliases: &["yml"],
+        textmate: "source.yaml",
+    },
+];
+
+/// 将语言名称或别名解析为 VSCode 的语言标识符
+pub fn resolve(name: &str) -> Option<&'static str> {
+    let name = name.trim();
+    for language in LANGUAGES.iter() {
+        if language.id.eq_ignore_ascii_case(name)
+            || language
+                .aliases
+                .iter()
+                .any(|alias| alias.eq_ignore_ascii_case(name))
+        {
+            return Some(language.id);
+        }
+    }
+    return None;
+}
+
+/// 作用域对应的 TextMate 作用域选择器
+///
+/// 无法识别的作用域写作 `source.<作用域>`。
+pub fn textmate_selector(scope: &str) -> String {
+    if let Some(id) = resolve(scope) {
+        for language in LANGUAGES.iter() {
+            if language.id == id {
+                return String::from(language.textmate);
+            }
+        }
+    }
+    return format!("source.{}", scope.trim().to_lowercase());
+}
+
+/// 部分语言的关键字，用于 `--warn-keyword-prefixes`
+const KEYWORDS: &[(&str, &[&str])] = &[
+    (
+        "c",
+        &[
+            "break", "case", "const", "continue", "do", "else", "enum", "for", "goto", "if",
+            "return", "sizeof", "static", "struct", "switch", "typedef", "union", "while",
+        ],
+    ),
+    (
+        "go",
+        &[
+            "break", "case", "chan", "const", "defer", "else", "for", "func", "go", "if", "import",
+            "map", "package", "range", "return", "select", "struct", "switch", "type", "var",
+        ],
+    ),
+    (
+        "javascript",
+        &[
+            "async", "await", "break", "case", "class", "const", "do", "else", "export", "for",
+            "function", "if", "import", "let", "new", "return", "switch", "try", "var", "while",
+        ],
+    ),
+    (
+        "lua",
+        &[
+            "do", "else", "elseif", "end", "for", "function", "if", "local", "repeat", "return",
+            "then", "until", "while",
+        ],
+    ),
+    (
+        "python",
+        &[
+            "class", "def", "elif", "else", "except", "for", "from", "if", "import", "lambda",
+            "return", "try", "while", "with", "yield",
+        ],
+    ),
+    (
+        "rust",
+        &[
+            "enum", "fn", "for", "if", "impl", "let", "loop", "match", "mod", "pub", "struct",
+            "trait", "type", "use", "where", "while",
+        ],
+    ),
+];
+
+/// 语言的关键字；`id` 须是 VSCode 的语言标识符，未收录的语言返回空列表
+pub fn keywords(id: &str) -> &'static [&'static str] {
+    for (language, keywords) in KEYWORDS.iter() {
+        if *language == id {
+            return keywords;
+        }
+    }
+    return &[];
+}
+
+/// 各语言的行注释前缀，用于 `mdppet:commentify`；没有行注释的语言（如 CSS、HTML）不在其中
+const COMMENT_LEADERS: &[(&str, &str)] = &[
+    ("c", "// "),
+    ("cpp", "// "),
+    ("csharp", "// "),
+    ("go", "// "),
+    ("java", "// "),
+    ("javascript", "// "),
+    ("latex", "% "),
+    ("lua", "-- "),
+    ("php", "// "),
+    ("powershell", "# "),
+    ("python", "# "),
+    ("ruby", "# "),
+    ("rust", "// "),
+    ("shellscript", "# "),
+    ("sql", "-- "),
+    ("toml", "# "),
+    ("typescript", "// "),
+    ("yaml", "# "),
+];
+
+/// 语言的行注释前缀；`id` 须是 VSCode 的语言标识符，未收录的语言返回 `None`
+pub fn comment_leader(id: &str) -> Option<&'static str> {
+    for (language, leader) in COMMENT_LEADERS.iter() {
+        if *language == id {
+            return Some(leader);
+        }
+    }
+    return None;
+}
+
+/// 作用域对应的语言标识符，无法识别时转换为小写，用作按语言查找的键
+pub fn language_key(scope: &str) -> String {
+    return resolve(scope).map_or_else(|| scope.trim().to_lowercase(), String::from);
+}
+
+/// Zed 代码片段文件的名称（不含扩展名），即 Zed 中语言名称的小写形式
+///
+/// 无法识别的作用域原样转换为小写。
+pub fn zed_name(scope: &str) -> String {
+    match resolve(scope) {
+        Some("cpp") => return String::from("c++"),
+        Some("csharp") => return String::from("c#"),
+        Some("plaintext") => return String::from("plain text"),
+        Some("shellscript") => return String::from("shell script"),
+        Some(id) => return String::from(id),
+        None => return scope.trim().to_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(resolve("Python"), Some("python"));
+        assert_eq!(resolve(" rs "), Some("rust"));
+        assert_eq!(resolve("C++"), Some("cpp"));
+        assert_eq!(resolve("bash"), Some("shellscript"));
+        assert_eq!(resolve("Klingon"), None);
+    }
+    #[test]
+    fn test_keywords() {
+        assert!(keywords("rust").contains(&"fn"));
+        assert!(!keywords("rust").contains(&"def"));
+        assert!(keywords("python").contains(&"def"));
+        assert!(keywords("yaml").is_empty());
+    }
+    #[test]
+    fn test_comment_leader() {
+        assert_eq!(comment_leader("python"), Some("# "));
+        assert_eq!(comment_leader("lua"), Some("-- "));
+        assert_eq!(comment_leader("css"), None);
+        assert_eq!(language_key(" JS "), "javascript");
+        assert_eq!(language_key("Elixir"), "elixir");
+    }
+    #[test]
+    fn test_textmate_selector() {
+        assert_eq!(textmate_selector("rs"), "source.rust");
+        assert_eq!(textmate_selector("html"), "text.html.basic");
+        assert_eq!(textmate_selector("Elixir"), "source.elixir");
+    }
+    #[test]
+    fn test_zed_name() {
+        assert_eq!(zed_name("rs"), "rust");
+        assert_eq!(zed_name("C++"), "c++");
+        assert_eq!(zed_name("bash"), "shell script");
+        assert_eq!(zed_name("Elixir"), "elixir");
+    }
+}
@@ -0,0 +1,25 @@
+//! mdppet：将 Markdown 中的代码片段转换为编辑器使用的 Snippet 文件
+//!
+//! 命令行程序见 `main.rs`；解析和输出所用的模块在这里公开，供基准测试和自己的工具使用。
+#![allow(clippy::needless_return)]
+pub mod archive;
+pub mod config;
+pub mod diagnostic;
+pub mod error;
+pub mod fmt;
+pub mod inline;
+pub mod lang;
+pub mod output;
+pub mod placeholder;
+pub mod rename;
+pub mod report;
+pub mod snip;
+pub mod source;
+pub mod sourcemap;
+pub mod tmsnippet;
+pub mod validate;
+pub mod vars;
+pub mod walk;
+
+/// 诊断信息等输出中使用的程序名
+pub const BIN_NAME: &str = "mdppet";
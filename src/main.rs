@@ -1,38 +1,1486 @@
-mod snip;
+#![allow(clippy::needless_return)]
+use mdppet::{
+    archive, config, diagnostic, error, fmt, lang, output, placeholder, rename, report, snip,
+    source, sourcemap, validate, vars, walk, BIN_NAME,
+};
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use diagnostic::{ColorChoice, Diagnostic, Diagnostics, MessageFormat};
+use error::MdppetError;
+use output::{BackupStyle, Clobber, Format, Indent, JsonStyle, Newline, WriteOptions};
+use report::BuildReport;
 use std::collections::BTreeMap;
 use std::fs;
-use std::io;
-use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
 
-const BIN_NAME: &str = "mdppet";
+const EXIT_CODES: &str = "EXIT CODES:
+    0    success
+    1    a file could not be read or written
+    2    a source file could not be parsed, or --preview or expand found no such snippet
+    3    validation failed (conflicting duplicates, --validate, --check, fmt --check)";
 
 fn main() {
     let args = get_app().get_matches();
-    let src = args.value_of("src").unwrap();
-    let out = args.value_of("dest").unwrap();
-    let mut text = String::new();
-    let mut snips: Vec<snip::Snippet> = Vec::new();
+    if let Err(e) = run(&args) {
+        // JSON 格式下诊断信息已经逐条输出，不再追加人类可读的汇总
+        let reported = matches!(e, MdppetError::Reported { .. });
+        if !(reported && args.value_of("message-format") == Some("json")) {
+            // `--color` 是全局参数，写在子命令之后时只出现在子命令的参数中
+            let color_args = args.subcommand().1.unwrap_or(&args);
+            let color = ColorChoice::from_name(color_args.value_of("color").unwrap()).unwrap();
+            diagnostic::eprint_message("error", &e.to_string(), color);
+        }
+        process::exit(e.exit_code());
+    }
+}
+
+/// 从命令行参数整理出的构建选项
+#[derive(Clone)]
+struct BuildOptions {
+    format: Format,
+    parse: snip::ParseOptions,
+    write: WriteOptions,
+    /// `--ascii-output` 和 `--json-indent`
+    json_style: JsonStyle,
+    /// `--match-existing-style`：沿用目标文件已有的缩进和转义
+    match_existing_style: bool,
+    trim_blank_body_lines: bool,
+    expand_escapes: bool,
+    plain_descriptions: bool,
+    default_description: snip::DefaultDescription,
+    deprecated: snip::Deprecated,
+    template_placeholders: bool,
+    include_scopes: Option<Vec<String>>,
+    exclude_scopes: Vec<String>,
+    validate: bool,
+    /// `--check`：只与已有的输出比较，不写入
+    check: bool,
+    strict: bool,
+    fail_fast: bool,
+    keep_going: bool,
+    /// `--lenient`：字段检查的错误降级为警告，丢弃出错的 Snippet
+    lenient: bool,
+    /// `--jobs`：同时解析源文件的线程数
+    jobs: usize,
+    message_format: MessageFormat,
+    messages_on_stdout: bool,
+    color: ColorChoice,
+    /// `--summary`：构建结束时统计的输出格式
+    summary: MessageFormat,
+    quiet: bool,
+    warn_keyword_prefixes: bool,
+    /// `--max-snippets-per-file` 和 `--max-file-size`：超过时将输出拆分为编号的多个文件
+    part_limits: output::PartLimits,
+    /// `--fix-scope-from-fence`：代码块的语言不在作用域之中时将它加入作用域，而不是给出警告
+    fix_scope_from_fence: bool,
+    /// `--max-body-lines`：补全体超过该行数时给出警告，0 表示不检查
+    max_body_lines: usize,
+    /// `--rename`：源文件中的标识符到输出中的标识符
+    renames: BTreeMap<String, String>,
+    vars: vars::Vars,
+    verbose: bool,
+}
+
+impl BuildOptions {
+    fn from_args(args: &ArgMatches) -> Self {
+        BuildOptions {
+            // `mdppet build` 没有 `--format`，由各目标指定
+            format: args
+                .value_of("format")
+                .map_or(Format::Json, |name| Format::from_name(name).unwrap()),
+            parse: parse_options(args),
+            write: WriteOptions {
+                clobber: if args.is_present("no-clobber") {
+                    Clobber::Refuse
+                } else if args.is_present("force") {
+                    Clobber::Force
+                } else {
+                    Clobber::Overwrite
+                },
+                newline: Newline::from_name(args.value_of("newline").unwrap()).unwrap(),
+                backup: if args.is_present("backup") {
+                    BackupStyle::from_name(args.value_of("backup-style").unwrap())
+                } else {
+                    None
+                },
+            },
+            json_style: JsonStyle {
+                ascii: args.is_present("ascii-output"),
+                indent: Indent::from_name(args.value_of("json-indent").unwrap()).unwrap(),
+            },
+            match_existing_style: args.is_present("match-existing-style"),
+            trim_blank_body_lines: args.is_present("trim-blank-body-lines"),
+            expand_escapes: args.is_present("expand-escapes"),
+            plain_descriptions: args.is_present("plain-descriptions"),
+            default_description: snip::DefaultDescription::from_name(
+                args.value_of("default-description").unwrap(),
+            )
+            .unwrap(),
+            deprecated: snip::Deprecated::from_name(args.value_of("deprecated").unwrap()).unwrap(),
+            template_placeholders: args.is_present("template-placeholders"),
+            include_scopes: args
+                .values_of("include-scopes")
+                .map(|scopes| scopes.map(String::from).collect()),
+            exclude_scopes: args
+                .values_of("exclude-scopes")
+                .into_iter()
+                .flatten()
+                .map(String::from)
+                .collect(),
+            // --check 同时校验生成的结果
+            validate: args.is_present("validate") || args.is_present("check"),
+            check: args.is_present("check"),
+            strict: args.is_present("strict"),
+            fail_fast: args.is_present("fail-fast"),
+            keep_going: args.is_present("keep-going"),
+            lenient: args.is_present("lenient"),
+            jobs: args.value_of("jobs").unwrap().parse().unwrap(),
+            message_format: MessageFormat::from_name(args.value_of("message-format").unwrap())
+                .unwrap(),
+            messages_on_stdout: args.is_present("messages-on-stdout"),
+            color: ColorChoice::from_name(args.value_of("color").unwrap()).unwrap(),
+            summary: MessageFormat::from_name(args.value_of("summary").unwrap()).unwrap(),
+            quiet: args.is_present("quiet"),
+            warn_keyword_prefixes: args.is_present("warn-keyword-prefixes"),
+            fix_scope_from_fence: args.is_present("fix-scope-from-fence"),
+            part_limits: output::PartLimits {
+                snippets: args
+                    .value_of("max-snippets-per-file")
+                    .map(|value| value.parse().unwrap()),
+                bytes: args
+                    .value_of("max-file-size")
+                    .map(|value| value.parse().unwrap()),
+            },
+            max_body_lines: args.value_of("max-body-lines").unwrap().parse().unwrap(),
+            renames: args
+                .values_of("rename")
+                .into_iter()
+                .flatten()
+                .map(|rename| snip::parse_rename(rename).unwrap())
+                .collect(),
+            vars: {
+                let date = args.is_present("date-var").then(|| {
+                    let epoch = std::env::var("SOURCE_DATE_EPOCH").ok();
+                    vars::build_date(epoch.as_deref(), SystemTime::now())
+                });
+                let mut vars = vars::Vars::builtin(date);
+                for definition in args.values_of("define").into_iter().flatten() {
+                    let (name, value) = vars::parse_define(definition).unwrap();
+                    vars.define(name, value);
+                }
+                vars.allow_undefined = args.is_present("allow-undefined-vars");
+                vars
+            },
+            verbose: args.is_present("verbose"),
+        }
+    }
+
+    /// 按 `--fail-fast` 和 `--message-format` 收集、输出诊断信息
+    fn diagnostics(&self) -> Diagnostics {
+        return Diagnostics::new(self.fail_fast)
+            .with_format(self.message_format, self.messages_on_stdout)
+            .with_color(self.color);
+    }
+
+    /// Snippet 是否通过 `--include-scopes` 和 `--exclude-scopes` 的筛选
+    fn keeps_scopes(&self, snip: &snip::Snippet) -> bool {
+        if let Some(include) = &self.include_scopes {
+            if !snip.in_scopes(include) {
+                return false;
+            }
+        }
+        return !snip.in_scopes(&self.exclude_scopes);
+    }
+}
+
+/// 解析 Markdown 的选项，`fmt` 子命令也使用这些参数
+fn parse_options(args: &ArgMatches) -> snip::ParseOptions {
+    snip::ParseOptions {
+        field_sep: String::from(args.value_of("field-sep").unwrap()),
+        heading_level: snip::parse_heading_level(args.value_of("heading-level").unwrap()).unwrap(),
+        group_level: args
+            .value_of("group-heading-level")
+            .map(|level| snip::parse_heading_level(level).unwrap()),
+        body: snip::BodyOptions {
+            keep_trailing_newline: args.is_present("keep-trailing-newline"),
+            trim_trailing_whitespace: !args.is_present("no-trim-trailing-whitespace"),
+        },
+        comment_leaders: args
+            .values_of("comment-leader")
+            .into_iter()
+            .flatten()
+            .map(|value| snip::parse_comment_leader(value).unwrap())
+            .collect(),
+    }
+}
+
+fn run(args: &ArgMatches) -> Result<(), MdppetError> {
+    if let Some(fmt_args) = args.subcommand_matches("fmt") {
+        return run_fmt(fmt_args);
+    }
+    if let Some(normalize_args) = args.subcommand_matches("normalize") {
+        return run_normalize(normalize_args);
+    }
+    if let Some(rename_args) = args.subcommand_matches("rename") {
+        return run_rename(rename_args);
+    }
+    if let Some(build_args) = args.subcommand_matches("build") {
+        return run_build(build_args);
+    }
+    if let Some(expand_args) = args.subcommand_matches("expand") {
+        return run_expand(expand_args);
+    }
+    let options = BuildOptions::from_args(args);
+    let sources = collect_sources(args)?;
+    let mut report = BuildReport::new();
+    report.sources = sources.len();
+    if args.is_present("emit-sourcemap") {
+        report.sourcemap = Some(sourcemap::SnippetMap::new());
+    }
+
+    if args.is_present("list") {
+        let mut diagnostics = options.diagnostics();
+        let snips = parse_sources(&sources, &options, &mut diagnostics, &mut report)?;
+        let pending = check_diagnostics(&mut diagnostics, &options, &mut report)?;
+        print!("{}", output::render_list(&snips));
+        return pending.map_or(Ok(()), Err);
+    }
+
+    if let Some(identifier) = args.value_of("preview") {
+        let mut diagnostics = options.diagnostics();
+        let snips = parse_sources(&sources, &options, &mut diagnostics, &mut report)?;
+        let pending = check_diagnostics(&mut diagnostics, &options, &mut report)?;
+        match output::render_preview(&snips, identifier, options.format, options.json_style) {
+            Some(text) => println!("{}", text.trim_end()),
+            None => {
+                return Err(MdppetError::SnippetNotFound(
+                    "identifier",
+                    String::from(identifier),
+                    snips.len(),
+                ))
+            }
+        }
+        return pending.map_or(Ok(()), Err);
+    }
+
+    let install_dir = if args.is_present("install") {
+        if options.format != Format::Zed {
+            clap::Error::with_description(
+                "--install is only supported with --format zed",
+                clap::ErrorKind::ArgumentConflict,
+            )
+            .exit();
+        }
+        Some(
+            output::zed_snippets_dir(|name| std::env::var_os(name), cfg!(windows))
+                .ok_or(MdppetError::NoInstallDir("Zed"))?,
+        )
+    } else {
+        args.value_of("out-dir").map(PathBuf::from)
+    };
+    let result = match install_dir {
+        Some(out_dir) => {
+            if let Some(message) = part_limits_conflict(&options, true) {
+                clap::Error::with_description(message, clap::ErrorKind::ArgumentConflict).exit();
+            }
+            let out_dir = out_dir.as_path();
+            fs::create_dir_all(out_dir).map_err(|e| MdppetError::Io(out_dir.to_path_buf(), e))?;
+            build_routed(&sources, out_dir, &options, &mut report)
+        }
+        None => {
+            if options.format == Format::TmSnippet {
+                clap::Error::with_description(
+                    "--format tmsnippet writes one file per snippet and requires --out-dir",
+                    clap::ErrorKind::MissingRequiredArgument,
+                )
+                .exit();
+            }
+            if let Some(message) = part_limits_conflict(&options, false) {
+                clap::Error::with_description(message, clap::ErrorKind::ArgumentConflict).exit();
+            }
+            let out = args
+                .value_of("dest")
+                .unwrap_or(options.format.default_dest());
+            build(&sources, Path::new(out), &options, &mut report)
+        }
+    };
+    if let (Some(path), Some(sourcemap)) = (args.value_of("emit-sourcemap"), &report.sourcemap) {
+        if !options.check && !sourcemap.is_empty() {
+            sourcemap.write(Path::new(path), args.value_of("relative-to").map(Path::new))?;
+        }
+    }
+    // `--fail-fast` 时第一个错误直接返回，没有经过 `check_diagnostics`
+    if let Err(MdppetError::Reported { errors, .. }) = &result {
+        report.errors = report.errors.max(*errors);
+    }
+    if !(options.quiet && options.summary == MessageFormat::Human) {
+        eprintln!("{}", report.render(options.summary));
+    }
+    return result;
+}
+
+/// `mdppet fmt`：将源文件整理为规范的写法，`--check` 时只检查不写入
+fn run_fmt(args: &ArgMatches) -> Result<(), MdppetError> {
+    let options = parse_options(args);
+    let sources = collect_sources(args)?;
+    let mut unformatted: usize = 0;
+    for src in sources.iter() {
+        let text = source::read_source(src)?;
+        let formatted = fmt::format(&text, &options, !args.is_present("no-sort"))
+            .map_err(|message| MdppetError::Unformattable(src.clone(), message))?;
+        if formatted == text {
+            continue;
+        }
+        if args.is_present("check") {
+            println!("would reformat {}", src.display());
+            unformatted += 1;
+        } else {
+            fs::write(src, formatted).map_err(|e| MdppetError::Io(src.clone(), e))?;
+            println!("reformatted {}", src.display());
+        }
+    }
+    if unformatted > 0 {
+        return Err(MdppetError::Unformatted(unformatted));
+    }
+    return Ok(());
+}
+
+/// `mdppet normalize`：按解析出的 Snippet 重新生成源文件，输出到标准输出，`--in-place` 时写回源文件
+fn run_normalize(args: &ArgMatches) -> Result<(), MdppetError> {
+    let options = parse_options(args);
+    let sources = collect_sources(args)?;
+    for src in sources.iter() {
+        let text = source::read_source(src)?;
+        let normalized = fmt::normalize(&text, &options)
+            .map_err(|message| MdppetError::Unformattable(src.clone(), message))?;
+        if !args.is_present("in-place") {
+            print!("{}", normalized);
+        } else if normalized != text {
+            fs::write(src, normalized).map_err(|e| MdppetError::Io(src.clone(), e))?;
+            println!("normalized {}", src.display());
+        }
+    }
+    return Ok(());
+}
+
+/// `mdppet rename`：修改源文件中 Snippet 标题的标识符或前缀，并可同时更新已生成的输出
+///
+/// 新的名称已被其他 Snippet 使用，或没有任何标题使用旧的名称时不做任何修改。
+fn run_rename(args: &ArgMatches) -> Result<(), MdppetError> {
+    let options = parse_options(args);
+    let sources = collect_sources(args)?;
+    let mut renames: Vec<(rename::Field, String, String)> = Vec::new();
+    for (name, field) in [
+        ("id", rename::Field::Identifier),
+        ("prefix", rename::Field::Prefix),
+    ] {
+        if let Some(value) = args.value_of(name) {
+            let (old, new) = snip::parse_rename(value).unwrap();
+            if new.contains(char::is_whitespace) || new.contains(options.field_sep.as_str()) {
+                clap::Error::with_description(
+                    &format!(
+                        "--{}: `{}` cannot contain whitespace or the field separator `{}`",
+                        name, new, options.field_sep
+                    ),
+                    clap::ErrorKind::InvalidValue,
+                )
+                .exit();
+            }
+            renames.push((field, old, new));
+        }
+    }
+
+    let mut files: Vec<(&PathBuf, String, snip::ParseOptions)> = Vec::new();
+    for src in sources.iter() {
+        let text = source::read_source(src)?;
+        let parse = options
+            .with_front_matter(&snip::split_front_matter(&text).0)
+            .map_err(|message| MdppetError::FrontMatter(src.clone(), message))?;
+        files.push((src, text, parse));
+    }
+    for (field, old, new) in renames.iter() {
+        let used = |name: &str| {
+            files
+                .iter()
+                .any(|(_, text, parse)| rename::field_values(text, parse, *field).contains(&name))
+        };
+        if !used(old) {
+            return Err(MdppetError::RenameNotFound(field.name(), old.clone()));
+        }
+        if old != new && used(new) {
+            return Err(MdppetError::RenameCollision(field.name(), new.clone()));
+        }
+    }
+
+    let dry_run = args.is_present("dry-run");
+    let mut identifiers: BTreeMap<String, String> = BTreeMap::new();
+    for (src, text, parse) in files.iter() {
+        let mut renamed = text.clone();
+        let mut count = 0;
+        for (field, old, new) in renames.iter() {
+            let (next, n) = rename::rename_headings(&renamed, parse, *field, old, new);
+            renamed = next;
+            count += n;
+        }
+        if count == 0 {
+            continue;
+        }
+        identifiers.extend(rename::renamed_identifiers(text, &renamed, parse));
+        if dry_run {
+            print!(
+                "{}",
+                rename::line_diff(&src.display().to_string(), text, &renamed)
+            );
+        } else {
+            fs::write(src, &renamed).map_err(|e| MdppetError::Io(src.to_path_buf(), e))?;
+            println!("{}: renamed {} heading(s)", src.display(), count);
+        }
+    }
+
+    if let Some(dest) = args.value_of("dest") {
+        let dest = Path::new(dest);
+        let format = match dest.extension().and_then(|ext| ext.to_str()) {
+            Some("code-snippets") => Format::CodeSnippets,
+            _ => Format::Json,
+        };
+        let prefixes: BTreeMap<String, String> = renames
+            .iter()
+            .filter(|(field, ..)| *field == rename::Field::Prefix)
+            .map(|(_, old, new)| (old.clone(), new.clone()))
+            .collect();
+        let text = fs::read_to_string(dest).map_err(|e| MdppetError::Io(dest.to_path_buf(), e))?;
+        let updated =
+            output::rename_output(&text, format, &identifiers, &prefixes).map_err(|message| {
+                let e = std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+                MdppetError::Io(dest.to_path_buf(), e)
+            })?;
+        if dry_run {
+            print!(
+                "{}",
+                rename::line_diff(&dest.display().to_string(), &text, &updated)
+            );
+        } else if updated != text {
+            let write = WriteOptions {
+                newline: if text.contains("\r\n") {
+                    Newline::Crlf
+                } else {
+                    Newline::Lf
+                },
+                ..WriteOptions::default()
+            };
+            output::write_file(dest, &updated, &write)?;
+            println!("{}: updated", dest.display());
+        }
+    }
+    return Ok(());
+}
+
+/// `mdppet expand`：输出 Snippet 在所有占位符都取默认值时插入的文本
+///
+/// 按 `--id` 查找时只有一个 Snippet；按 `--prefix` 查找时可能有多个，各自以 `--- 标识符` 开头。
+fn run_expand(args: &ArgMatches) -> Result<(), MdppetError> {
+    let options = BuildOptions::from_args(args);
+    let sources = collect_sources(args)?;
+    let mut report = BuildReport::new();
+    let mut diagnostics = options.diagnostics();
+    let snips = parse_sources(&sources, &options, &mut diagnostics, &mut report)?;
+    let pending = check_diagnostics(&mut diagnostics, &options, &mut report)?;
+    let (field, name, matched): (&'static str, &str, Vec<&snip::Snippet>) =
+        match args.value_of("id") {
+            Some(identifier) => (
+                "identifier",
+                identifier,
+                snips
+                    .iter()
+                    .filter(|snip| snip.get_identifier() == identifier)
+                    .collect(),
+            ),
+            None => {
+                let prefix = args.value_of("prefix").unwrap();
+                let matched = snips
+                    .iter()
+                    .filter(|snip| snip.prefixes().iter().any(|p| p == prefix))
+                    .collect();
+                ("prefix", prefix, matched)
+            }
+        };
+    if matched.is_empty() {
+        return Err(MdppetError::SnippetNotFound(
+            field,
+            String::from(name),
+            snips.len(),
+        ));
+    }
+    for snip in matched.iter() {
+        if matched.len() > 1 {
+            println!("--- {}", snip.get_identifier());
+        }
+        println!("{}", placeholder::expand_body(snip.get_body()));
+    }
+    return pending.map_or(Ok(()), Err);
+}
+
+/// `mdppet build`：按 `mdppet.toml` 构建指定的目标，没有指定时构建所有目标
+///
+/// 所有目标的源文件只解析一次，各目标再按自己的作用域和标签筛选，
+/// 以各自的格式写入各自的位置；`--check` 时检查所有目标的输出。
+fn run_build(build_args: &ArgMatches) -> Result<(), MdppetError> {
+    let options = BuildOptions::from_args(build_args);
+    let config_file = Path::new(build_args.value_of("config").unwrap());
+    let names: Vec<&str> = build_args
+        .values_of("target")
+        .into_iter()
+        .flatten()
+        .collect();
+    let targets = config::select(config::load(config_file)?, &names)?;
+    for target in targets.iter() {
+        let mut target_options = options.clone();
+        target_options.format = target.format;
+        let out_dir = matches!(target.dest, config::Dest::Dir(_));
+        if let Some(message) = part_limits_conflict(&target_options, out_dir) {
+            let message = format!("target `{}`: {}", target.name, message);
+            return Err(MdppetError::Config(config_file.to_path_buf(), message));
+        }
+    }
+
+    let mut report = BuildReport::new();
+    let mut diagnostics = options.diagnostics();
+    let walk = walk_options(build_args);
+    let mut unique: Vec<PathBuf> = Vec::new();
+    let mut target_sources: Vec<Vec<PathBuf>> = Vec::new();
+    for target in targets.iter() {
+        let sources = expand_sources(&target.sources, &walk, options.verbose)?;
+        for src in sources.iter() {
+            if !unique.contains(src) {
+                unique.push(src.clone());
+            }
+        }
+        target_sources.push(sources);
+    }
+    let parsed: BTreeMap<PathBuf, Vec<snip::Snippet>> =
+        parse_each(&unique, &options, &mut diagnostics, &mut report)?
+            .into_iter()
+            .collect();
+    report.sources = parsed.len();
+    let pending = check_diagnostics(&mut diagnostics, &options, &mut report)?;
+
+    let mut result: Result<(), MdppetError> = Ok(());
+    let mut outdated = 0;
+    for (target, sources) in targets.iter().zip(target_sources) {
+        let mut target_options = options.clone();
+        target_options.format = target.format;
+        let target_parsed: Vec<(PathBuf, Vec<snip::Snippet>)> = sources
+            .into_iter()
+            .map(|src| {
+                let snips = parsed[&src]
+                    .iter()
+                    .filter(|snip| target.keeps(snip))
+                    .cloned()
+                    .collect();
+                (src, snips)
+            })
+            .collect();
+        report.target = Some(target.name.clone());
+        let built = match &target.dest {
+            config::Dest::Dir(out_dir) => Some(out_dir)
+                .filter(|_| !options.check)
+                .map_or(Ok(()), fs::create_dir_all)
+                .map_err(|e| MdppetError::Io(out_dir.clone(), e))
+                .and_then(|_| {
+                    build_routed_parsed(
+                        &target_parsed,
+                        out_dir,
+                        &target_options,
+                        &mut diagnostics,
+                        &mut report,
+                    )
+                }),
+            config::Dest::File(dest) => {
+                let parent = dest.parent().filter(|_| !options.check);
+                parent
+                    .map_or(Ok(()), fs::create_dir_all)
+                    .map_err(|e| MdppetError::Io(dest.clone(), e))
+                    .and_then(|_| {
+                        build_merged(
+                            &target_parsed,
+                            dest,
+                            &target_options,
+                            &mut diagnostics,
+                            &mut report,
+                        )
+                    })
+            }
+        };
+        match built {
+            Ok(()) => {}
+            Err(MdppetError::Outdated(count)) => outdated += count,
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    if result.is_ok() && outdated > 0 {
+        result = Err(MdppetError::Outdated(outdated));
+    }
+    if let Err(MdppetError::Reported { errors, .. }) = &result {
+        report.errors = report.errors.max(*errors);
+    }
+    if !(options.quiet && options.summary == MessageFormat::Human) {
+        eprintln!("{}", report.render(options.summary));
+    }
+    return result.and(pending.map_or(Ok(()), Err));
+}
+
+/// 将 `sources` 中的所有 Snippet 合并写入 `dest`
+fn build(
+    sources: &[PathBuf],
+    dest: &Path,
+    options: &BuildOptions,
+    report: &mut BuildReport,
+) -> Result<(), MdppetError> {
+    let mut diagnostics = options.diagnostics();
+    let parsed = parse_each(sources, options, &mut diagnostics, report)?;
+    return build_merged(&parsed, dest, options, &mut diagnostics, report);
+}
+
+/// 将已解析的 Snippet 去重后合并写入 `dest`，`parsed` 中每一项是一个源文件及其中的 Snippet
+fn build_merged(
+    parsed: &[(PathBuf, Vec<snip::Snippet>)],
+    dest: &Path,
+    options: &BuildOptions,
+    diagnostics: &mut Diagnostics,
+    report: &mut BuildReport,
+) -> Result<(), MdppetError> {
+    let sources: Vec<PathBuf> = parsed.iter().map(|(src, _)| src.clone()).collect();
+    let snips = dedup(named(parsed), diagnostics)?;
+    let pending = check_diagnostics(diagnostics, options, report)?;
+    for snip in snips.iter() {
+        if let Some(output) = snip.get_output() {
+            diagnostic::eprint_message(
+                "warning",
+                &format!(
+                    "snippet `{}` is routed to {} but output directives only apply with --out-dir; ignoring it",
+                    snip.get_identifier(),
+                    output
+                ),
+                options.color,
+            );
+        }
+    }
+    emit_parts(&snips, &sources, dest, options, report)?;
+    return pending.map_or(Ok(()), Err);
+}
+
+/// `--max-snippets-per-file` 和 `--max-file-size` 不适用于该输出时的说明，`out_dir` 表示写入输出目录
+fn part_limits_conflict(options: &BuildOptions, out_dir: bool) -> Option<&'static str> {
+    if !options.part_limits.is_set() {
+        return None;
+    }
+    if out_dir {
+        return Some("--max-snippets-per-file and --max-file-size only apply to a single output file, not to --out-dir");
+    }
+    if !matches!(options.format, Format::Json | Format::CodeSnippets) {
+        return Some("--max-snippets-per-file and --max-file-size are only supported with --format json or code-snippets");
+    }
+    return None;
+}
+
+/// 按 `--max-snippets-per-file` 和 `--max-file-size` 将 `snips` 拆分写入 `out.1.json`、`out.2.json` 等
+///
+/// 没有超过上限时照常写入 `dest`。拆分前后的各个文件视为同一个输出：
+/// 不再需要的旧文件（拆分后的 `dest`、多出的部分）被删除，`--check` 时它们和缺少的部分都算作过期。
+/// 各部分的大小按实际写出时的 JSON 风格计算，`--match-existing-style` 时沿用 `dest` 或第一部分的写法。
+fn emit_parts(
+    snips: &[snip::Snippet],
+    sources: &[PathBuf],
+    dest: &Path,
+    options: &BuildOptions,
+    report: &mut BuildReport,
+) -> Result<(), MdppetError> {
+    if !options.part_limits.is_set()
+        || !matches!(options.format, Format::Json | Format::CodeSnippets)
+    {
+        let style = output_style(&[dest], options);
+        return emit(snips, sources, dest, style, options, report);
+    }
+    let style = output_style(&[dest, &output::part_path(dest, 1)], options);
+    let parts = output::partition(
+        snips,
+        options.format,
+        style,
+        options.write.newline,
+        options.part_limits,
+    );
+    let mut stale: Vec<PathBuf> = Vec::new();
+    let mut outdated = 0;
+    let mut check = |result: Result<(), MdppetError>| match result {
+        Err(MdppetError::Outdated(n)) => {
+            outdated += n;
+            return Ok(());
+        }
+        other => return other,
+    };
+    if parts.len() == 1 {
+        check(emit(snips, sources, dest, style, options, report))?;
+    } else {
+        for (index, part) in parts.iter().enumerate() {
+            let path = output::part_path(dest, index + 1);
+            check(emit(part, sources, &path, style, options, report))?;
+        }
+        if dest.exists() {
+            stale.push(dest.to_path_buf());
+        }
+    }
+    let mut index = if parts.len() == 1 { 1 } else { parts.len() + 1 };
+    while output::part_path(dest, index).exists() {
+        stale.push(output::part_path(dest, index));
+        index += 1;
+    }
+    for path in stale.iter() {
+        if options.check {
+            println!("{}: stale, would be removed", path.display());
+            outdated += 1;
+        } else {
+            output::remove_output(path, &options.write)?;
+            report.record_removed(path);
+        }
+    }
+    if parts.len() > 1 && !options.check {
+        report.record_split(dest, parts.len());
+    }
+    if outdated > 0 {
+        return Err(MdppetError::Outdated(outdated));
+    }
+    return Ok(());
+}
+
+/// 输出解析阶段的诊断信息，并将警告数和错误数记入 `report`
+///
+/// 存在错误时，没有 `--keep-going` 则返回该错误，不写出任何结果；
+/// 否则返回它，留待写出结果之后再报告。
+fn check_diagnostics(
+    diagnostics: &mut Diagnostics,
+    options: &BuildOptions,
+    report: &mut BuildReport,
+) -> Result<Option<MdppetError>, MdppetError> {
+    report.record_diagnostics(diagnostics.counts());
+    match diagnostics.report() {
+        Some(e) if !options.keep_going => return Err(e),
+        pending => return Ok(pending),
+    }
+}
+
+/// 写入同一目标文件的源文件，以及附带来源的 Snippet
+type Route = (Vec<PathBuf>, Vec<(String, snip::Snippet)>);
+
+/// `--out-dir` 模式：每个源文件写入 `<out-dir>/<文件名>.<扩展名>`
+///
+/// 带有 `mdppet:output` 指令或 `@file` 标记的 Snippet 改为写入 `<out-dir>/<指定的文件名>`，
+/// 同一目标文件的 Snippet 来自多个源文件时合并，并按目标文件分别去重。
+/// 不同目录下的同名源文件（如 `x/s.md` 和 `y/s.md`）对应同一个输出文件，视为错误。
+/// 目标文件比它的所有源文件都新时跳过。
+fn build_routed(
+    sources: &[PathBuf],
+    out_dir: &Path,
+    options: &BuildOptions,
+    report: &mut BuildReport,
+) -> Result<(), MdppetError> {
+    let mut diagnostics = options.diagnostics();
+    let parsed = parse_each(sources, options, &mut diagnostics, report)?;
+    return build_routed_parsed(&parsed, out_dir, options, &mut diagnostics, report);
+}
+
+/// 按 `build_routed` 的规则将已解析的 Snippet 写入 `out_dir`
+fn build_routed_parsed(
+    parsed: &[(PathBuf, Vec<snip::Snippet>)],
+    out_dir: &Path,
+    options: &BuildOptions,
+    diagnostics: &mut Diagnostics,
+    report: &mut BuildReport,
+) -> Result<(), MdppetError> {
+    let mut routes: BTreeMap<PathBuf, Route> = BTreeMap::new();
+    let mut stems: BTreeMap<PathBuf, &PathBuf> = BTreeMap::new();
+    for (src, snips) in parsed.iter() {
+        let mut default_dest = out_dir.join(src.file_stem().unwrap());
+        default_dest.set_extension(options.format.extension());
+        if split_dests(out_dir, None, options.format).is_none() {
+            if let Some(first) = stems
+                .insert(default_dest.clone(), src)
+                .filter(|first| *first != src)
+            {
+                return Err(MdppetError::OutputCollision(
+                    default_dest,
+                    first.clone(),
+                    src.clone(),
+                ));
+            }
+            routes
+                .entry(default_dest.clone())
+                .or_default()
+                .0
+                .push(src.clone());
+        }
+        for snip in snips.iter() {
+            let dests = match snip.get_output() {
+                Some(output) => vec![out_dir.join(output)],
+                None => split_dests(out_dir, Some(snip), options.format)
+                    .unwrap_or_else(|| vec![default_dest.clone()]),
+            };
+            for dest in dests {
+                let (route_sources, snips) = routes.entry(dest).or_default();
+                if !route_sources.contains(src) {
+                    route_sources.push(src.clone());
+                }
+                snips.push((src.display().to_string(), snip.clone()));
+            }
+        }
+    }
+
+    let mut outputs: Vec<(PathBuf, Vec<PathBuf>, Vec<snip::Snippet>)> = Vec::new();
+    for (dest, (route_sources, snips)) in routes {
+        if options.write.clobber != Clobber::Force
+            && !options.check
+            && route_sources.iter().all(|src| is_up_to_date(src, &dest))
+        {
+            if options.verbose {
+                eprintln!(
+                    "{}: skipping {}: it is up to date",
+                    BIN_NAME,
+                    dest.display()
+                );
+            }
+            if let Some(sourcemap) = report.sourcemap.as_mut() {
+                sourcemap.record_output(&dest, &route_sources, snips.iter().map(|(_, snip)| snip));
+            }
+            continue;
+        }
+        let snips = dedup(snips, diagnostics)?;
+        outputs.push((dest, route_sources, snips));
+    }
+
+    let pending = check_diagnostics(diagnostics, options, report)?;
+    let mut outdated = 0;
+    for (dest, route_sources, snips) in outputs {
+        if let Some(parent) = dest.parent().filter(|_| !options.check) {
+            fs::create_dir_all(parent).map_err(|e| MdppetError::Io(parent.to_path_buf(), e))?;
+        }
+        let style = output_style(&[&dest], options);
+        match emit(&snips, &route_sources, &dest, style, options, report) {
+            Err(MdppetError::Outdated(count)) => outdated += count,
+            result => result?,
+        }
+    }
+    if outdated > 0 {
+        return Err(MdppetError::Outdated(outdated));
+    }
+    return pending.map_or(Ok(()), Err);
+}
 
-    let mut istream = get_read_stream(Path::new(src));
-    istream.read_to_string(&mut text).unwrap();
-    for md in snip::get_snippet_segments(&text) {
-        snips.push(snip::Snippet::from_markdown(md));
+/// 不按源文件划分输出的格式中，Snippet 所属的文件
+///
+/// `--format zed` 时每个作用域一个 `<语言>.json`，没有作用域的 Snippet 写入全局的 `snippets.json`；
+/// `--format tmsnippet` 时每个 Snippet 一个 `<标识符>.tmSnippet`。
+/// 其他格式按源文件划分，返回 `None`；`snip` 为 `None` 时只判断格式。
+fn split_dests(
+    out_dir: &Path,
+    snip: Option<&snip::Snippet>,
+    format: Format,
+) -> Option<Vec<PathBuf>> {
+    let snip = match (format, snip) {
+        (Format::Zed, Some(snip)) | (Format::TmSnippet, Some(snip)) => snip,
+        (Format::Zed, None) | (Format::TmSnippet, None) => return Some(Vec::new()),
+        _ => return None,
+    };
+    if format == Format::TmSnippet {
+        let name = format!("{}.{}", snip.get_identifier(), format.extension());
+        return Some(vec![out_dir.join(name)]);
+    }
+    let mut dests: Vec<PathBuf> = snip
+        .scopes()
+        .map(|scope| out_dir.join(format!("{}.json", lang::zed_name(scope))))
+        .collect();
+    if dests.is_empty() {
+        dests.push(out_dir.join("snippets.json"));
+    }
+    return Some(dests);
+}
+
+/// 写出时使用的 JSON 风格：`--match-existing-style` 时沿用 `candidates` 中第一个已存在的文件的写法
+fn output_style(candidates: &[&Path], options: &BuildOptions) -> JsonStyle {
+    if options.match_existing_style {
+        for path in candidates.iter() {
+            if let Ok(existing) = fs::read_to_string(path) {
+                return JsonStyle::sniff(&existing, options.json_style);
+            }
+        }
+    }
+    return options.json_style;
+}
+
+/// 按 `style` 序列化 `snips` 并写入 `dest`，`sources` 只用于输出提示
+fn emit(
+    snips: &[snip::Snippet],
+    sources: &[PathBuf],
+    dest: &Path,
+    style: JsonStyle,
+    options: &BuildOptions,
+    report: &mut BuildReport,
+) -> Result<(), MdppetError> {
+    // 一个 `.tmSnippet` 只能容纳一个 plist，多个 Snippet 经 `mdppet:output` 或 `@file` 路由到同一文件时无法写出
+    if options.format == Format::TmSnippet && snips.len() > 1 {
+        let identifiers: Vec<String> = snips
+            .iter()
+            .map(|snip| format!("`{}`", snip.get_identifier()))
+            .collect();
+        return Err(MdppetError::Validation(
+            dest.to_path_buf(),
+            vec![format!(
+                "a tmSnippet file holds a single snippet, but {} are routed to it",
+                identifiers.join(", ")
+            )],
+        ));
+    }
+    let serielized_text = output::render(snips, options.format, style);
+    if options.validate {
+        let violations = validate::validate_rendered(&serielized_text, options.format);
+        if !violations.is_empty() {
+            return Err(MdppetError::Validation(dest.to_path_buf(), violations));
+        }
+    }
+    report.record_output(dest, sources, snips);
+    if options.check {
+        return check_output(&serielized_text, dest, options);
+    }
+    output::write_file(dest, &serielized_text, &options.write)?;
+    let names: Vec<String> = sources
+        .iter()
+        .map(|src| src.display().to_string())
+        .collect();
+    println!("{} -> {}", names.join(", "), dest.display());
+    return Ok(());
+}
+
+/// `--check`：将重新生成的 `text` 与已有的 `dest` 比较，不写入
+///
+/// 比较前按 `--newline` 统一换行符；不一致时列出差异的概要，返回 `Outdated`。
+fn check_output(text: &str, dest: &Path, options: &BuildOptions) -> Result<(), MdppetError> {
+    let text = options.write.newline.normalize(text);
+    let existing = match fs::read(dest) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            println!("{}: missing", dest.display());
+            return Err(MdppetError::Outdated(1));
+        }
+        Err(e) => return Err(MdppetError::Io(dest.to_path_buf(), e)),
+    };
+    if existing == text {
+        if options.verbose {
+            println!("{}: up to date", dest.display());
+        }
+        return Ok(());
+    }
+    println!("{}: out of date", dest.display());
+    for line in output::diff_summary(&existing, &text, options.format) {
+        println!("    {}", line);
+    }
+    return Err(MdppetError::Outdated(1));
+}
+
+/// 解析 `sources` 中的所有 Snippet 并去除重复
+///
+/// 解析出错的 Snippet 被跳过，错误记入 `diagnostics`；只有 `--fail-fast` 时才会返回 `Err`。
+fn parse_sources(
+    sources: &[PathBuf],
+    options: &BuildOptions,
+    diagnostics: &mut Diagnostics,
+    report: &mut BuildReport,
+) -> Result<Vec<snip::Snippet>, MdppetError> {
+    let parsed = parse_each(sources, options, diagnostics, report)?;
+    return dedup(named(&parsed), diagnostics);
+}
+
+/// 逐个解析 `sources`，返回每个源文件及其中的 Snippet，不去重
+fn parse_each(
+    sources: &[PathBuf],
+    options: &BuildOptions,
+    diagnostics: &mut Diagnostics,
+    report: &mut BuildReport,
+) -> Result<Vec<(PathBuf, Vec<snip::Snippet>)>, MdppetError> {
+    let mut parsed: Vec<(PathBuf, Vec<snip::Snippet>)> = Vec::new();
+    if options.jobs <= 1 || sources.len() <= 1 {
+        for src in sources.iter() {
+            let snips = parse_source(src, options, diagnostics, report.sourcemap.as_mut())?;
+            report.snippets += snips.len();
+            parsed.push((src.clone(), snips));
+        }
+        return Ok(parsed);
     }
 
-    let mut json_buffer: BTreeMap<&str, &snip::SnippetBody> = BTreeMap::new();
-    let mut ostream = fs::File::create(Path::new(out)).ok().unwrap();
-    for i in snips.iter() {
-        let id = i.get_identifier().as_str();
-        let body = i.get_snippetbody();
-        json_buffer.insert(id, body);
+    // 每个线程按顺序解析连续的一段源文件，诊断信息和位置先各自收集，再按源文件的顺序合并，
+    // 因此结果与单线程时相同；`--fail-fast` 时要等所有线程结束后才停止
+    let chunk_size = sources.len().div_ceil(options.jobs);
+    let with_sourcemap = report.sourcemap.is_some();
+    let chunks = std::thread::scope(|scope| {
+        let workers: Vec<_> = sources
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    let mut collected = Diagnostics::new(false);
+                    let mut sourcemap = with_sourcemap.then(sourcemap::SnippetMap::new);
+                    let mut parsed: Vec<(PathBuf, Result<Vec<snip::Snippet>, MdppetError>)> =
+                        Vec::new();
+                    for src in chunk.iter() {
+                        let snips = parse_source(src, options, &mut collected, sourcemap.as_mut());
+                        parsed.push((src.clone(), snips));
+                    }
+                    return (parsed, collected, sourcemap);
+                })
+            })
+            .collect();
+        return workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .collect::<Vec<_>>();
+    });
+    for (chunk, mut collected, sourcemap) in chunks {
+        for diagnostic in collected.take() {
+            diagnostics.push(diagnostic)?;
+        }
+        if let (Some(all), Some(sourcemap)) = (report.sourcemap.as_mut(), sourcemap) {
+            all.merge(sourcemap);
+        }
+        for (src, snips) in chunk {
+            let snips = snips?;
+            report.snippets += snips.len();
+            parsed.push((src, snips));
+        }
     }
+    return Ok(parsed);
+}
+
+/// 将 `parse_each` 的结果展开为附带来源的 Snippet，供 `dedup` 使用
+fn named(parsed: &[(PathBuf, Vec<snip::Snippet>)]) -> Vec<(String, snip::Snippet)> {
+    let mut named: Vec<(String, snip::Snippet)> = Vec::new();
+    for (src, snips) in parsed.iter() {
+        let name = src.display().to_string();
+        named.extend(snips.iter().map(|snip| (name.clone(), snip.clone())));
+    }
+    return named;
+}
 
-    let serielized_text = serde_json::to_string_pretty(&json_buffer).ok().unwrap();
-    write!(&mut ostream, "{}", serielized_text).ok().unwrap();
-    println!("{} -> {}", src, out);
+/// 去除重复的 Snippet，`parsed` 中每一项附带其来源
+fn dedup(
+    parsed: Vec<(String, snip::Snippet)>,
+    diagnostics: &mut Diagnostics,
+) -> Result<Vec<snip::Snippet>, MdppetError> {
+    let deduped = snip::dedup_snippets(parsed);
+    if deduped.collapsed > 0 {
+        diagnostics.note(&format!(
+            "collapsed {} identical duplicate snippet(s)",
+            deduped.collapsed
+        ));
+    }
+    for conflict in deduped.conflicts.iter() {
+        if let MdppetError::ConflictingSnippet { sources, .. } = conflict {
+            diagnostics.push(Diagnostic::from_error(Path::new(&sources.1), conflict))?;
+        }
+    }
+    return Ok(deduped.kept.into_iter().map(|(_, snip)| snip).collect());
+}
+
+/// 解析一个源文件中的 Snippet，并按选项进行变换和筛选
+///
+/// 给出 `sourcemap` 时记录保留下来的 Snippet 的位置，标识符是经过 `--rename` 之后的。
+fn parse_source(
+    src: &Path,
+    options: &BuildOptions,
+    diagnostics: &mut Diagnostics,
+    mut sourcemap: Option<&mut sourcemap::SnippetMap>,
+) -> Result<Vec<snip::Snippet>, MdppetError> {
+    let source = match source::load(src) {
+        Ok(source) => source,
+        Err(e) => {
+            diagnostics.push(Diagnostic::from_error(src, &e))?;
+            return Ok(Vec::new());
+        }
+    };
+    let parse = match options.parse.with_front_matter(&source.front_matter) {
+        Ok(parse) => parse,
+        Err(message) => {
+            let e = MdppetError::FrontMatter(src.to_path_buf(), message);
+            diagnostics.push(Diagnostic::from_error(src, &e))?;
+            return Ok(Vec::new());
+        }
+    };
+    let (text, origins) = snip::strip_html_comments_mapped(&source.text);
+    let map = source.map.remap(&origins);
+    let mut warnings = snip::check_segment_boundaries(&text, &parse);
+    warnings.extend(snip::check_synthesized_ids(&text, &parse));
+    if options.strict {
+        warnings.extend(snip::check_heading_style(&text, &parse));
+        warnings.sort_by_key(|warning| warning.line);
+    }
+    for warning in warnings {
+        let (file, line) = map.locate(warning.line);
+        diagnostics.push(Diagnostic::warning(
+            file,
+            line,
+            warning.code,
+            warning.message,
+        ))?;
+    }
+    let fences = match sourcemap {
+        Some(_) => sourcemap::fence_lines(&text, &parse),
+        None => BTreeMap::new(),
+    };
+    let (snips, errors) = snip::parse_snippets_collecting(&text, &parse);
+    for (line, e) in errors {
+        let (file, line) = map.locate(line);
+        if options.lenient && e.is_field_error() {
+            let warning = Diagnostic::warning(file, line, e.code(), e.to_string());
+            diagnostics.push(warning.with_identifier(e.identifier().unwrap()))?;
+        } else {
+            diagnostics.push(Diagnostic::error(file, line, &e))?;
+        }
+    }
+    let mut parsed: Vec<snip::Snippet> = Vec::new();
+    for (line, mut snip) in snips {
+        if options.template_placeholders {
+            snip.template_placeholders(&options.vars);
+        }
+        if let Err(e) = snip.expand_vars(&options.vars) {
+            let (file, line) = map.locate(line);
+            diagnostics.push(Diagnostic::error(file, line, &e))?;
+            continue;
+        }
+        if let Some(lang) = snip.foreign_fence_lang() {
+            if !options.fix_scope_from_fence {
+                let (file, line) = map.locate(line);
+                let message = format!(
+                    "code block of snippet `{}` is marked `{}`, which is not one of its scopes ({}); use --fix-scope-from-fence to adopt it",
+                    snip.get_identifier(),
+                    lang,
+                    snip.get_scope()
+                );
+                let warning = Diagnostic::warning(file, line, "fence-scope", message)
+                    .with_identifier(snip.get_identifier());
+                diagnostics.push(warning)?;
+            } else {
+                snip.fix_scope_from_fence();
+            }
+        }
+        if !options.keeps_scopes(&snip) {
+            continue;
+        }
+        if snip.is_deprecated() && options.deprecated == snip::Deprecated::Skip {
+            continue;
+        }
+        if options.warn_keyword_prefixes {
+            let scopes = snip.keyword_prefix_scopes();
+            if !scopes.is_empty() {
+                let (file, line) = map.locate(line);
+                let message = format!(
+                    "prefix `{}` of snippet `{}` is a keyword in {}",
+                    snip.get_prefix(),
+                    snip.get_identifier(),
+                    scopes.join(", ")
+                );
+                let warning = Diagnostic::warning(file, line, "keyword-prefix", message)
+                    .with_identifier(snip.get_identifier());
+                diagnostics.push(warning)?;
+            }
+        }
+        if options.expand_escapes {
+            snip.expand_escapes();
+        }
+        if options.trim_blank_body_lines {
+            snip.trim_blank_body_lines();
+        }
+        if options.plain_descriptions {
+            snip.plain_descriptions();
+        }
+        let (body_warnings, body_errors) = snip.check_body(options.max_body_lines);
+        if !body_warnings.is_empty() || !body_errors.is_empty() {
+            let (file, line) = map.locate(line);
+            for (code, message) in body_warnings {
+                let warning = Diagnostic::warning(file, line, code, message)
+                    .with_identifier(snip.get_identifier());
+                diagnostics.push(warning)?;
+            }
+            if !body_errors.is_empty() {
+                for e in body_errors.iter() {
+                    diagnostics.push(Diagnostic::error(file, line, e))?;
+                }
+                continue;
+            }
+        }
+        snip.default_description(options.default_description);
+        if snip.is_deprecated() && options.deprecated == snip::Deprecated::Annotate {
+            snip.annotate_deprecated();
+        }
+        snip.rename(&options.renames);
+        if let Some(sourcemap) = sourcemap.as_mut() {
+            let (body_start, body_end) = fences
+                .get(&line)
+                .and_then(|fences| sourcemap::body_lines(fences, snip.get_fence_lang()))
+                .unwrap_or((line, line));
+            let (file, heading_line) = map.locate(line);
+            sourcemap.insert(
+                src,
+                snip.get_identifier(),
+                sourcemap::Location {
+                    file: file.display().to_string(),
+                    heading_line,
+                    body_start_line: map.locate(body_start).1,
+                    body_end_line: map.locate(body_end).1,
+                },
+            );
+        }
+        parsed.push(snip);
+    }
+    return Ok(parsed);
+}
+
+/// 展开命令行中给出的源文件，目录会被递归地展开为其中的 `.md` 文件，
+/// zip 压缩包会被展开为其中的 `.md` 条目
+///
+/// 展开目录时遵循 `.gitignore` 和 `.ignore`，并跳过隐藏的文件（`--no-ignore`、`--hidden`）；
+/// `-v` 时列出被跳过的文件及原因。
+fn collect_sources(args: &ArgMatches) -> Result<Vec<PathBuf>, MdppetError> {
+    let inputs: Vec<PathBuf> = args.values_of("src").unwrap().map(PathBuf::from).collect();
+    return expand_sources(&inputs, &walk_options(args), args.is_present("verbose"));
+}
+
+/// `--no-ignore` 和 `--hidden`
+fn walk_options(args: &ArgMatches) -> walk::WalkOptions {
+    walk::WalkOptions {
+        ignore: !args.is_present("no-ignore"),
+        hidden: args.is_present("hidden"),
+    }
+}
+
+/// 按 `collect_sources` 的规则展开 `inputs`
+fn expand_sources(
+    inputs: &[PathBuf],
+    options: &walk::WalkOptions,
+    verbose: bool,
+) -> Result<Vec<PathBuf>, MdppetError> {
+    let mut sources: Vec<PathBuf> = Vec::new();
+    for path in inputs.iter() {
+        if path.is_dir() {
+            if verbose {
+                for (skipped, reason) in walk::skipped(path, options)? {
+                    eprintln!("{}: skipping {}: {}", BIN_NAME, skipped.display(), reason);
+                }
+            }
+            sources.extend(walk::markdown_files(path, options)?);
+        } else if archive::is_archive(path) {
+            sources.extend(archive::members(path)?);
+        } else {
+            sources.push(path.clone());
+        }
+    }
+    return Ok(sources);
+}
+
+/// 输出文件比源文件新时无需重新生成
+fn is_up_to_date(src: &Path, dest: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified());
+    match (modified(src), modified(dest)) {
+        (Ok(src_time), Ok(dest_time)) => return dest_time >= src_time,
+        _ => return false,
+    }
+}
+
+/// 构建时的选项，顶层命令和 `mdppet build` 共用
+fn build_option_args() -> Vec<Arg<'static, 'static>> {
+    return vec![
+        Arg::with_name("validate")
+            .long("validate")
+            .visible_alias("validate-output")
+            .help("Check the generated output against the VSCode snippet schema before writing"),
+        Arg::with_name("strict")
+            .long("strict")
+            .help("Warn about snippet headings that only parse thanks to lenient matching"),
+        Arg::with_name("warn-keyword-prefixes")
+            .long("warn-keyword-prefixes")
+            .help("Warn about prefixes that are keywords of the snippet's language"),
+        Arg::with_name("fix-scope-from-fence")
+            .long("fix-scope-from-fence")
+            .help("Add the code block language to the scopes when it is not one of the snippet's scopes"),
+        Arg::with_name("max-body-lines")
+            .long("max-body-lines")
+            .value_name("N")
+            .default_value("200")
+            .validator(|value| {
+                value
+                    .parse::<usize>()
+                    .map(|_| ())
+                    .map_err(|_| format!("expected a number of lines, got `{}`", value))
+            })
+            .help("Warn about snippet bodies longer than N lines (0 disables the check)"),
+        Arg::with_name("jobs")
+            .long("jobs")
+            .short("j")
+            .value_name("N")
+            .default_value("1")
+            .validator(|value| match value.parse::<usize>() {
+                Ok(jobs) if jobs > 0 => Ok(()),
+                _ => Err(format!("expected a positive number of threads, got `{}`", value)),
+            })
+            .help("Parse source files on N threads; the output does not depend on N"),
+        Arg::with_name("fail-fast")
+            .long("fail-fast")
+            .help("Stop at the first error instead of reporting every broken snippet"),
+        Arg::with_name("keep-going")
+            .long("keep-going")
+            .conflicts_with("fail-fast")
+            .help("Write the snippets that parsed even if other snippets have errors"),
+        Arg::with_name("lenient")
+            .long("lenient")
+            .help("Drop snippets with an empty prefix or body, or a padded identifier, with a warning instead of an error"),
+        Arg::with_name("message-format")
+            .long("message-format")
+            .possible_values(MessageFormat::NAMES)
+            .default_value("human")
+            .help("Print diagnostics as text, or as one JSON object per line"),
+        Arg::with_name("messages-on-stdout")
+            .long("messages-on-stdout")
+            .help("Print diagnostics on stdout instead of stderr"),
+        Arg::with_name("summary")
+            .long("summary")
+            .possible_values(MessageFormat::NAMES)
+            .default_value("human")
+            .help("Print the build summary as text, or as a JSON object"),
+        Arg::with_name("quiet")
+            .long("quiet")
+            .short("q")
+            .help("Do not print the build summary unless --summary json is given"),
+        Arg::with_name("verbose")
+            .long("verbose")
+            .short("v")
+            .help("Print what is being done"),
+        Arg::with_name("no-clobber")
+            .long("no-clobber")
+            .conflicts_with("force")
+            .help("Refuse to overwrite an existing destination"),
+        Arg::with_name("force")
+            .long("force")
+            .short("f")
+            .help("Overwrite read-only destinations or ones not generated by mdppet"),
+        Arg::with_name("backup")
+            .long("backup")
+            .help("Back up an existing destination before overwriting it"),
+        Arg::with_name("backup-style")
+            .long("backup-style")
+            .possible_values(BackupStyle::NAMES)
+            .default_value("simple")
+            .help("Name backups out.json.bak, or out.json.<timestamp>.bak"),
+        Arg::with_name("ascii-output")
+            .long("ascii-output")
+            .visible_alias("escape-non-ascii")
+            .help("Escape every non-ASCII character in the JSON output as \\uXXXX"),
+        Arg::with_name("json-indent")
+            .long("json-indent")
+            .default_value("2")
+            .validator(|value| Indent::from_name(&value).map(|_| ()))
+            .help("Indent JSON output with this many spaces (1 to 8), or `tab`"),
+        Arg::with_name("match-existing-style")
+            .long("match-existing-style")
+            .help("Copy the indentation and escaping of the existing destination file"),
+        Arg::with_name("include-scopes")
+            .long("include-scopes")
+            .takes_value(true)
+            .use_delimiter(true)
+            .multiple(true)
+            .help("Only emit snippets with at least one of these comma-separated scopes"),
+        Arg::with_name("exclude-scopes")
+            .long("exclude-scopes")
+            .takes_value(true)
+            .use_delimiter(true)
+            .multiple(true)
+            .help("Skip snippets with any of these comma-separated scopes"),
+        Arg::with_name("default-description")
+            .long("default-description")
+            .possible_values(snip::DefaultDescription::NAMES)
+            .default_value("none")
+            .help("Description of snippets written without one: omitted, the identifier or the prefix"),
+        Arg::with_name("deprecated")
+            .long("deprecated")
+            .possible_values(snip::Deprecated::NAMES)
+            .default_value("include")
+            .help("What to do with snippets marked @deprecated: drop them, note it in the description, or keep them as is"),
+        Arg::with_name("plain-descriptions")
+            .long("plain-descriptions")
+            .help("Strip inline markdown (emphasis, code, links) from descriptions"),
+        Arg::with_name("expand-escapes")
+            .long("expand-escapes")
+            .help("Turn literal \\n in body lines into separate lines and \\t into tabs"),
+        Arg::with_name("trim-blank-body-lines")
+            .long("trim-blank-body-lines")
+            .help("Strip blank lines from the start and end of each body"),
+        Arg::with_name("define")
+            .long("define")
+            .short("D")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(|value| vars::parse_define(&value).map(|_| ()))
+            .help("Define a {{key}} variable expanded in bodies and descriptions, as key=value"),
+        Arg::with_name("date-var")
+            .long("date-var")
+            .help("Define {{date}} as the build date (SOURCE_DATE_EPOCH if set), making the output depend on it"),
+        Arg::with_name("rename")
+            .long("rename")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(|value| snip::parse_rename(&value).map(|_| ()))
+            .help("Emit the snippet with identifier old under the key new, as old=new"),
+        Arg::with_name("allow-undefined-vars")
+            .long("allow-undefined-vars")
+            .help("Leave {{key}} placeholders with no definition as they are"),
+        Arg::with_name("template-placeholders")
+            .long("template-placeholders")
+            .help("Turn undefined {{name}} placeholders in bodies into ${1:name} tabstops"),
+        Arg::with_name("keep-trailing-newline")
+            .long("keep-trailing-newline")
+            .help("Keep one empty last body line when a code block ends with a blank line"),
+        Arg::with_name("no-trim-trailing-whitespace")
+            .long("no-trim-trailing-whitespace")
+            .help("Keep spaces and tabs at the end of body lines"),
+        Arg::with_name("newline")
+            .long("newline")
+            .possible_values(Newline::NAMES)
+            .default_value("lf")
+            .help("Line endings of generated files"),
+        Arg::with_name("max-snippets-per-file")
+            .long("max-snippets-per-file")
+            .value_name("N")
+            .validator(|value| positive(&value, "snippets"))
+            .help("Split a json or code-snippets output holding more than N snippets into numbered parts"),
+        Arg::with_name("max-file-size")
+            .long("max-file-size")
+            .value_name("bytes")
+            .validator(|value| positive(&value, "bytes"))
+            .help("Split a json or code-snippets output larger than this into numbered parts"),
+    ];
+}
+
+/// 校验参数是正整数，`unit` 用于错误信息
+fn positive(value: &str, unit: &str) -> Result<(), String> {
+    match value.parse::<usize>() {
+        Ok(n) if n > 0 => return Ok(()),
+        _ => {
+            return Err(format!(
+                "expected a positive number of {}, got `{}`",
+                unit, value
+            ))
+        }
+    }
 }
 
 fn get_app() -> App<'static, 'static> {
@@ -40,21 +1488,237 @@ fn get_app() -> App<'static, 'static> {
         .about("mdppet is a tool to transfer markdown to vscode snippet json.")
         .version("0.1.1")
         .author("zombie110year <zombie110year@outlook.com>")
-        .arg(Arg::with_name("src").required(true))
-        .arg(Arg::with_name("dest").short("o").default_value("out.json"));
+        .after_help(EXIT_CODES)
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .setting(AppSettings::ArgsNegateSubcommands)
+        .arg(Arg::with_name("src").required(true).multiple(true))
+        .arg(
+            Arg::with_name("dest")
+                .short("o")
+                .takes_value(true)
+                .help("Output file [default: out.json, or out.code-snippets]"),
+        )
+        .arg(
+            Arg::with_name("out-dir")
+                .long("out-dir")
+                .takes_value(true)
+                .conflicts_with("dest")
+                .help("Write one output file per source into this directory, skipping up-to-date ones"),
+        )
+        .arg(
+            Arg::with_name("list")
+                .long("list")
+                .help("Print a table of the parsed snippets instead of writing output"),
+        )
+        .arg(
+            Arg::with_name("check")
+                .long("check")
+                .conflicts_with_all(&["list", "preview"])
+                .help("Compare the output with the existing files instead of writing, failing if they differ; implies --validate"),
+        )
+        .arg(
+            Arg::with_name("color")
+                .long("color")
+                .possible_values(ColorChoice::NAMES)
+                .default_value("auto")
+                .global(true)
+                .help("Color errors and warnings: auto colors only on a terminal without NO_COLOR"),
+        )
+        .arg(
+            Arg::with_name("preview")
+                .long("preview")
+                .takes_value(true)
+                .value_name("ID")
+                .conflicts_with_all(&["list", "out-dir", "dest"])
+                .help("Print the output entry of a single snippet instead of writing output"),
+        )
+        .arg(
+            Arg::with_name("emit-sourcemap")
+                .long("emit-sourcemap")
+                .takes_value(true)
+                .value_name("path")
+                .conflicts_with_all(&["list", "preview"])
+                .help("Also write a JSON map from each output file and snippet identifier to its markdown source lines"),
+        )
+        .arg(
+            Arg::with_name("relative-to")
+                .long("relative-to")
+                .takes_value(true)
+                .value_name("dir")
+                .requires("emit-sourcemap")
+                .help("Write the paths in the --emit-sourcemap map relative to this directory"),
+        )
+        .arg(
+            Arg::with_name("no-ignore")
+                .long("no-ignore")
+                .global(true)
+                .help("Include files excluded by .gitignore and .ignore when walking directories"),
+        )
+        .arg(
+            Arg::with_name("hidden")
+                .long("hidden")
+                .global(true)
+                .help("Include hidden files and directories when walking directories"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .possible_values(Format::NAMES)
+                .default_value("json")
+                .help("Output format"),
+        )
+        .arg(
+            Arg::with_name("install")
+                .long("install")
+                .conflicts_with_all(&["out-dir", "dest", "list", "preview"])
+                .help("Write one file per language into Zed's snippet directory (--format zed)"),
+        )
+        .arg(
+            Arg::with_name("field-sep")
+                .long("field-sep")
+                .global(true)
+                .default_value(snip::DEFAULT_FIELD_SEP)
+                .help("Separator between identifier, prefix and scope in headings"),
+        )
+        .arg(
+            Arg::with_name("comment-leader")
+                .long("comment-leader")
+                .global(true)
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|value| snip::parse_comment_leader(&value).map(|_| ()))
+                .help("Line comment leader used by mdppet:commentify for a language, as lang=leader"),
+        )
+        .arg(
+            Arg::with_name("heading-level")
+                .long("heading-level")
+                .global(true)
+                .default_value("1")
+                .validator(|value| snip::parse_heading_level(&value).map(|_| ()))
+                .help("Number of `#` introducing a snippet section; front matter may override it"),
+        )
+        .arg(
+            Arg::with_name("group-heading-level")
+                .long("group-heading-level")
+                .global(true)
+                .takes_value(true)
+                .validator(|value| snip::parse_heading_level(&value).map(|_| ()))
+                .help("Headings of this level name a language that becomes the default scope"),
+        )
+        .args(&build_option_args())
+        .subcommand(
+            SubCommand::with_name("fmt")
+                .about("Rewrite snippet sources in the canonical markdown layout")
+                .arg(Arg::with_name("src").required(true).multiple(true))
+                .arg(
+                    Arg::with_name("check")
+                        .long("check")
+                        .help("Only report files that would be reformatted, exiting with 3 if any"),
+                )
+                .arg(
+                    Arg::with_name("no-sort")
+                        .long("no-sort")
+                        .help("Keep snippets in their original order instead of sorting by identifier"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("normalize")
+                .about("Regenerate the snippets in sources from the parsed snippets, keeping the prose between them")
+                .arg(Arg::with_name("src").required(true).multiple(true))
+                .arg(
+                    Arg::with_name("in-place")
+                        .long("in-place")
+                        .short("i")
+                        .help("Rewrite the source files instead of printing the result"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("build")
+                .about("Build the targets defined in mdppet.toml, parsing the shared sources once")
+                .args(&build_option_args())
+                .arg(
+                    Arg::with_name("target")
+                        .multiple(true)
+                        .help("Targets to build [default: all targets]"),
+                )
+                .arg(
+                    Arg::with_name("config")
+                        .long("config")
+                        .takes_value(true)
+                        .value_name("file")
+                        .default_value(config::CONFIG_FILE)
+                        .help("Read the targets from this file"),
+                )
+                .arg(
+                    Arg::with_name("check")
+                        .long("check")
+                        .help("Compare the output of every target with the existing files instead of writing"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("expand")
+                .about("Print a snippet body as inserted when every placeholder keeps its default")
+                .args(&build_option_args())
+                .arg(Arg::with_name("src").required(true).multiple(true))
+                .arg(
+                    Arg::with_name("id")
+                        .long("id")
+                        .takes_value(true)
+                        .value_name("identifier")
+                        .required_unless("prefix")
+                        .conflicts_with("prefix")
+                        .help("Expand the snippet with this identifier"),
+                )
+                .arg(
+                    Arg::with_name("prefix")
+                        .long("prefix")
+                        .takes_value(true)
+                        .help("Expand every snippet triggered by this prefix"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("rename")
+                .about("Rename a snippet identifier or prefix in the sources and an existing output")
+                .arg(Arg::with_name("src").required(true).multiple(true))
+                .arg(
+                    Arg::with_name("id")
+                        .long("id")
+                        .takes_value(true)
+                        .value_name("old=new")
+                        .required_unless("prefix")
+                        .validator(|value| snip::parse_rename(&value).map(|_| ()))
+                        .help("Rename the identifier in snippet headings"),
+                )
+                .arg(
+                    Arg::with_name("prefix")
+                        .long("prefix")
+                        .takes_value(true)
+                        .value_name("old=new")
+                        .validator(|value| snip::parse_rename(&value).map(|_| ()))
+                        .help("Rename the prefix in snippet headings"),
+                )
+                .arg(
+                    Arg::with_name("dest")
+                        .short("o")
+                        .takes_value(true)
+                        .help("Also update this generated json or code-snippets output"),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Print the edits as diffs without writing anything"),
+                ),
+        );
 
     return parser;
 }
 
-fn get_read_stream(file: &Path) -> io::BufReader<fs::File> {
-    let ifile = fs::File::open(file).ok().unwrap();
-    let istream = io::BufReader::new(ifile);
-    return istream;
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::{Duration, SystemTime};
+
     #[test]
     fn test_arg_parse() {
         let parser = get_app();
@@ -64,4 +1728,339 @@ mod tests {
         assert_eq!(src, "source.md");
         assert_eq!(out, "output.json");
     }
+    #[test]
+    fn test_arg_parse_multiple_src() {
+        let matches =
+            get_app().get_matches_from([BIN_NAME, "a.md", "b.md", "-o", "out.json"].iter());
+        let sources: Vec<&str> = matches.values_of("src").unwrap().collect();
+        assert_eq!(sources, vec!["a.md", "b.md"]);
+        assert_eq!(matches.value_of("dest"), Some("out.json"));
+    }
+    #[test]
+    fn test_arg_parse_fmt() {
+        let matches =
+            get_app().get_matches_from([BIN_NAME, "fmt", "--check", "a.md", "b.md"].iter());
+        let fmt_args = matches
+            .subcommand_matches("fmt")
+            .expect("应当解析为 fmt 子命令");
+        assert!(fmt_args.is_present("check"));
+        assert_eq!(fmt_args.values_of("src").unwrap().count(), 2);
+        assert_eq!(parse_options(fmt_args).heading_level, 1);
+
+        let matches = get_app().get_matches_from([BIN_NAME, "a.md", "fmt"].iter());
+        assert!(matches.subcommand_matches("fmt").is_none());
+        assert_eq!(matches.values_of("src").unwrap().count(), 2);
+    }
+    #[test]
+    fn test_arg_parse_format() {
+        let matches = get_app().get_matches_from([BIN_NAME, "source.md"].iter());
+        assert_eq!(matches.value_of("format"), Some("json"));
+        assert_eq!(matches.value_of("dest"), None);
+
+        let matches =
+            get_app().get_matches_from([BIN_NAME, "source.md", "--format", "code-snippets"].iter());
+        let format = Format::from_name(matches.value_of("format").unwrap()).unwrap();
+        assert_eq!(format, Format::CodeSnippets);
+        assert_eq!(format.default_dest(), "out.code-snippets");
+    }
+    #[test]
+    fn test_scope_filters() {
+        let matches = get_app().get_matches_from(
+            [
+                BIN_NAME,
+                "tests/test_markdown.2.md",
+                "--include-scopes",
+                "python,c",
+            ]
+            .iter(),
+        );
+        let options = BuildOptions::from_args(&matches);
+        let sources = [PathBuf::from("tests/test_markdown.2.md")];
+        let mut diagnostics = Diagnostics::new(true);
+        let ids: Vec<String> = parse_sources(
+            &sources,
+            &options,
+            &mut diagnostics,
+            &mut BuildReport::new(),
+        )
+        .unwrap()
+        .iter()
+        .map(|s| s.get_identifier().clone())
+        .collect();
+        assert_eq!(ids, vec!["a", "abc"]);
+
+        let matches = get_app().get_matches_from(
+            [
+                BIN_NAME,
+                "tests/test_markdown.2.md",
+                "--exclude-scopes",
+                "lua",
+            ]
+            .iter(),
+        );
+        let options = BuildOptions::from_args(&matches);
+        let mut diagnostics = Diagnostics::new(true);
+        let ids: Vec<String> = parse_sources(
+            &sources,
+            &options,
+            &mut diagnostics,
+            &mut BuildReport::new(),
+        )
+        .unwrap()
+        .iter()
+        .map(|s| s.get_identifier().clone())
+        .collect();
+        assert_eq!(ids, vec!["a", "e"]);
+    }
+    #[test]
+    fn test_zed_out_dir() {
+        let written = build_golden("tests/test_markdown_zed.md", "zed", "tests/zed");
+        assert_eq!(written, vec!["python.json", "rust.json"]);
+    }
+    #[test]
+    fn test_tmsnippet_out_dir() {
+        let written = build_golden(
+            "tests/test_markdown_tmsnippet.md",
+            "tmsnippet",
+            "tests/tmsnippet",
+        );
+        assert_eq!(written, vec!["cmp.tmSnippet", "tag.tmSnippet"]);
+    }
+    /// 以 `--out-dir` 构建 `src`，逐一与 `golden` 目录中的同名文件比较，返回生成的文件名
+    fn build_golden(src: &str, format: &str, golden: &str) -> Vec<String> {
+        let out_dir = temp_path(format);
+        let args = [
+            BIN_NAME,
+            src,
+            "--format",
+            format,
+            "--out-dir",
+            out_dir.to_str().unwrap(),
+        ];
+        run(&get_app().get_matches_from(args.iter())).unwrap();
+
+        let mut written: Vec<String> = fs::read_dir(&out_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        written.sort();
+        for name in written.iter() {
+            assert_eq!(
+                fs::read_to_string(out_dir.join(name)).unwrap(),
+                fs::read_to_string(Path::new(golden).join(name)).unwrap(),
+                "{} 与预期不一致",
+                name
+            );
+        }
+        fs::remove_dir_all(&out_dir).unwrap();
+        return written;
+    }
+    #[test]
+    fn test_rename() {
+        let dir = temp_path("rename");
+        fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("snips.md");
+        let dest = dir.join("out.json");
+        fs::copy("tests/test_markdown.2.md", &src).unwrap();
+        let original = fs::read_to_string(&src).unwrap();
+        let args = [
+            BIN_NAME,
+            src.to_str().unwrap(),
+            "-o",
+            dest.to_str().unwrap(),
+            "--rename",
+            "abc=alphabet",
+            "--rename",
+            "nope=unused",
+        ];
+        run(&get_app().get_matches_from(args.iter())).unwrap();
+
+        let value = Format::Json
+            .parse(&fs::read_to_string(&dest).unwrap())
+            .unwrap();
+        assert!(value.get("abc").is_none());
+        assert_eq!(value["alphabet"]["prefix"], "123");
+        assert!(value.get("a").is_some());
+        assert_eq!(fs::read_to_string(&src).unwrap(), original);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn test_rename_subcommand() {
+        let dir = temp_path("rename-subcommand");
+        fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a.md"), dir.join("b.md"));
+        fs::copy("tests/rename/a.md", &a).unwrap();
+        fs::copy("tests/rename/b.md", &b).unwrap();
+        let (dest, expected) = (dir.join("out.json"), dir.join("expected.json"));
+        let paths = [a.to_str().unwrap(), b.to_str().unwrap()];
+        let build_args = |out: &Path| {
+            let mut args = vec![BIN_NAME, "-o", out.to_str().unwrap()];
+            args.extend(paths);
+            return get_app().get_matches_from(args);
+        };
+        run(&build_args(&dest)).unwrap();
+
+        let rename_args = |extra: &[&str]| {
+            let mut args = vec![BIN_NAME, "rename", "-o", dest.to_str().unwrap()];
+            args.extend(extra);
+            args.extend(paths);
+            return get_app().get_matches_from(args);
+        };
+        match run(&rename_args(&["--id", "loop=while"])) {
+            Err(MdppetError::RenameCollision(_, name)) => assert_eq!(name, "while"),
+            other => panic!("应当拒绝重名，实际为 {:?}", other),
+        }
+        run(&rename_args(&["--id", "license=spdx", "--dry-run"])).unwrap();
+        assert_eq!(
+            fs::read_to_string(&b).unwrap(),
+            fs::read_to_string("tests/rename/b.md").unwrap(),
+            "--dry-run 不应写入"
+        );
+
+        run(&rename_args(&[
+            "--id",
+            "license=spdx",
+            "--prefix",
+            "lp=forever",
+        ]))
+        .unwrap();
+        let a_text = fs::read_to_string(&a).unwrap();
+        let b_text = fs::read_to_string(&b).unwrap();
+        assert!(a_text.contains("\n# loop/forever/rust\n"));
+        assert!(b_text.starts_with("# spdx/lic/python,rust\n"));
+        assert!(b_text.contains("mentions loop/lp/rust in prose"));
+        assert!(b_text.contains("\n# forever/forever/python\n"));
+        run(&build_args(&expected)).unwrap();
+        assert_eq!(
+            fs::read_to_string(&dest).unwrap(),
+            fs::read_to_string(&expected).unwrap()
+        );
+        let value = Format::Json
+            .parse(&fs::read_to_string(&dest).unwrap())
+            .unwrap();
+        assert_eq!(value["spdx-rust"]["prefix"], "lic");
+        assert_eq!(value["loop"]["prefix"], "forever");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn test_validate_output_alias() {
+        let matches = get_app().get_matches_from([BIN_NAME, "a.md", "--validate-output"].iter());
+        assert!(BuildOptions::from_args(&matches).validate);
+        let matches = get_app().get_matches_from([BIN_NAME, "a.md", "--check"].iter());
+        assert!(BuildOptions::from_args(&matches).validate);
+    }
+    #[test]
+    fn test_collect_sources_from_dir() {
+        let dir = temp_path("collect");
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::write(dir.join("b.md"), "").unwrap();
+        fs::write(dir.join("a.md"), "").unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        fs::write(dir.join("nested").join("c.md"), "").unwrap();
+
+        let matches = get_app().get_matches_from([BIN_NAME, dir.to_str().unwrap()].iter());
+        let sources = collect_sources(&matches).unwrap();
+        assert_eq!(
+            sources,
+            vec![
+                dir.join("a.md"),
+                dir.join("b.md"),
+                dir.join("nested").join("c.md")
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn test_incremental_out_dir() {
+        let dir = temp_path("incremental");
+        let out_dir = dir.join("out");
+        fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("snips.md");
+        let dest = out_dir.join("snips.json");
+        fs::copy("tests/test_markdown.2.md", &src).unwrap();
+        let args = [
+            BIN_NAME,
+            src.to_str().unwrap(),
+            "--out-dir",
+            out_dir.to_str().unwrap(),
+        ];
+        let hour = Duration::from_secs(3600);
+
+        run(&get_app().get_matches_from(args.iter())).unwrap();
+        assert!(fs::read_to_string(&dest).unwrap().contains("\"abc\""));
+
+        // 输出比源文件新：跳过
+        let stale = format!("{}\n{{}}", output::GENERATED_MARKER);
+        fs::write(&dest, &stale).unwrap();
+        set_modified(&src, SystemTime::now() - hour);
+        run(&get_app().get_matches_from(args.iter())).unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), stale);
+
+        // 源文件更新过：重新生成
+        set_modified(&src, SystemTime::now() + hour);
+        run(&get_app().get_matches_from(args.iter())).unwrap();
+        assert!(fs::read_to_string(&dest).unwrap().contains("\"abc\""));
+
+        // --force 总是重新生成
+        fs::write(&dest, &stale).unwrap();
+        set_modified(&src, SystemTime::now() - hour);
+        let mut forced = args.to_vec();
+        forced.push("--force");
+        run(&get_app().get_matches_from(forced.iter())).unwrap();
+        assert!(fs::read_to_string(&dest).unwrap().contains("\"abc\""));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn test_routed_out_dir() {
+        let dir = temp_path("routed");
+        let out_dir = dir.join("out");
+        let args = [
+            BIN_NAME,
+            "tests/test_markdown_routing.md",
+            "--out-dir",
+            out_dir.to_str().unwrap(),
+        ];
+        run(&get_app().get_matches_from(args.iter())).unwrap();
+
+        let routing = fs::read_to_string(out_dir.join("test_markdown_routing.json")).unwrap();
+        assert!(routing.contains("\"local\""));
+        assert!(!routing.contains("\"shared\""));
+        let global = fs::read_to_string(out_dir.join("global.json")).unwrap();
+        assert!(global.contains("\"shared\""));
+        assert!(!global.contains("\"local\""));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn test_file_attribute_out_dir() {
+        let dir = temp_path("file-attribute");
+        let out_dir = dir.join("out");
+        let args = [
+            BIN_NAME,
+            "tests/test_markdown_file_attr.md",
+            "--out-dir",
+            out_dir.to_str().unwrap(),
+        ];
+        run(&get_app().get_matches_from(args.iter())).unwrap();
+
+        let keys = |name: &str| -> Vec<String> {
+            let text = fs::read_to_string(out_dir.join(name)).unwrap();
+            let value = Format::Json.parse(&text).unwrap();
+            return value.as_object().unwrap().keys().cloned().collect();
+        };
+        assert_eq!(keys("test_markdown_file_attr.json"), vec!["local"]);
+        assert_eq!(keys("collections.json"), vec!["map", "vec"]);
+        assert_eq!(keys("io/files.json"), vec!["read"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    fn set_modified(path: &Path, time: SystemTime) {
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(time).unwrap();
+    }
+
+    /// 在系统临时目录下为测试生成一个不会冲突的路径
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}-test-{}", BIN_NAME, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        return dir.join(name);
+    }
 }
@@ -0,0 +1,1493 @@
+use crate::error::MdppetError;
+use crate::snip::{self, ParseOptions, Snippet, SnippetBody};
+use crate::tmsnippet;
+use serde::Serialize;
+use serde_json::ser::{Formatter, PrettyFormatter, Serializer};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use unicode_width::UnicodeWidthStr;
+
+/// 输出格式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// 按语言划分的 `<language>.json` 代码片段文件
+    Json,
+    /// 项目级的 `.code-snippets` 文件，所有作用域写在同一个文件中，
+    /// 每一项都带有以 `,` 连接的 `scope` 字段
+    CodeSnippets,
+    /// 与 `Json` 内容相同的 TOML，供其他工具使用
+    Toml,
+    /// 与 `Json` 内容相同的 YAML，供其他工具使用
+    Yaml,
+    /// Zed 编辑器的 `<language>.json`：与 `Json` 相同，但没有 `scope` 字段；
+    /// 配合 `--out-dir` 按语言拆分为多个文件
+    Zed,
+    /// TextMate 系编辑器的 `.tmSnippet` plist，每个 Snippet 一个文件，须配合 `--out-dir`
+    TmSnippet,
+    /// 供阅读的 Markdown 文档：开头是目录，其后每个 Snippet 一节，见 `Snippet::to_markdown`
+    MarkdownIndex,
+    /// 与 `Json` 相同，每一项还带有元数据 `metadata`，供自己的工具使用
+    JsonFull,
+    /// 每行一个 JSON 对象：`identifier` 之外的字段与 `JsonFull` 相同
+    Ndjson,
+}
+
+impl Format {
+    /// `--format` 可以接受的取值
+    pub const NAMES: &'static [&'static str] = &[
+        "json",
+        "code-snippets",
+        "toml",
+        "yaml",
+        "zed",
+        "tmsnippet",
+        "markdown-index",
+        "json-full",
+        "ndjson",
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => return Some(Format::Json),
+            "code-snippets" => return Some(Format::CodeSnippets),
+            "toml" => return Some(Format::Toml),
+            "yaml" => return Some(Format::Yaml),
+            "zed" => return Some(Format::Zed),
+            "tmsnippet" => return Some(Format::TmSnippet),
+            "markdown-index" => return Some(Format::MarkdownIndex),
+            "json-full" => return Some(Format::JsonFull),
+            "ndjson" => return Some(Format::Ndjson),
+            _ => return None,
+        }
+    }
+
+    /// 输出文件的扩展名
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Json | Format::Zed | Format::JsonFull => return "json",
+            Format::Ndjson => return "ndjson",
+            Format::CodeSnippets => return "code-snippets",
+            Format::Toml => return "toml",
+            Format::Yaml => return "yaml",
+            Format::TmSnippet => return "tmSnippet",
+            Format::MarkdownIndex => return "md",
+        }
+    }
+
+    /// 未指定 `-o` 时的输出文件名
+    pub fn default_dest(&self) -> &'static str {
+        match self {
+            Format::Json | Format::Zed | Format::JsonFull => return "out.json",
+            Format::Ndjson => return "out.ndjson",
+            Format::CodeSnippets => return "out.code-snippets",
+            Format::Toml => return "out.toml",
+            Format::Yaml => return "out.yaml",
+            Format::TmSnippet => return "out.tmSnippet",
+            Format::MarkdownIndex => return "out.md",
+        }
+    }
+
+    /// 该格式中的生成标记
+    pub fn marker(&self) -> &'static str {
+        match self {
+            Format::Json
+            | Format::CodeSnippets
+            | Format::Zed
+            | Format::JsonFull
+            | Format::Ndjson => return GENERATED_MARKER,
+            Format::Toml | Format::Yaml => return GENERATED_MARKER_HASH,
+            Format::TmSnippet | Format::MarkdownIndex => return GENERATED_MARKER_XML,
+        }
+    }
+
+    /// 将 `render` 生成的文本解析回与 JSON 输出等价的值
+    pub fn parse(&self, text: &str) -> Result<serde_json::Value, String> {
+        match self {
+            Format::Json | Format::CodeSnippets | Format::Zed | Format::JsonFull => {
+                return serde_json::from_str(&strip_json_comments(text)).map_err(|e| e.to_string())
+            }
+            Format::Ndjson => {
+                let mut map = serde_json::Map::new();
+                for line in text.lines().skip(1).filter(|line| !line.trim().is_empty()) {
+                    let mut entry: serde_json::Map<String, serde_json::Value> =
+                        serde_json::from_str(line).map_err(|e| e.to_string())?;
+                    match entry.remove("identifier") {
+                        Some(serde_json::Value::String(identifier)) => {
+                            map.insert(identifier, serde_json::Value::Object(entry))
+                        }
+                        _ => return Err(String::from("expected an `identifier` on every line")),
+                    };
+                }
+                return Ok(serde_json::Value::Object(map));
+            }
+            Format::Toml => return toml::from_str(text).map_err(|e| e.to_string()),
+            Format::Yaml => return serde_yaml::from_str(text).map_err(|e| e.to_string()),
+            Format::TmSnippet => return tmsnippet::parse(text),
+            Format::MarkdownIndex => {
+                if !text.lines().any(|line| line == MARKDOWN_INDEX_TITLE) {
+                    return Err(format!("expected a `{}` line", MARKDOWN_INDEX_TITLE));
+                }
+                let text = snip::strip_html_comments(text);
+                let snips = snip::parse_snippets(&text, &ParseOptions::default())?;
+                return serde_json::to_value(snippet_map(&snips)).map_err(|e| e.to_string());
+            }
+        }
+    }
+}
+
+/// 生成文件的第一行，用于识别由 mdppet 生成的文件
+///
+/// VSCode 的代码片段文件允许 `//` 注释。
+pub const GENERATED_MARKER: &str = "// Generated by mdppet, do not edit by hand.";
+
+/// TOML 和 YAML 输出中的生成标记
+pub const GENERATED_MARKER_HASH: &str = "# Generated by mdppet, do not edit by hand.";
+
+/// `.tmSnippet` 输出中的生成标记，位于 XML 声明之后；也用于 Markdown 输出
+pub const GENERATED_MARKER_XML: &str = "<!-- Generated by mdppet, do not edit by hand. -->";
+
+/// 按照 `format` 序列化全部 Snippet，开头带有该格式的生成标记
+///
+/// `style` 只对 JSON 格式有效。
+pub fn render(snips: &[Snippet], format: Format, style: JsonStyle) -> String {
+    let text = render_body(snips, format, style);
+    if format == Format::TmSnippet {
+        return format!(
+            "{}\n{}\n{}",
+            tmsnippet::XML_DECLARATION,
+            format.marker(),
+            text
+        );
+    }
+    return format!("{}\n{}", format.marker(), text);
+}
+
+/// 只序列化标识符为 `identifier` 的 Snippet，不带生成标记：`--preview`
+pub fn render_preview(
+    snips: &[Snippet],
+    identifier: &str,
+    format: Format,
+    style: JsonStyle,
+) -> Option<String> {
+    let snip = snips.iter().find(|i| i.get_identifier() == identifier)?;
+    return Some(render_body(std::slice::from_ref(snip), format, style));
+}
+
+fn render_body(snips: &[Snippet], format: Format, style: JsonStyle) -> String {
+    match format {
+        Format::Json => return to_json(&snippet_map(snips), style),
+        Format::Toml => return toml::to_string(&snippet_map(snips)).unwrap(),
+        Format::Yaml => return serde_yaml::to_string(&snippet_map(snips)).unwrap(),
+        Format::CodeSnippets => {
+            let mut map: BTreeMap<&str, SnippetBody> = BTreeMap::new();
+            for i in snips.iter() {
+                let scope = i.scopes().collect::<Vec<&str>>().join(",");
+                map.insert(
+                    i.get_identifier().as_str(),
+                    i.get_snippetbody().with_scope(scope),
+                );
+            }
+            return to_json(&map, style);
+        }
+        Format::Zed => {
+            let mut map: BTreeMap<&str, ZedEntry> = BTreeMap::new();
+            for i in snips.iter() {
+                let entry = ZedEntry {
+                    prefix: i.prefixes(),
+                    body: i.get_body(),
+                    description: i.get_description(),
+                };
+                map.insert(i.get_identifier().as_str(), entry);
+            }
+            return to_json(&map, style);
+        }
+        Format::TmSnippet => {
+            let plists: Vec<String> = snips.iter().map(tmsnippet::render).collect();
+            return plists.join("\n");
+        }
+        Format::MarkdownIndex => return markdown_index(snips),
+        Format::JsonFull => {
+            let mut map: BTreeMap<&str, FullEntry> = BTreeMap::new();
+            for i in snips.iter() {
+                map.insert(i.get_identifier().as_str(), FullEntry::new(i, false));
+            }
+            return to_json(&map, style);
+        }
+        Format::Ndjson => {
+            let mut lines = String::new();
+            for i in snips.iter() {
+                lines.push_str(&serde_json::to_string(&FullEntry::new(i, true)).unwrap());
+                lines.push('\n');
+            }
+            return lines;
+        }
+    }
+}
+
+/// `--format json-full` 和 `ndjson` 中的一项：VSCode 的字段之外还有非空的元数据
+#[derive(Serialize)]
+struct FullEntry<'a> {
+    /// 只在 `ndjson` 中写出
+    #[serde(skip_serializing_if = "Option::is_none")]
+    identifier: Option<&'a str>,
+    #[serde(flatten)]
+    body: &'a SnippetBody,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: &'a BTreeMap<String, String>,
+}
+
+impl<'a> FullEntry<'a> {
+    fn new(snip: &'a Snippet, with_identifier: bool) -> Self {
+        FullEntry {
+            identifier: Some(snip.get_identifier().as_str()).filter(|_| with_identifier),
+            body: snip.get_snippetbody(),
+            metadata: snip.get_metadata(),
+        }
+    }
+}
+
+/// Markdown 输出的标题，位于目录之前
+const MARKDOWN_INDEX_TITLE: &str = "# Snippets";
+
+/// `--format markdown-index`：按标识符排序，开头是链接到各节的目录
+fn markdown_index(snips: &[Snippet]) -> String {
+    let mut sorted: Vec<&Snippet> = snips.iter().collect();
+    sorted.sort_by(|a, b| a.get_identifier().cmp(b.get_identifier()));
+    let options = snip::RenderOptions::default();
+    let sections: Vec<String> = sorted
+        .iter()
+        .map(|snip| snip.to_markdown(&options))
+        .collect();
+    let mut text = format!("{}\n\n", MARKDOWN_INDEX_TITLE);
+    for (snip, section) in sorted.iter().zip(sections.iter()) {
+        let heading = section.lines().next().unwrap_or("");
+        text.push_str(&format!(
+            "- [{}](#{})\n",
+            snip.get_identifier(),
+            heading_anchor(heading)
+        ));
+    }
+    for section in sections.iter() {
+        text.push('\n');
+        text.push_str(section);
+    }
+    return text;
+}
+
+/// GitHub 为 Markdown 标题生成的锚点：小写，空格换为 `-`，去掉其他标点
+fn heading_anchor(heading: &str) -> String {
+    let mut anchor = String::new();
+    for c in heading.trim_start_matches('#').trim().chars() {
+        match c {
+            ' ' => anchor.push('-'),
+            '-' | '_' => anchor.push(c),
+            c if c.is_alphanumeric() => anchor.extend(c.to_lowercase()),
+            _ => {}
+        }
+    }
+    return anchor;
+}
+
+/// Zed 代码片段文件中的一项，按语言分文件，因此没有 `scope` 字段
+#[derive(Serialize)]
+struct ZedEntry<'a> {
+    #[serde(serialize_with = "crate::snip::serialize_prefix")]
+    prefix: &'a [String],
+    body: &'a [String],
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    description: &'a [String],
+}
+
+/// Zed 的代码片段目录：`--install`
+///
+/// Windows 上为 `%APPDATA%\Zed\snippets`，其余平台为 `$XDG_CONFIG_HOME/zed/snippets`，
+/// 未设置 `XDG_CONFIG_HOME` 时为 `~/.config/zed/snippets`。`var` 用于读取环境变量。
+pub fn zed_snippets_dir<F: Fn(&str) -> Option<OsString>>(var: F, windows: bool) -> Option<PathBuf> {
+    if windows {
+        return var("APPDATA").map(|appdata| PathBuf::from(appdata).join("Zed").join("snippets"));
+    }
+    let config = match var("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(var("HOME")?).join(".config"),
+    };
+    return Some(config.join("zed").join("snippets"));
+}
+
+/// 目标文件已存在时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Clobber {
+    /// 只覆盖由 mdppet 生成的文件
+    Overwrite,
+    /// 拒绝覆盖：`--no-clobber`
+    Refuse,
+    /// 即使只读也覆盖：`--force`
+    Force,
+}
+
+/// 换行符风格：`--newline`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Newline {
+    Lf,
+    Crlf,
+    /// 跟随当前平台，Windows 上为 CRLF，其余为 LF
+    Native,
+}
+
+impl Newline {
+    /// `--newline` 可以接受的取值
+    pub const NAMES: &'static [&'static str] = &["lf", "crlf", "native"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "lf" => return Some(Newline::Lf),
+            "crlf" => return Some(Newline::Crlf),
+            "native" => return Some(Newline::Native),
+            _ => return None,
+        }
+    }
+
+    /// 将 `text` 中的换行统一为当前风格
+    pub fn normalize(&self, text: &str) -> String {
+        let lf = text.replace("\r\n", "\n");
+        let crlf = match self {
+            Newline::Lf => false,
+            Newline::Crlf => true,
+            Newline::Native => cfg!(windows),
+        };
+        if crlf {
+            return lf.replace('\n', "\r\n");
+        }
+        return lf;
+    }
+}
+
+/// 覆盖前备份原文件的方式：`--backup-style`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BackupStyle {
+    /// `out.json.bak`，每次覆盖上一次的备份
+    Simple,
+    /// `out.json.<时间戳>.bak`
+    Timestamped,
+}
+
+impl BackupStyle {
+    /// `--backup-style` 可以接受的取值
+    pub const NAMES: &'static [&'static str] = &["simple", "timestamped"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "simple" => return Some(BackupStyle::Simple),
+            "timestamped" => return Some(BackupStyle::Timestamped),
+            _ => return None,
+        }
+    }
+
+    /// `dest` 的备份文件路径
+    pub fn backup_path(&self, dest: &Path) -> PathBuf {
+        let mut name = dest.as_os_str().to_os_string();
+        if let BackupStyle::Timestamped = self {
+            name.push(format!(".{}", timestamp(SystemTime::now())));
+        }
+        name.push(".bak");
+        return PathBuf::from(name);
+    }
+}
+
+/// 将时间格式化为 `YYYYMMDDTHHMMSS`（UTC）
+fn timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let rem = secs % 86400;
+    let (year, month, day) = civil_date(secs / 86400);
+    return format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    );
+}
+
+/// 将时间格式化为 `YYYY-MM-DD`（UTC）
+pub fn date(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let (year, month, day) = civil_date(secs / 86400);
+    return format!("{:04}-{:02}-{:02}", year, month, day);
+}
+
+/// 由 1970-01-01 起的天数推算公历年月日
+fn civil_date(days: u64) -> (i64, i64, i64) {
+    // 参见 http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    return (year, month, day);
+}
+
+/// JSON 的缩进：`--json-indent`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Indent {
+    /// 每层若干个空格，默认为 2
+    Spaces(usize),
+    Tab,
+}
+
+impl Indent {
+    /// 解析 `--json-indent`：1 到 8 之间的空格数，或 `tab`
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        if name == "tab" {
+            return Ok(Indent::Tab);
+        }
+        match name.parse::<usize>() {
+            Ok(spaces) if (1..=8).contains(&spaces) => return Ok(Indent::Spaces(spaces)),
+            _ => {
+                return Err(format!(
+                    "invalid indent `{}`, expected a number of spaces from 1 to 8 or `tab`",
+                    name
+                ))
+            }
+        }
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        match self {
+            Indent::Spaces(spaces) => return vec![b' '; spaces],
+            Indent::Tab => return vec![b'\t'],
+        }
+    }
+}
+
+/// JSON 输出的写法，`to_json` 按它缩进和转义
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JsonStyle {
+    /// 所有非 ASCII 字符都写作 `\uXXXX`：`--ascii-output`
+    pub ascii: bool,
+    pub indent: Indent,
+}
+
+impl Default for JsonStyle {
+    fn default() -> Self {
+        JsonStyle {
+            ascii: false,
+            indent: Indent::Spaces(2),
+        }
+    }
+}
+
+impl JsonStyle {
+    /// 从已有的 JSON 文件中推断写法：`--match-existing-style`
+    ///
+    /// 缩进取第一个缩进的行；含有非 ASCII 字符时不转义，
+    /// 只用 `\uXXXX` 写出非 ASCII 字符时转义。无法判断的部分沿用 `fallback`。
+    pub fn sniff(text: &str, fallback: JsonStyle) -> JsonStyle {
+        let mut style = fallback;
+        let indented = text
+            .lines()
+            .find(|line| line.starts_with([' ', '\t']) && !line.trim().is_empty());
+        if let Some(line) = indented {
+            let spaces = line.len() - line.trim_start_matches(' ').len();
+            style.indent = match spaces {
+                0 => Indent::Tab,
+                spaces => Indent::Spaces(spaces),
+            };
+        }
+        if !text.is_ascii() {
+            style.ascii = false;
+        } else if has_non_ascii_escape(text) {
+            style.ascii = true;
+        }
+        return style;
+    }
+}
+
+/// 是否含有表示非 ASCII 字符的 `\uXXXX` 转义
+fn has_non_ascii_escape(text: &str) -> bool {
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            continue;
+        }
+        if chars.next() != Some('u') {
+            continue;
+        }
+        let hex: String = chars.by_ref().take(4).collect();
+        if u32::from_str_radix(&hex, 16).is_ok_and(|unit| unit >= 0x80) {
+            return true;
+        }
+    }
+    return false;
+}
+
+/// 写出文件时的选项，所有输出格式共用
+#[derive(Debug, Clone)]
+pub struct WriteOptions {
+    pub clobber: Clobber,
+    pub newline: Newline,
+    /// 覆盖前是否备份原文件：`--backup`
+    pub backup: Option<BackupStyle>,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            clobber: Clobber::Overwrite,
+            newline: Newline::Lf,
+            backup: None,
+        }
+    }
+}
+
+/// 去掉 JSONC 中的整行 `//` 注释
+///
+/// JSON 字符串不能跨行，因此以 `//` 开头的行一定是注释。
+pub fn strip_json_comments(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        if !line.trim_start().starts_with("//") {
+            stripped.push_str(line);
+        }
+    }
+    return stripped;
+}
+
+/// 检查已存在的目标文件是否可以直接覆盖
+///
+/// 只有带有生成标记、且能按对应格式解析为代码片段表的文件才会被视为 mdppet 的输出，
+/// 否则返回不能覆盖的原因。
+fn check_generated(existing: &str) -> Result<(), &'static str> {
+    let first_line = existing
+        .trim_start()
+        .lines()
+        .find(|line| !line.starts_with("<?xml"))
+        .unwrap_or("");
+    let (candidates, invalid): (&[Format], _) = if first_line.starts_with(GENERATED_MARKER) {
+        (
+            &[Format::Json, Format::Ndjson],
+            "it is not valid snippet JSON",
+        )
+    } else if first_line.starts_with(GENERATED_MARKER_XML) && existing.starts_with("<?xml") {
+        (&[Format::TmSnippet], "it is not a valid tmSnippet plist")
+    } else if first_line.starts_with(GENERATED_MARKER_XML) {
+        (&[Format::MarkdownIndex], "it is not a valid snippet index")
+    } else if first_line.starts_with(GENERATED_MARKER_HASH) {
+        (
+            &[Format::Toml, Format::Yaml],
+            "it is not valid snippet TOML or YAML",
+        )
+    } else {
+        return Err("it was not generated by mdppet");
+    };
+    let is_snippet_map = |value: serde_json::Value| match value.as_object() {
+        Some(map) => map.values().all(|entry| entry.is_object()),
+        None => false,
+    };
+    if candidates
+        .iter()
+        .filter_map(|format| format.parse(existing).ok())
+        .any(is_snippet_map)
+    {
+        return Ok(());
+    }
+    return Err(invalid);
+}
+
+/// 将生成的文本写入 `dest`
+///
+/// 所有生成的文件都经由此处写出：写入前根据 `clobber` 检查目标文件是否已存在，
+/// 并按 `newline` 统一换行符。已存在的文件会先被读取检查，确认可以覆盖后才会截断；
+/// 指定了 `backup` 时原文件会被改名为备份文件。
+pub fn write_file(dest: &Path, text: &str, options: &WriteOptions) -> Result<(), MdppetError> {
+    let io_err = |e| MdppetError::Io(dest.to_path_buf(), e);
+    release_existing(dest, options)?;
+    let mut ostream = fs::File::create(dest).map_err(io_err)?;
+    let text = options.newline.normalize(text);
+    ostream.write_all(text.as_bytes()).map_err(io_err)?;
+    return Ok(());
+}
+
+/// 删除不再需要的旧输出，如拆分后多出的部分；检查和备份的规则与 `write_file` 相同
+pub fn remove_output(dest: &Path, options: &WriteOptions) -> Result<(), MdppetError> {
+    if release_existing(dest, options)? {
+        fs::remove_file(dest).map_err(|e| MdppetError::Io(dest.to_path_buf(), e))?;
+    }
+    return Ok(());
+}
+
+/// 按 `clobber` 检查已存在的 `dest` 能否被覆盖，并按 `backup` 备份，返回 `dest` 是否仍然存在
+fn release_existing(dest: &Path, options: &WriteOptions) -> Result<bool, MdppetError> {
+    let io_err = |e| MdppetError::Io(dest.to_path_buf(), e);
+    if let Ok(meta) = fs::metadata(dest) {
+        match options.clobber {
+            Clobber::Refuse => return Err(MdppetError::DestinationExists(dest.to_path_buf())),
+            Clobber::Overwrite => {
+                let existing = fs::read(dest).map_err(io_err)?;
+                if let Err(reason) = check_generated(&String::from_utf8_lossy(&existing)) {
+                    return Err(MdppetError::ForeignDestination(dest.to_path_buf(), reason));
+                }
+            }
+            Clobber::Force => {
+                let mut permissions = meta.permissions();
+                if permissions.readonly() {
+                    #[allow(clippy::permissions_set_readonly_false)]
+                    permissions.set_readonly(false);
+                    fs::set_permissions(dest, permissions).map_err(io_err)?;
+                }
+            }
+        }
+        if let Some(style) = options.backup {
+            let backup = style.backup_path(dest);
+            if backup.exists() {
+                fs::remove_file(&backup).map_err(|e| MdppetError::Io(backup.clone(), e))?;
+            }
+            fs::rename(dest, &backup).map_err(|e| MdppetError::Io(backup.clone(), e))?;
+            return Ok(false);
+        }
+        return Ok(true);
+    }
+    return Ok(false);
+}
+
+/// 单个输出文件的上限：`--max-snippets-per-file` 和 `--max-file-size`，超过时拆分为编号的多个文件
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PartLimits {
+    pub snippets: Option<usize>,
+    /// 写出的字节数，按 `--newline` 统一换行符之后计算
+    pub bytes: Option<usize>,
+}
+
+impl PartLimits {
+    pub fn is_set(&self) -> bool {
+        return self.snippets.is_some() || self.bytes.is_some();
+    }
+}
+
+/// 将 `snips` 按标识符排序后依次填入各部分，每部分都不超过 `limits`
+///
+/// 结果只取决于 Snippet 本身，重新生成时不会在各部分之间移动。本身就超过 `limits.bytes`
+/// 的 Snippet 独占一个部分。只支持 JSON 和 `.code-snippets`：各条目的序列化互不影响，
+/// 一个部分的大小等于其中每个 Snippet 单独序列化的大小之和，再加上每增加一项的固定差值。
+pub fn partition(
+    snips: &[Snippet],
+    format: Format,
+    style: JsonStyle,
+    newline: Newline,
+    limits: PartLimits,
+) -> Vec<Vec<Snippet>> {
+    let mut sorted: Vec<&Snippet> = snips.iter().collect();
+    sorted.sort_by(|a, b| a.get_identifier().cmp(b.get_identifier()));
+    let size = |snips: &[Snippet]| newline.normalize(&render(snips, format, style)).len();
+    let sizes: Vec<usize> = sorted
+        .iter()
+        .map(|snip| size(std::slice::from_ref(*snip)))
+        .collect();
+    // 两项合并序列化与分别序列化的字节数之差：多了分隔符，少了一份生成标记和括号
+    let joint: isize = match sorted.get(..2) {
+        Some(&[a, b]) => {
+            size(&[a.clone(), b.clone()]) as isize - sizes[0] as isize - sizes[1] as isize
+        }
+        _ => 0,
+    };
+    let mut parts: Vec<Vec<Snippet>> = Vec::new();
+    let mut current: Vec<Snippet> = Vec::new();
+    let mut bytes: isize = 0;
+    for (snip, &snip_size) in sorted.into_iter().zip(sizes.iter()) {
+        let grown = bytes + joint + snip_size as isize;
+        let full = limits.snippets.is_some_and(|max| current.len() >= max)
+            || limits.bytes.is_some_and(|max| grown > max as isize);
+        if !current.is_empty() && full {
+            parts.push(std::mem::take(&mut current));
+        }
+        bytes = if current.is_empty() {
+            snip_size as isize
+        } else {
+            grown
+        };
+        current.push(snip.clone());
+    }
+    parts.push(current);
+    return parts;
+}
+
+/// 拆分后第 `index` 个部分（从 1 开始）的路径：`out.code-snippets` 的第一部分为 `out.1.code-snippets`
+pub fn part_path(dest: &Path, index: usize) -> PathBuf {
+    let stem = dest.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match dest.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, index, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, index),
+    };
+    return dest.with_file_name(name);
+}
+
+/// 按改名的结果更新已生成的输出：`mdppet rename -o`
+///
+/// 只支持 JSON 和 `.code-snippets`，重新序列化后与直接生成的结果一致；
+/// 缩进和转义沿用原文件的写法，见 `JsonStyle::sniff`。
+pub fn rename_output(
+    text: &str,
+    format: Format,
+    identifiers: &BTreeMap<String, String>,
+    prefixes: &BTreeMap<String, String>,
+) -> Result<String, String> {
+    if !matches!(format, Format::Json | Format::CodeSnippets) {
+        return Err(String::from(
+            "only json and code-snippets output can be updated",
+        ));
+    }
+    let snippets: BTreeMap<String, SnippetBody> =
+        serde_json::from_str(&strip_json_comments(text)).map_err(|e| e.to_string())?;
+    let mut renamed: BTreeMap<String, SnippetBody> = BTreeMap::new();
+    for (identifier, mut body) in snippets {
+        body.rename_prefix(prefixes);
+        let identifier = identifiers.get(&identifier).cloned().unwrap_or(identifier);
+        renamed.insert(identifier, body);
+    }
+    return Ok(format!(
+        "{}\n{}",
+        format.marker(),
+        to_json(&renamed, JsonStyle::sniff(text, JsonStyle::default()))
+    ));
+}
+
+/// 概括已有输出 `existing` 与重新生成的 `rendered` 之间的差异：`--check`
+///
+/// 按标识符列出新增（`+`）、删除（`-`）和内容改变（`~`）的 Snippet；
+/// 解析出的内容相同、只有写法不同时给出一行说明。
+pub fn diff_summary(existing: &str, rendered: &str, format: Format) -> Vec<String> {
+    let old = match format.parse(existing) {
+        Ok(serde_json::Value::Object(old)) => old,
+        Ok(_) => return vec![String::from("the existing file is not a snippet table")],
+        Err(e) => return vec![format!("the existing file cannot be parsed: {}", e)],
+    };
+    let new = match format.parse(rendered) {
+        Ok(serde_json::Value::Object(new)) => new,
+        _ => return vec![String::from("the regenerated output cannot be parsed")],
+    };
+    let mut summary: Vec<String> = Vec::new();
+    for (identifier, value) in new.iter() {
+        match old.get(identifier) {
+            None => summary.push(format!("+ {}", identifier)),
+            Some(old_value) if old_value != value => summary.push(format!("~ {}", identifier)),
+            Some(_) => {}
+        }
+    }
+    for identifier in old.keys() {
+        if !new.contains_key(identifier) {
+            summary.push(format!("- {}", identifier));
+        }
+    }
+    summary.sort_by(|a, b| a[2..].cmp(&b[2..]));
+    if summary.is_empty() {
+        summary.push(String::from(
+            "the snippets are the same but the file is laid out differently",
+        ));
+    }
+    return summary;
+}
+
+/// 以表格列出 Snippet 的标识符、前缀、作用域和补全体行数：`--list`
+///
+/// 各列按最长内容的显示宽度对齐，中文等全角字符占两列。
+pub fn render_list(snips: &[Snippet]) -> String {
+    let header = ["IDENTIFIER", "PREFIX", "SCOPE", "LINES"];
+    let mut rows: Vec<[String; 4]> = vec![header.map(String::from)];
+    for i in snips.iter() {
+        rows.push([
+            i.get_identifier().clone(),
+            i.get_prefix(),
+            i.get_scope().clone(),
+            i.get_body().len().to_string(),
+        ]);
+    }
+    let mut widths = [0usize; 4];
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.width());
+        }
+    }
+
+    let mut table = String::new();
+    for row in rows.iter() {
+        let mut line = String::new();
+        for (column, cell) in row.iter().enumerate() {
+            line.push_str(cell);
+            let padding = widths[column] - cell.width();
+            line.push_str(&" ".repeat(padding + 2));
+        }
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    return table;
+}
+
+/// 以标识符为键，整理出用于序列化的 Snippet 表
+pub fn snippet_map(snips: &[Snippet]) -> BTreeMap<&str, &SnippetBody> {
+    let mut map: BTreeMap<&str, &SnippetBody> = BTreeMap::new();
+    for i in snips.iter() {
+        map.insert(i.get_identifier().as_str(), i.get_snippetbody());
+    }
+    return map;
+}
+
+/// 序列化为带缩进的 JSON
+///
+/// 默认直接输出 UTF-8 字符，只转义 JSON 要求转义的字符；
+/// `style.ascii` 为真时（`--ascii-output`）所有非 ASCII 字符都写作 `\uXXXX`。
+pub fn to_json<T: Serialize>(value: &T, style: JsonStyle) -> String {
+    let mut buffer: Vec<u8> = Vec::new();
+    let indent = style.indent.to_bytes();
+    let formatter = SnippetFormatter {
+        pretty: PrettyFormatter::with_indent(&indent),
+        ascii: style.ascii,
+    };
+    let mut serializer = Serializer::with_formatter(&mut buffer, formatter);
+    value.serialize(&mut serializer).unwrap();
+    return String::from_utf8(buffer).unwrap();
+}
+
+/// 在 `PrettyFormatter` 的基础上控制字符串的转义方式
+struct SnippetFormatter<'a> {
+    pretty: PrettyFormatter<'a>,
+    ascii: bool,
+}
+
+impl<'a> Formatter for SnippetFormatter<'a> {
+    fn write_string_fragment<W: ?Sized + io::Write>(
+        &mut self,
+        writer: &mut W,
+        fragment: &str,
+    ) -> io::Result<()> {
+        if !self.ascii {
+            return writer.write_all(fragment.as_bytes());
+        }
+        let mut units = [0u16; 2];
+        for c in fragment.chars() {
+            if c.is_ascii() {
+                writer.write_all(&[c as u8])?;
+            } else {
+                for unit in c.encode_utf16(&mut units).iter() {
+                    write!(writer, "\\u{:04x}", unit)?;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    fn begin_array<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        return self.pretty.begin_array(writer);
+    }
+    fn end_array<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        return self.pretty.end_array(writer);
+    }
+    fn begin_array_value<W: ?Sized + io::Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        return self.pretty.begin_array_value(writer, first);
+    }
+    fn end_array_value<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        return self.pretty.end_array_value(writer);
+    }
+    fn begin_object<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        return self.pretty.begin_object(writer);
+    }
+    fn end_object<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        return self.pretty.end_object(writer);
+    }
+    fn begin_object_key<W: ?Sized + io::Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        return self.pretty.begin_object_key(writer, first);
+    }
+    fn begin_object_value<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        return self.pretty.begin_object_value(writer);
+    }
+    fn end_object_value<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        return self.pretty.end_object_value(writer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snip::get_snippet_segments;
+    use std::process;
+    use std::time::Duration;
+
+    #[test]
+    fn test_unicode_round_trip() {
+        let text = fs::read_to_string("tests/test_markdown_unicode.md").unwrap();
+        let snips: Vec<Snippet> = get_snippet_segments(&text)
+            .iter()
+            .map(|&md_text| Snippet::from_markdown(md_text))
+            .collect();
+        assert_eq!(snips.len(), 2);
+        assert_eq!(snips[0].get_identifier().as_str(), "函数");
+        assert_eq!(snips[1].get_prefix().as_str(), "😀");
+        assert_eq!(
+            snips[1].get_description(),
+            &vec![String::from("Ｅｍｏｊｉ 👨\u{200d}👩\u{200d}👧 family")]
+        );
+
+        let map = snippet_map(&snips);
+        for &ascii in [false, true].iter() {
+            let json = to_json(
+                &map,
+                JsonStyle {
+                    ascii,
+                    ..JsonStyle::default()
+                },
+            );
+            let parsed: BTreeMap<String, SnippetBody> = serde_json::from_str(&json).unwrap();
+            assert_eq!(
+                serde_json::to_string(&parsed).unwrap(),
+                serde_json::to_string(&map).unwrap()
+            );
+        }
+    }
+    #[test]
+    fn test_toml_yaml_round_trip() {
+        let text = fs::read_to_string("tests/test_markdown_unicode.md").unwrap()
+            + &fs::read_to_string("tests/test_markdown.2.md").unwrap();
+        let snips: Vec<Snippet> = get_snippet_segments(&text)
+            .iter()
+            .map(|&md_text| Snippet::from_markdown(md_text))
+            .collect();
+        let original: BTreeMap<String, SnippetBody> = snips
+            .iter()
+            .map(|i| (i.get_identifier().clone(), i.get_snippetbody().clone()))
+            .collect();
+
+        for &format in [Format::Toml, Format::Yaml].iter() {
+            let rendered = render(&snips, format, JsonStyle::default());
+            assert!(rendered.starts_with(GENERATED_MARKER_HASH));
+            let parsed: BTreeMap<String, SnippetBody> = match format {
+                Format::Toml => toml::from_str(&rendered).unwrap(),
+                _ => serde_yaml::from_str(&rendered).unwrap(),
+            };
+            assert_eq!(parsed, original, "{:?} 往返后内容不一致", format);
+            assert_eq!(
+                format.parse(&rendered).unwrap(),
+                Format::Json
+                    .parse(&render(&snips, Format::Json, JsonStyle::default()))
+                    .unwrap()
+            );
+            assert_eq!(check_generated(&rendered), Ok(()));
+        }
+    }
+    #[test]
+    fn test_render_preview() {
+        let text = fs::read_to_string("tests/test_markdown.2.md").unwrap();
+        let snips: Vec<Snippet> = get_snippet_segments(&text)
+            .iter()
+            .map(|&md_text| Snippet::from_markdown(md_text))
+            .collect();
+
+        let preview = render_preview(&snips, "abc", Format::Json, JsonStyle::default()).unwrap();
+        let parsed: BTreeMap<String, SnippetBody> = serde_json::from_str(&preview).unwrap();
+        assert_eq!(parsed.len(), 1);
+        let abc = snips.iter().find(|i| i.get_identifier() == "abc").unwrap();
+        assert_eq!(&parsed["abc"], abc.get_snippetbody());
+        assert_eq!(
+            render_preview(&snips, "nope", Format::Json, JsonStyle::default()),
+            None
+        );
+    }
+    #[test]
+    fn test_markdown_index() {
+        let text = fs::read_to_string("tests/test_markdown.2.md").unwrap();
+        let snips: Vec<Snippet> = get_snippet_segments(&text)
+            .iter()
+            .map(|&md_text| Snippet::from_markdown(md_text))
+            .collect();
+        let rendered = render(&snips, Format::MarkdownIndex, JsonStyle::default());
+        assert!(rendered.starts_with(GENERATED_MARKER_XML));
+        let (toc, sections) = rendered.split_once("\n\n# ").unwrap();
+        for snip in snips.iter() {
+            let id = snip.get_identifier();
+            assert!(toc.contains(&format!("- [{}](#", id)), "目录中缺少 {}", id);
+            assert!(sections.contains(&format!("{}/{}/", id, snip.get_prefix())));
+        }
+        assert_eq!(check_generated(&rendered), Ok(()));
+        assert_eq!(
+            Format::MarkdownIndex.parse(&rendered).unwrap(),
+            Format::Json
+                .parse(&render(&snips, Format::Json, JsonStyle::default()))
+                .unwrap()
+        );
+        assert_eq!(
+            check_generated(&rendered.replace(MARKDOWN_INDEX_TITLE, "# Notes")),
+            Err("it is not a valid snippet index")
+        );
+        assert_eq!(heading_anchor("# fn/fn,f/rust @dedent"), "fnfnfrust-dedent");
+    }
+    #[test]
+    fn test_tmsnippet_generated() {
+        let snips = vec![Snippet::from_text("a", "b", "rust", "x < y", "").unwrap()];
+        let rendered = render(&snips, Format::TmSnippet, JsonStyle::default());
+        assert!(rendered.starts_with("<?xml"));
+        assert_eq!(check_generated(&rendered), Ok(()));
+        assert_eq!(
+            check_generated(&rendered.replace("<plist", "<list")),
+            Err("it is not a valid tmSnippet plist")
+        );
+    }
+    #[test]
+    fn test_full_formats_metadata() {
+        let text = "# a/b/rust\n[author=me]\n\n```rust\nx\n```\n\n# c/d/rust\n\n```rust\ny\n```\n";
+        let snips = snip::parse_snippets(text, &ParseOptions::default()).unwrap();
+        let full = render(&snips, Format::JsonFull, JsonStyle::default());
+        let value: serde_json::Value = Format::JsonFull.parse(&full).unwrap();
+        assert_eq!(value["a"]["metadata"]["author"], "me");
+        assert_eq!(value["a"]["scope"], "rust");
+        assert!(value["c"].get("metadata").is_none());
+        let ndjson = render(&snips, Format::Ndjson, JsonStyle::default());
+        assert_eq!(
+            ndjson,
+            format!(
+                "{}\n{}\n{}\n",
+                GENERATED_MARKER,
+                r#"{"identifier":"a","prefix":"b","scope":"rust","body":["x"],"metadata":{"author":"me"}}"#,
+                r#"{"identifier":"c","prefix":"d","scope":"rust","body":["y"]}"#
+            )
+        );
+        assert_eq!(Format::Ndjson.parse(&ndjson).unwrap(), value);
+        assert_eq!(check_generated(&ndjson), Ok(()));
+        assert_eq!(check_generated(&full), Ok(()));
+    }
+    #[test]
+    fn test_zed_snippets_dir() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| OsString::from(value))
+            }
+        };
+        assert_eq!(
+            zed_snippets_dir(env(&[("HOME", "/home/me")]), false),
+            Some(PathBuf::from("/home/me/.config/zed/snippets"))
+        );
+        assert_eq!(
+            zed_snippets_dir(
+                env(&[("HOME", "/home/me"), ("XDG_CONFIG_HOME", "/cfg")]),
+                false
+            ),
+            Some(PathBuf::from("/cfg/zed/snippets"))
+        );
+        assert_eq!(
+            zed_snippets_dir(env(&[("APPDATA", "C:/Users/me/AppData")]), true),
+            Some(
+                PathBuf::from("C:/Users/me/AppData")
+                    .join("Zed")
+                    .join("snippets")
+            )
+        );
+        assert_eq!(zed_snippets_dir(env(&[]), false), None);
+    }
+    #[test]
+    fn test_code_snippets_scope() {
+        let text = fs::read_to_string("tests/test_markdown.2.md").unwrap();
+        let snips: Vec<Snippet> = get_snippet_segments(&text)
+            .iter()
+            .map(|&md_text| Snippet::from_markdown(md_text))
+            .collect();
+
+        let json = render(&snips, Format::CodeSnippets, JsonStyle::default());
+        assert!(json.starts_with(GENERATED_MARKER));
+        let parsed: BTreeMap<String, serde_json::Value> =
+            serde_json::from_str(&strip_json_comments(&json)).unwrap();
+        assert_eq!(parsed.len(), 3);
+        for (id, entry) in parsed.iter() {
+            assert!(entry["scope"].is_string(), "{} 缺少 scope 字段", id);
+        }
+        assert_eq!(parsed["abc"]["scope"], "python,lua");
+        assert_eq!(parsed["a"]["scope"], "c");
+    }
+    #[test]
+    fn test_diff_summary() {
+        let old = vec![
+            Snippet::from_text("a", "a", "rust", "a", "").unwrap(),
+            Snippet::from_text("b", "b", "rust", "b", "").unwrap(),
+        ];
+        let new = vec![
+            Snippet::from_text("b", "b", "rust", "bb", "").unwrap(),
+            Snippet::from_text("c", "c", "rust", "c", "").unwrap(),
+        ];
+        let existing = render(&old, Format::Json, JsonStyle::default());
+        let rendered = render(&new, Format::Json, JsonStyle::default());
+        assert_eq!(
+            diff_summary(&existing, &rendered, Format::Json),
+            vec!["- a", "~ b", "+ c"]
+        );
+        let ascii = render(
+            &old,
+            Format::Json,
+            JsonStyle {
+                ascii: true,
+                ..JsonStyle::default()
+            },
+        );
+        assert_eq!(
+            diff_summary(&ascii.replace("    ", "  "), &ascii, Format::Json).len(),
+            1
+        );
+        assert!(diff_summary("{", &rendered, Format::Json)[0].contains("cannot be parsed"));
+    }
+    #[test]
+    fn test_rename_output() {
+        let snips = vec![
+            Snippet::from_text("a", "x", "rust", "a", "中文").unwrap(),
+            Snippet::from_text("b", "y", "rust", "b", "").unwrap(),
+        ];
+        let renamed_snips = vec![
+            Snippet::from_text("c", "z", "rust", "a", "中文").unwrap(),
+            Snippet::from_text("b", "y", "rust", "b", "").unwrap(),
+        ];
+        let identifiers = BTreeMap::from([(String::from("a"), String::from("c"))]);
+        let prefixes = BTreeMap::from([(String::from("x"), String::from("z"))]);
+        for (format, ascii) in [(Format::Json, false), (Format::CodeSnippets, true)] {
+            let style = JsonStyle {
+                ascii,
+                ..JsonStyle::default()
+            };
+            let text = render(&snips, format, style);
+            assert_eq!(
+                rename_output(&text, format, &identifiers, &prefixes).unwrap(),
+                render(&renamed_snips, format, style)
+            );
+        }
+        assert!(rename_output("", Format::Toml, &identifiers, &prefixes).is_err());
+    }
+    #[test]
+    fn test_render_list() {
+        let text = fs::read_to_string("tests/test_markdown.2.md").unwrap();
+        let snips: Vec<Snippet> = get_snippet_segments(&text)
+            .iter()
+            .map(|&md_text| Snippet::from_markdown(md_text))
+            .collect();
+
+        let table = render_list(&snips);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "IDENTIFIER  PREFIX  SCOPE       LINES");
+        assert_eq!(lines[1], "a           b       c           1");
+        assert_eq!(lines[3], "abc         123     python,lua  2");
+        for id in ["a", "e", "abc"].iter() {
+            assert!(lines
+                .iter()
+                .any(|line| line.split_whitespace().next() == Some(id)));
+        }
+    }
+    #[test]
+    fn test_json_escaping() {
+        let text = fs::read_to_string("tests/test_markdown_unicode.md").unwrap();
+        let snips: Vec<Snippet> = get_snippet_segments(&text)
+            .iter()
+            .map(|&md_text| Snippet::from_markdown(md_text))
+            .collect();
+        let map = snippet_map(&snips);
+
+        let json = to_json(&map, JsonStyle::default());
+        assert!(json.contains("\"函数\": {"));
+        assert!(json.contains("\"fn 函数() {\""));
+        assert!(json.contains("🎉 cafe\u{301} "));
+        assert!(!json.contains("\\u"));
+
+        let json = to_json(
+            &map,
+            JsonStyle {
+                ascii: true,
+                ..JsonStyle::default()
+            },
+        );
+        assert!(json.is_ascii());
+        assert!(json.contains("\"\\u51fd\\u6570\": {"));
+        // 😀 位于辅助平面，需要写成代理对
+        assert!(json.contains("\"prefix\": \"\\ud83d\\ude00\""));
+        assert!(json.contains("cafe\\u0301"));
+    }
+    #[test]
+    fn test_json_indent() {
+        let text = fs::read_to_string("tests/test_markdown.2.md").unwrap();
+        let snips: Vec<Snippet> = get_snippet_segments(&text)
+            .iter()
+            .map(|&md_text| Snippet::from_markdown(md_text))
+            .collect();
+        let indented = |indent: &str| {
+            let style = JsonStyle {
+                indent: Indent::from_name(indent).unwrap(),
+                ..JsonStyle::default()
+            };
+            return render(&snips, Format::Json, style);
+        };
+        let two = indented("2");
+        let four = indented("4");
+        assert_eq!(two, render(&snips, Format::Json, JsonStyle::default()));
+        assert_ne!(two, four);
+        assert_eq!(two.lines().count(), four.lines().count());
+        for (a, b) in two.lines().zip(four.lines()) {
+            let depth = a.len() - a.trim_start().len();
+            assert_eq!(depth % 2, 0);
+            assert_eq!(b, format!("{}{}", " ".repeat(depth * 2), a.trim_start()));
+        }
+        assert_eq!(Format::Json.parse(&two), Format::Json.parse(&four));
+        assert!(indented("tab").contains("\n\t\""));
+        assert!(Indent::from_name("0").is_err());
+        assert!(Indent::from_name("two").is_err());
+    }
+    #[test]
+    fn test_json_style_sniff() {
+        let fallback = JsonStyle::default();
+        let style = JsonStyle::sniff("{\n\t\"\\u51fd\": {}\n}", fallback);
+        assert_eq!(
+            style,
+            JsonStyle {
+                ascii: true,
+                indent: Indent::Tab
+            }
+        );
+        let style = JsonStyle::sniff("{\n    \"函数\": {}\n}", style);
+        assert_eq!(
+            style,
+            JsonStyle {
+                ascii: false,
+                indent: Indent::Spaces(4)
+            }
+        );
+        assert_eq!(JsonStyle::sniff("{\"\\u0009\": 1}", fallback), fallback);
+        assert_eq!(JsonStyle::sniff("", style), style);
+    }
+    #[test]
+    fn test_no_clobber() {
+        let dest = temp_path("no-clobber.json");
+        fs::write(&dest, "old").unwrap();
+
+        let options = WriteOptions {
+            clobber: Clobber::Refuse,
+            ..WriteOptions::default()
+        };
+        let result = write_file(&dest, "new", &options);
+        match result {
+            Err(MdppetError::DestinationExists(path)) => assert_eq!(path, dest),
+            other => panic!("应当拒绝覆盖，实际为 {:?}", other),
+        }
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "old");
+        fs::remove_file(&dest).unwrap();
+    }
+    #[test]
+    fn test_force_overwrite_readonly() {
+        let dest = temp_path("force.json");
+        fs::write(&dest, "old").unwrap();
+        let mut permissions = fs::metadata(&dest).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&dest, permissions).unwrap();
+
+        let options = WriteOptions {
+            clobber: Clobber::Force,
+            ..WriteOptions::default()
+        };
+        write_file(&dest, "new", &options).unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "new");
+        assert!(!fs::metadata(&dest).unwrap().permissions().readonly());
+        fs::remove_file(&dest).unwrap();
+    }
+    #[test]
+    fn test_overwrite_generated() {
+        let dest = temp_path("generated.json");
+        fs::write(&dest, format!("{}\n{{\"a\": {{}}}}", GENERATED_MARKER)).unwrap();
+
+        write_file(&dest, "new", &WriteOptions::default()).unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "new");
+        fs::remove_file(&dest).unwrap();
+    }
+    #[test]
+    fn test_refuse_foreign_json() {
+        let dest = temp_path("foreign.json");
+        fs::write(&dest, "{\"name\": \"package\", \"version\": \"1.0.0\"}").unwrap();
+
+        match write_file(&dest, "new", &WriteOptions::default()) {
+            Err(e @ MdppetError::ForeignDestination(..)) => {
+                assert!(e.to_string().contains("not generated by mdppet"))
+            }
+            other => panic!("应当拒绝覆盖，实际为 {:?}", other),
+        }
+        assert!(fs::read_to_string(&dest).unwrap().contains("package"));
+
+        let options = WriteOptions {
+            clobber: Clobber::Force,
+            ..WriteOptions::default()
+        };
+        write_file(&dest, "new", &options).unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "new");
+        fs::remove_file(&dest).unwrap();
+    }
+    #[test]
+    fn test_refuse_garbage() {
+        let dest = temp_path("garbage.json");
+        fs::write(&dest, format!("{}\n<<<garbage", GENERATED_MARKER)).unwrap();
+
+        match write_file(&dest, "new", &WriteOptions::default()) {
+            Err(e @ MdppetError::ForeignDestination(..)) => {
+                assert!(e.to_string().contains("not valid snippet JSON"))
+            }
+            other => panic!("应当拒绝覆盖，实际为 {:?}", other),
+        }
+        assert!(fs::read_to_string(&dest).unwrap().ends_with("<<<garbage"));
+        fs::remove_file(&dest).unwrap();
+    }
+    #[test]
+    fn test_backup() {
+        let dest = temp_path("backup.json");
+        let old = format!("{}\n{{}}", GENERATED_MARKER);
+        fs::write(&dest, &old).unwrap();
+        let options = WriteOptions {
+            backup: Some(BackupStyle::Simple),
+            ..WriteOptions::default()
+        };
+
+        let fresh = format!("{}\n{{\"a\": {{}}}}", GENERATED_MARKER);
+        write_file(&dest, &fresh, &options).unwrap();
+        let backup = temp_path("backup.json.bak");
+        assert_eq!(fs::read_to_string(&backup).unwrap(), old);
+        assert_eq!(fs::read_to_string(&dest).unwrap(), fresh);
+
+        // 默认方式下只保留最近的一份备份
+        write_file(&dest, "newer", &options).unwrap();
+        assert_eq!(fs::read_to_string(&backup).unwrap(), fresh);
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "newer");
+        fs::remove_file(&dest).unwrap();
+        fs::remove_file(&backup).unwrap();
+    }
+    #[test]
+    fn test_backup_timestamped() {
+        let dest = temp_path("timestamped.json");
+        let old = format!("{}\n{{}}", GENERATED_MARKER);
+        fs::write(&dest, &old).unwrap();
+        let options = WriteOptions {
+            backup: Some(BackupStyle::Timestamped),
+            ..WriteOptions::default()
+        };
+
+        write_file(&dest, "new", &options).unwrap();
+        let backups: Vec<PathBuf> = fs::read_dir(dest.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                let name = path.file_name().unwrap().to_str().unwrap();
+                name.starts_with("timestamped.json.") && name.ends_with(".bak")
+            })
+            .collect();
+        assert_eq!(backups.len(), 1);
+        assert_eq!(fs::read_to_string(&backups[0]).unwrap(), old);
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "new");
+        fs::remove_file(&dest).unwrap();
+        fs::remove_file(&backups[0]).unwrap();
+    }
+    #[test]
+    fn test_timestamp() {
+        let time = UNIX_EPOCH + Duration::from_secs(951_827_696);
+        assert_eq!(timestamp(time), "20000229T123456");
+    }
+    #[test]
+    fn test_newline() {
+        let text = "{\n  \"a\": 1\r\n}";
+        let dest = temp_path("newline.json");
+
+        write_file(&dest, text, &WriteOptions::default()).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"{\n  \"a\": 1\n}".to_vec());
+
+        let options = WriteOptions {
+            clobber: Clobber::Force,
+            newline: Newline::Crlf,
+            backup: None,
+        };
+        write_file(&dest, text, &options).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"{\r\n  \"a\": 1\r\n}".to_vec());
+        fs::remove_file(&dest).unwrap();
+    }
+    #[test]
+    fn test_partition() {
+        let snips: Vec<Snippet> = (0..50)
+            .rev()
+            .map(|i| {
+                let body = "x".repeat(i % 7 + 1);
+                Snippet::from_text(&format!("s{:02}", i), "p", "rust", &body, "é").unwrap()
+            })
+            .collect();
+        let style = JsonStyle::default();
+        for (format, newline) in [
+            (Format::CodeSnippets, Newline::Lf),
+            (Format::Json, Newline::Crlf),
+        ] {
+            let limits = PartLimits {
+                snippets: None,
+                bytes: Some(600),
+            };
+            let parts = partition(&snips, format, style, newline, limits);
+            assert!(parts.len() > 1);
+            let mut identifiers: Vec<&str> = Vec::new();
+            for (index, part) in parts.iter().enumerate() {
+                let size = newline.normalize(&render(part, format, style)).len();
+                assert!(size <= 600, "第 {} 部分有 {} 字节", index + 1, size);
+                if index + 1 < parts.len() {
+                    let next = &parts[index + 1][0];
+                    let grown = [part.clone(), vec![next.clone()]].concat();
+                    let grown = newline.normalize(&render(&grown, format, style)).len();
+                    assert!(grown > 600, "第 {} 部分应当尽量填满", index + 1);
+                }
+                identifiers.extend(part.iter().map(|snip| snip.get_identifier().as_str()));
+            }
+            let sorted: Vec<String> = (0..50).map(|i| format!("s{:02}", i)).collect();
+            assert_eq!(identifiers, sorted);
+        }
+        let limits = PartLimits {
+            snippets: Some(20),
+            bytes: None,
+        };
+        let counts: Vec<usize> = partition(&snips, Format::Json, style, Newline::Lf, limits)
+            .iter()
+            .map(|part| part.len())
+            .collect();
+        assert_eq!(counts, vec![20, 20, 10]);
+        let limits = PartLimits {
+            snippets: None,
+            bytes: Some(1),
+        };
+        assert_eq!(
+            partition(&snips[..3], Format::Json, style, Newline::Lf, limits).len(),
+            3,
+            "超过上限的 Snippet 应当独占一个部分"
+        );
+    }
+    #[test]
+    fn test_part_path() {
+        assert_eq!(
+            part_path(Path::new("out/rust.code-snippets"), 2),
+            Path::new("out/rust.2.code-snippets")
+        );
+        assert_eq!(part_path(Path::new("a.b.json"), 1), Path::new("a.b.1.json"));
+        assert_eq!(part_path(Path::new("out"), 3), Path::new("out.3"));
+    }
+
+    /// 在系统临时目录下为测试生成一个不会冲突的路径
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mdppet-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        return dir.join(name);
+    }
+}
@@ -0,0 +1,369 @@
+use std::collections::BTreeMap;
+
+/// VSCode snippet 语法中的一个节点，见
+/// <https://code.visualstudio.com/docs/editor/userdefinedsnippets#_grammar>
+///
+/// 不合语法的 `$` 和 `${` 按字面文本处理，与 VSCode 一致；转换（`${1/regex/format/}`）被解析但不保留。
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Text(String),
+    /// `$1`、`${1}`
+    Tabstop(usize),
+    /// `${1:default}`，默认值中可以嵌套其他节点
+    Placeholder(usize, Vec<Node>),
+    /// `${1|one,two|}`
+    Choice(usize, Vec<String>),
+    /// `$TM_FILENAME`、`${TM_FILENAME:default}`
+    Variable {
+        name: String,
+        default: Option<Vec<Node>>,
+    },
+}
+
+/// 将补全体的文本解析为节点
+pub fn parse(text: &str) -> Vec<Node> {
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        pos: 0,
+    };
+    return parser.any(false);
+}
+
+/// 所有占位符都取默认值时插入的文本：相当于插入后一路按 Tab 跳过
+///
+/// - `${1:name}` 展开为默认值，同一编号的 `$1` 与之相同
+/// - 没有默认值的 `$2` 和 `$0` 为空
+/// - `${1|a,b|}` 取第一个选项
+/// - 变量由编辑器在插入时决定，写作 `<TM_FILENAME>`
+pub fn expand(nodes: &[Node]) -> String {
+    let mut defaults: BTreeMap<usize, &Node> = BTreeMap::new();
+    collect_defaults(nodes, &mut defaults);
+    let mut text = String::new();
+    expand_into(nodes, &defaults, &mut Vec::new(), &mut text);
+    return text;
+}
+
+/// 展开以行存储的补全体，见 `expand`
+pub fn expand_body(body: &[String]) -> String {
+    return expand(&parse(&body.join("\n")));
+}
+
+/// 节点中最大的 tabstop 编号，包括嵌套在默认值中的；没有 tabstop 时为 0
+///
+/// 转换中的 `$1` 引用的是正则的分组，选项中的 `$` 是字面文本，都不算作 tabstop。
+pub fn max_index(nodes: &[Node]) -> usize {
+    let mut max = 0;
+    for node in nodes.iter() {
+        let index = match node {
+            Node::Text(_) => 0,
+            Node::Tabstop(index) | Node::Choice(index, _) => *index,
+            Node::Placeholder(index, children) => (*index).max(max_index(children)),
+            Node::Variable { default, .. } => default.as_deref().map_or(0, max_index),
+        };
+        max = max.max(index);
+    }
+    return max;
+}
+
+/// 每个编号第一次出现的占位符或选项，VSCode 以它作为该编号所有位置的初始值
+fn collect_defaults<'a>(nodes: &'a [Node], defaults: &mut BTreeMap<usize, &'a Node>) {
+    for node in nodes.iter() {
+        match node {
+            Node::Placeholder(index, children) => {
+                defaults.entry(*index).or_insert(node);
+                collect_defaults(children, defaults);
+            }
+            Node::Choice(index, _) => {
+                defaults.entry(*index).or_insert(node);
+            }
+            Node::Variable {
+                default: Some(children),
+                ..
+            } => collect_defaults(children, defaults),
+            _ => {}
+        }
+    }
+}
+
+/// `expanding` 是正在展开的编号，占位符的默认值引用自身时按空处理
+fn expand_into(
+    nodes: &[Node],
+    defaults: &BTreeMap<usize, &Node>,
+    expanding: &mut Vec<usize>,
+    text: &mut String,
+) {
+    for node in nodes.iter() {
+        match node {
+            Node::Text(literal) => text.push_str(literal),
+            Node::Variable { name, .. } => text.push_str(&format!("<{}>", name)),
+            Node::Tabstop(index) | Node::Placeholder(index, _) | Node::Choice(index, _) => {
+                if *index == 0 || expanding.contains(index) {
+                    continue;
+                }
+                match defaults.get(index) {
+                    Some(Node::Placeholder(_, children)) => {
+                        expanding.push(*index);
+                        expand_into(children, defaults, expanding, text);
+                        expanding.pop();
+                    }
+                    Some(Node::Choice(_, options)) => {
+                        text.push_str(options.first().map_or("", String::as_str))
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        return self.chars.get(self.pos).copied();
+    }
+
+    /// 下一个字符是 `c` 时消耗它
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            return true;
+        }
+        return false;
+    }
+
+    /// 解析到文本末尾，`nested` 时在未转义的 `}` 之前停下
+    fn any(&mut self, nested: bool) -> Vec<Node> {
+        let mut nodes: Vec<Node> = Vec::new();
+        while let Some(c) = self.peek() {
+            if nested && c == '}' {
+                break;
+            }
+            match c {
+                '\\' if matches!(self.chars.get(self.pos + 1), Some('$' | '}' | '\\')) => {
+                    push_text(&mut nodes, self.chars[self.pos + 1]);
+                    self.pos += 2;
+                }
+                '$' => match self.dollar() {
+                    Some(node) => nodes.push(node),
+                    None => {
+                        push_text(&mut nodes, '$');
+                        self.pos += 1;
+                    }
+                },
+                _ => {
+                    push_text(&mut nodes, c);
+                    self.pos += 1;
+                }
+            }
+        }
+        return nodes;
+    }
+
+    /// 解析以 `$` 开头的节点，不合语法时回到 `$` 处并返回 `None`
+    fn dollar(&mut self) -> Option<Node> {
+        let start = self.pos;
+        self.pos += 1;
+        let node = if self.eat('{') {
+            self.braced()
+        } else if let Some(index) = self.int() {
+            Some(Node::Tabstop(index))
+        } else {
+            self.var().map(|name| Node::Variable {
+                name,
+                default: None,
+            })
+        };
+        if node.is_none() {
+            self.pos = start;
+        }
+        return node;
+    }
+
+    /// `${` 之后的部分
+    fn braced(&mut self) -> Option<Node> {
+        if let Some(index) = self.int() {
+            if self.eat('}') {
+                return Some(Node::Tabstop(index));
+            }
+            if self.eat(':') {
+                let children = self.any(true);
+                return self.eat('}').then_some(Node::Placeholder(index, children));
+            }
+            if self.eat('|') {
+                return self.choices().map(|options| Node::Choice(index, options));
+            }
+            return self.transform().then_some(Node::Tabstop(index));
+        }
+        let name = self.var()?;
+        if self.eat('}') {
+            return Some(Node::Variable {
+                name,
+                default: None,
+            });
+        }
+        if self.eat(':') {
+            let children = self.any(true);
+            return self.eat('}').then_some(Node::Variable {
+                name,
+                default: Some(children),
+            });
+        }
+        return self.transform().then_some(Node::Variable {
+            name,
+            default: None,
+        });
+    }
+
+    fn int(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        return digits.parse().ok();
+    }
+
+    fn var(&mut self) -> Option<String> {
+        let start = self.pos;
+        if !self
+            .peek()
+            .is_some_and(|c| c == '_' || c.is_ascii_alphabetic())
+        {
+            return None;
+        }
+        while self
+            .peek()
+            .is_some_and(|c| c == '_' || c.is_ascii_alphanumeric())
+        {
+            self.pos += 1;
+        }
+        return Some(self.chars[start..self.pos].iter().collect());
+    }
+
+    /// `${1|` 之后以 `,` 分隔、以 `|}` 结尾的选项，`\,`、`\|` 和 `\\` 为转义
+    fn choices(&mut self) -> Option<Vec<String>> {
+        let mut options: Vec<String> = vec![String::new()];
+        loop {
+            match self.peek()? {
+                '\\' if matches!(self.chars.get(self.pos + 1), Some(',' | '|' | '\\')) => {
+                    options.last_mut().unwrap().push(self.chars[self.pos + 1]);
+                    self.pos += 2;
+                    continue;
+                }
+                '|' if self.chars.get(self.pos + 1) == Some(&'}') => {
+                    self.pos += 2;
+                    return Some(options);
+                }
+                ',' => options.push(String::new()),
+                c => options.last_mut().unwrap().push(c),
+            }
+            self.pos += 1;
+        }
+    }
+
+    /// `/regex/format/options}`，正则和格式中的 `\/` 为转义，格式中的 `${1:/upcase}` 可以含有 `/`
+    fn transform(&mut self) -> bool {
+        if !self.eat('/') {
+            return false;
+        }
+        for _ in 0..2 {
+            loop {
+                match self.peek() {
+                    None => return false,
+                    Some('\\') => self.pos += 2,
+                    Some('$') if self.chars.get(self.pos + 1) == Some(&'{') => {
+                        match self.chars[self.pos..].iter().position(|&c| c == '}') {
+                            Some(end) => self.pos += end + 1,
+                            None => return false,
+                        }
+                    }
+                    Some('/') => {
+                        self.pos += 1;
+                        break;
+                    }
+                    Some(_) => self.pos += 1,
+                }
+            }
+        }
+        while self.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+            self.pos += 1;
+        }
+        return self.eat('}');
+    }
+}
+
+/// 在末尾追加一个字符，与前面的文本节点合并
+fn push_text(nodes: &mut Vec<Node>, c: char) {
+    match nodes.last_mut() {
+        Some(Node::Text(text)) => text.push(c),
+        _ => nodes.push(Node::Text(c.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = |s: &str| Node::Text(String::from(s));
+        assert_eq!(
+            parse("fn ${1:name}($2) {$0}"),
+            vec![
+                text("fn "),
+                Node::Placeholder(1, vec![text("name")]),
+                text("("),
+                Node::Tabstop(2),
+                text(") {"),
+                Node::Tabstop(0),
+                text("}"),
+            ]
+        );
+        assert_eq!(
+            parse("${1|a\\,b,c|}${TM_FILENAME:x}"),
+            vec![
+                Node::Choice(1, vec![String::from("a,b"), String::from("c")]),
+                Node::Variable {
+                    name: String::from("TM_FILENAME"),
+                    default: Some(vec![text("x")]),
+                },
+            ]
+        );
+        assert_eq!(parse("a $ ${ ${1:x \\$5"), vec![text("a $ ${ ${1:x $5")]);
+        assert_eq!(parse("${1/(.*)/${1:/upcase}/g}"), vec![Node::Tabstop(1)]);
+    }
+    #[test]
+    fn test_max_index() {
+        assert_eq!(max_index(&parse("plain $ text")), 0);
+        assert_eq!(max_index(&parse("${1:${4}} ${2|a,b|}")), 4);
+        assert_eq!(max_index(&parse("${TM_FILENAME:${3:x}}")), 3);
+        assert_eq!(max_index(&parse("${1|$9,b|} ${2/(.*)/$7/}")), 2);
+        assert_eq!(max_index(&parse("\\\\$5 \\$6")), 5);
+    }
+    #[test]
+    fn test_expand() {
+        let body: Vec<String> = [
+            "// ${TM_FILENAME} by $USER",
+            "fn ${1:name}(${2:arg}: ${3|i32,u8|}) -> ${4:${3}} {",
+            "    $1(${5}); \\$x ${6:nested ${7:inner}}",
+            "    ${1/(.*)/${1:/upcase}/}$0",
+            "}",
+        ]
+        .iter()
+        .map(|line| String::from(*line))
+        .collect();
+        assert_eq!(
+            expand_body(&body),
+            "// <TM_FILENAME> by <USER>\nfn name(arg: i32) -> i32 {\n    name(); $x nested inner\n    name\n}"
+        );
+        assert_eq!(
+            expand(&parse("${1:a$1}")),
+            "a",
+            "引用自身的占位符不应无限展开"
+        );
+    }
+}
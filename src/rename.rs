@@ -0,0 +1,173 @@
+use crate::snip::{self, ParseOptions};
+use regex::Match;
+use std::collections::BTreeMap;
+
+/// `mdppet rename` 可以修改的标题字段
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Identifier,
+    Prefix,
+}
+
+impl Field {
+    /// `MARKDOWN_RE` 中对应的捕获组
+    fn group(&self) -> &'static str {
+        match self {
+            Field::Identifier => return "id",
+            Field::Prefix => return "prefix",
+        }
+    }
+
+    /// 用于错误信息的名称
+    pub fn name(&self) -> &'static str {
+        match self {
+            Field::Identifier => return "identifier",
+            Field::Prefix => return "prefix",
+        }
+    }
+}
+
+/// `text` 中所有 Snippet 标题的 `field` 字段，以 `,` 分隔的多个前缀分别列出
+pub fn field_values<'a>(text: &'a str, options: &ParseOptions, field: Field) -> Vec<&'a str> {
+    let mut values: Vec<&str> = Vec::new();
+    for m in snip::split_segments(text, options).0 {
+        if let Some(value) = m.name(field.group()) {
+            values.extend(pieces(value, field).into_iter().map(|(_, piece)| piece));
+        }
+    }
+    return values;
+}
+
+/// 字段中可以单独改名的部分及其在文本中的起始位置：前缀按 `,` 拆分，标识符是整个字段
+fn pieces(value: Match<'_>, field: Field) -> Vec<(usize, &str)> {
+    if field == Field::Identifier {
+        return vec![(value.start(), value.as_str())];
+    }
+    let mut pieces: Vec<(usize, &str)> = Vec::new();
+    let mut start = value.start();
+    for piece in value.as_str().split(',') {
+        if !piece.is_empty() {
+            pieces.push((start, piece));
+        }
+        start += piece.len() + 1;
+    }
+    return pieces;
+}
+
+/// 将 `text` 中 Snippet 标题的 `field` 字段由 `old` 改为 `new`，返回修改后的文本和修改的处数
+///
+/// 只替换标题中该字段所在的范围，文件的其余部分保持原样；
+/// 有多个前缀时只替换与 `old` 相同的那一个。
+pub fn rename_headings(
+    text: &str,
+    options: &ParseOptions,
+    field: Field,
+    old: &str,
+    new: &str,
+) -> (String, usize) {
+    let mut renamed = String::with_capacity(text.len());
+    let mut copied = 0;
+    let mut count = 0;
+    for m in snip::split_segments(text, options).0 {
+        let value = match m.name(field.group()) {
+            Some(value) => value,
+            None => continue,
+        };
+        for (start, piece) in pieces(value, field) {
+            if piece == old {
+                renamed.push_str(&text[copied..start]);
+                renamed.push_str(new);
+                copied = start + piece.len();
+                count += 1;
+            }
+        }
+    }
+    renamed.push_str(&text[copied..]);
+    return (renamed, count);
+}
+
+/// 改名前后输出中的标识符，由两次解析的结果逐个对应得出
+///
+/// 修改标题中的标识符也会改变由它拆分出的各语言版本的标识符（如 `license-python`）。
+pub fn renamed_identifiers(
+    before: &str,
+    after: &str,
+    options: &ParseOptions,
+) -> BTreeMap<String, String> {
+    let parse = |text: &str| {
+        let text = snip::strip_html_comments(&snip::split_front_matter(text).1);
+        return snip::parse_snippets_collecting(&text, options).0;
+    };
+    let mut renames: BTreeMap<String, String> = BTreeMap::new();
+    for ((_, old), (_, new)) in parse(before).iter().zip(parse(after).iter()) {
+        if old.get_identifier() != new.get_identifier() {
+            renames.insert(old.get_identifier().clone(), new.get_identifier().clone());
+        }
+    }
+    return renames;
+}
+
+/// 以统一 diff 的格式列出 `before` 和 `after` 中不同的行，`--dry-run`
+///
+/// 改名不会增删行，因此逐行比较即可。
+pub fn line_diff(path: &str, before: &str, after: &str) -> String {
+    let mut diff = format!("--- {}\n+++ {}\n", path, path);
+    for (index, (old, new)) in before.lines().zip(after.lines()).enumerate() {
+        if old != new {
+            diff.push_str(&format!(
+                "@@ -{} +{} @@\n-{}\n+{}\n",
+                index + 1,
+                index + 1,
+                old,
+                new
+            ));
+        }
+    }
+    return diff;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "# fn/fn/rust\n\n```rust\nfn fn() {}\n```\n\n# lic/fn/python,rust\n\n```python\n# fn\n```\n\n```rust\n// fn\n```\n";
+
+    #[test]
+    fn test_rename_headings() {
+        let options = ParseOptions::default();
+        let (renamed, count) = rename_headings(TEXT, &options, Field::Prefix, "fn", "func");
+        assert_eq!(count, 2);
+        assert!(renamed.starts_with("# fn/func/rust\n\n```rust\nfn fn() {}\n```"));
+        assert!(renamed.contains("# lic/func/python,rust\n"));
+        assert_eq!(
+            field_values(&renamed, &options, Field::Identifier),
+            vec!["fn", "lic"]
+        );
+        let text = "# a/x,fn/rust\n\n```\nx\n```\n";
+        let (renamed, count) = rename_headings(text, &options, Field::Prefix, "fn", "func");
+        assert_eq!(
+            (renamed.as_str(), count),
+            ("# a/x,func/rust\n\n```\nx\n```\n", 1)
+        );
+        assert_eq!(field_values(text, &options, Field::Prefix), vec!["x", "fn"]);
+        let (unchanged, count) = rename_headings(TEXT, &options, Field::Identifier, "f", "g");
+        assert_eq!((unchanged.as_str(), count), (TEXT, 0));
+    }
+    #[test]
+    fn test_renamed_identifiers() {
+        let options = ParseOptions::default();
+        let (renamed, _) = rename_headings(TEXT, &options, Field::Identifier, "lic", "license");
+        let renames = renamed_identifiers(TEXT, &renamed, &options);
+        assert_eq!(renames.len(), 2);
+        assert_eq!(renames["lic-python"], "license-python");
+        assert_eq!(renames["lic-rust"], "license-rust");
+    }
+    #[test]
+    fn test_line_diff() {
+        let diff = line_diff("a.md", "# a/b/c\nx\n", "# d/b/c\nx\n");
+        assert_eq!(
+            diff,
+            "--- a.md\n+++ a.md\n@@ -1 +1 @@\n-# a/b/c\n+# d/b/c\n"
+        );
+    }
+}
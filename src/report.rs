@@ -0,0 +1,161 @@
+use crate::diagnostic::MessageFormat;
+use crate::snip::Snippet;
+use crate::sourcemap::SnippetMap;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// 一次构建的统计，在构建结束时输出：`--summary`
+#[derive(Debug)]
+pub struct BuildReport {
+    /// 读取的源文件数，不含 include 的文件
+    pub sources: usize,
+    /// 解析出的 Snippet 数，去重之前
+    pub snippets: usize,
+    /// 每个输出文件及写入（或 `--check` 比较）的 Snippet 数
+    pub outputs: Vec<OutputCount>,
+    /// 按 `--max-snippets-per-file` 或 `--max-file-size` 拆分的输出文件及拆分出的部分数
+    pub splits: Vec<SplitCount>,
+    /// 拆分前后不再需要而被删除的旧文件
+    pub removed: Vec<String>,
+    pub warnings: usize,
+    pub errors: usize,
+    /// `mdppet build` 正在构建的目标，之后记录的输出文件都属于它
+    pub target: Option<String>,
+    /// `--emit-sourcemap` 时记录每个 Snippet 的位置和写入的输出文件
+    pub sourcemap: Option<SnippetMap>,
+    started: Instant,
+}
+
+/// 一个输出文件中的 Snippet 数
+#[derive(Serialize, Debug, PartialEq)]
+pub struct OutputCount {
+    /// 所属的构建目标，只在 `mdppet build` 中出现
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub file: String,
+    pub snippets: usize,
+}
+
+/// 一个拆分为多个部分的输出文件
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SplitCount {
+    /// 所属的构建目标，只在 `mdppet build` 中出现
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub file: String,
+    pub parts: usize,
+}
+
+/// `--summary json` 输出的内容，与 `BuildReport` 对应
+#[derive(Serialize)]
+struct Summary<'a> {
+    sources: usize,
+    snippets: usize,
+    outputs: &'a [OutputCount],
+    splits: &'a [SplitCount],
+    removed: &'a [String],
+    warnings: usize,
+    errors: usize,
+    elapsed_ms: u128,
+}
+
+impl Default for BuildReport {
+    fn default() -> Self {
+        return BuildReport::new();
+    }
+}
+
+impl BuildReport {
+    /// 从现在开始计时
+    pub fn new() -> Self {
+        BuildReport {
+            sources: 0,
+            snippets: 0,
+            outputs: Vec::new(),
+            splits: Vec::new(),
+            removed: Vec::new(),
+            warnings: 0,
+            errors: 0,
+            target: None,
+            sourcemap: None,
+            started: Instant::now(),
+        }
+    }
+
+    /// 记录由 `sources` 生成的 `snips` 写入了 `dest`
+    pub fn record_output(&mut self, dest: &Path, sources: &[PathBuf], snips: &[Snippet]) {
+        self.outputs.push(OutputCount {
+            target: self.target.clone(),
+            file: dest.display().to_string(),
+            snippets: snips.len(),
+        });
+        if let Some(sourcemap) = self.sourcemap.as_mut() {
+            sourcemap.record_output(dest, sources, snips.iter());
+        }
+    }
+
+    /// 记录 `dest` 被拆分为 `parts` 个部分
+    pub fn record_split(&mut self, dest: &Path, parts: usize) {
+        self.splits.push(SplitCount {
+            target: self.target.clone(),
+            file: dest.display().to_string(),
+            parts,
+        });
+    }
+
+    /// 记录删除了不再需要的旧文件 `path`
+    pub fn record_removed(&mut self, path: &Path) {
+        self.removed.push(path.display().to_string());
+    }
+
+    /// 记录诊断信息中的警告数和错误数，取已记录的和新的两者中较大的一个
+    pub fn record_diagnostics(&mut self, (warnings, errors): (usize, usize)) {
+        self.warnings = self.warnings.max(warnings);
+        self.errors = self.errors.max(errors);
+    }
+
+    /// 按 `format` 输出统计：人类可读的汇总，或一个 JSON 对象，都不带末尾的换行
+    pub fn render(&self, format: MessageFormat) -> String {
+        let elapsed_ms = self.started.elapsed().as_millis();
+        match format {
+            MessageFormat::Human => {
+                let mut text = format!(
+                    "mdppet: summary: {} source file(s), {} snippet(s) parsed, {} warning(s), {} error(s) in {} ms",
+                    self.sources, self.snippets, self.warnings, self.errors, elapsed_ms
+                );
+                for output in self.outputs.iter() {
+                    text.push_str("\n    ");
+                    if let Some(target) = &output.target {
+                        text.push_str(&format!("[{}] ", target));
+                    }
+                    text.push_str(&format!("{}: {} snippet(s)", output.file, output.snippets));
+                }
+                for split in self.splits.iter() {
+                    text.push_str("\n    ");
+                    if let Some(target) = &split.target {
+                        text.push_str(&format!("[{}] ", target));
+                    }
+                    text.push_str(&format!("{}: split into {} parts", split.file, split.parts));
+                }
+                for removed in self.removed.iter() {
+                    text.push_str(&format!("\n    removed {}", removed));
+                }
+                return text;
+            }
+            MessageFormat::Json => {
+                let summary = Summary {
+                    sources: self.sources,
+                    snippets: self.snippets,
+                    outputs: &self.outputs,
+                    splits: &self.splits,
+                    removed: &self.removed,
+                    warnings: self.warnings,
+                    errors: self.errors,
+                    elapsed_ms,
+                };
+                return serde_json::to_string(&summary).unwrap();
+            }
+        }
+    }
+}
@@ -1,7 +1,16 @@
 #![allow(dead_code)]
+use crate::error::MdppetError;
+use crate::inline::strip_inline_markdown;
+use crate::lang;
+use crate::vars::Vars;
 use regex::Regex;
-use serde::Serialize;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use std::path::{Component, Path};
+use std::sync::{Mutex, OnceLock};
 
 /// 用于匹配 Markdown 中一个 Snippet 片段的正则表达式
 ///
@@ -9,18 +18,468 @@ use std::fmt::Debug;
 /// - `\x23` 表示 `#`
 ///
 /// 以上字符由于和正则引擎冲突，因此使用转义表达法
+///
+/// 标题的写法较为宽松：`#` 之后可以是任意个空格或制表符，
+/// 结尾可以有闭合的 `#` 序列（`# id/prefix/scope #`）和空白。
+/// 作用域之后可以跟随以空白分隔的标记，如 `@dedent`；只有 `@file` 带一个参数，如 `@file a.json`，
+/// 其他标记之后的参数由 `Snippet::apply_flags` 报告为错误。
+/// 标识符可以为空（`# /fn/rust`），此时由前缀和补全体生成，见 `synthesize_identifier`。
+///
+/// 标题和描述之间可以有若干行 `[key=value]` 形式的元数据，见 `parse_metadata`。
+/// 描述可以省略；描述的各行不能以 ```` ``` ```` 或 `~~~` 开头，以免把代码块当作描述。
+/// 代码块可以用 ```` ``` ```` 或 `~~~` 围起，后者的捕获组带有 `tilde_` 前缀，见 `fence_group`。
 const MARKDOWN_RE: &str = r#"((?msx)
-\x23\x20(?P<id>\S+)/(?P<prefix>\S+)/(?P<scope>\S+)
+^\x23[\x20\t]+(?P<id>\S*)/(?P<prefix>\S+)/(?P<scope>\S+)(?P<flags>(?:[\x20\t]+@[A-Za-z-]+(?:[\x20\t]+[^\s@\x23]\S*)?)*)(?:[\x20\t]+\x23+)?[\x20\t]*
 \n+
-(?P<description>
-  (?:[^\n]+\n)+
+(?:
+  (?P<metadata>
+    (?:\[[^\]\n=]+=[^\]\n]*\][\x20\t]*\n)+
+  )
+  \n*
+)?
+(?:
+  (?P<description>
+    (?:(?:[^`~\n]|`[^`\n]|``[^`\n]|~[^~\n]|~~[^~\n])[^\n]*\n)+
+  )
+  \n+
+)?
+(?:
+  ```(?P<lang>\S+)?(?P<attrs>[^\n]*)\n
+  (?P<body>.+?)
+  ```
+|
+  ~~~(?P<tilde_lang>\S+)?(?P<tilde_attrs>[^\n]*)\n
+  (?P<tilde_body>.+?)
+  ~~~
 )
-\n+
-```(?:\S+)?\n
-(?P<body>.+?)
-```
 )"#;
 
+/// 一行元数据：`[author=me]`
+const METADATA_RE: &str = r"\[([^\]\n=]+)=([^\]\n]*)\]";
+
+/// 写在 Snippet 标题上方的指令，可以连续写多行：
+///
+/// - 输出路由：`<!-- mdppet:output global.code-snippets -->`
+/// - 继承：`<!-- mdppet:extends base-id -->`，见 `resolve_extends`
+/// - 按语言加上注释前缀：`<!-- mdppet:commentify -->`，见 `Snippet::commentify`
+///
+/// `output` 和 `extends` 缺少参数时被忽略。
+pub const DIRECTIVE_RE: &str = r"^[\x20\t]*<!--[\x20\t]*mdppet:(?P<name>output|extends|commentify)(?:[\x20\t]+(?P<arg>\S+))?[\x20\t]*-->[\x20\t]*$";
+
+/// 基础 Snippet 的补全体中代入派生 Snippet 补全体的位置
+pub const SLOT_MARKER: &str = "{{slot}}";
+
+/// 紧跟在 Snippet 代码块之后、中间只隔着空行的代码块
+pub const FOLLOWING_FENCE_RE: &str = r#"(?s)^\n+(?:```(?P<lang>\S+)?(?P<attrs>[^\n]*)\n(?P<body>.+?)```|~~~(?P<tilde_lang>\S+)?(?P<tilde_attrs>[^\n]*)\n(?P<tilde_body>.+?)~~~)"#;
+
+/// 代码块的捕获组 `lang`、`attrs` 或 `body`，代码块以 `~~~` 围起时取带 `tilde_` 前缀的同名捕获组
+pub fn fence_group<'t>(m: &regex::Captures<'t>, name: &str) -> Option<regex::Match<'t>> {
+    return m.name(name).or_else(|| m.name(&format!("tilde_{}", name)));
+}
+
+/// 用于识别一行是否像 Snippet 标题
+const HEADING_RE: &str = r"(?m)^\x23[\x20\t]+\S*/\S+/\S+(?:[\x20\t]+@[A-Za-z-]+(?:[\x20\t]+[^\s@\x23]\S*)?)*(?:[\x20\t]+\x23+)?[\x20\t]*$";
+
+/// 规范的标题写法：`#` 之后恰好一个空格，结尾没有多余的字符，用于 `--strict`
+const STRICT_HEADING_RE: &str = r"^\x23\x20\S*/\S+/\S+(?:\x20@[A-Za-z-]+(?:\x20[^\s@\x23]\S*)?)*$";
+
+/// 默认的标题字段分隔符
+pub const DEFAULT_FIELD_SEP: &str = "/";
+
+/// 编译正则表达式，结果按表达式缓存
+///
+/// 解析每个片段时都要用到按选项调整过的表达式，同样的选项只编译一次；`Regex` 的克隆开销很小。
+fn compile_cached(re: String) -> Regex {
+    static CACHE: OnceLock<Mutex<HashMap<String, Regex>>> = OnceLock::new();
+    let mut cache = CACHE.get_or_init(Default::default).lock().unwrap();
+    return cache
+        .entry(re)
+        .or_insert_with_key(|re| Regex::new(re).unwrap())
+        .clone();
+}
+
+/// 解析 Markdown 时的选项
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// 标题中分隔标识符、前缀和作用域的字符串：`--field-sep`
+    pub field_sep: String,
+    /// Snippet 标题的级别，即开头 `#` 的个数：`--heading-level`
+    ///
+    /// 其他级别的标题不会被当作 Snippet，可以用来组织文档结构。
+    pub heading_level: usize,
+    /// 分组标题的级别：`--group-heading-level`
+    ///
+    /// 分组标题的文字（如 `## Python`）按语言表解析为作用域，
+    /// 作为其后 Snippet 的默认作用域，此时 Snippet 标题可以省略作用域。
+    pub group_level: Option<usize>,
+    /// 补全体的整理方式，代码块可以用属性单独覆盖
+    pub body: BodyOptions,
+    /// `--comment-leader` 指定的行注释前缀，以语言标识符为键，优先于内置的表
+    pub comment_leaders: BTreeMap<String, String>,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            field_sep: String::from(DEFAULT_FIELD_SEP),
+            heading_level: 1,
+            group_level: None,
+            body: BodyOptions::default(),
+            comment_leaders: BTreeMap::new(),
+        }
+    }
+}
+
+impl ParseOptions {
+    /// 作用域对应语言的行注释前缀，见 `lang::comment_leader`
+    pub fn comment_leader(&self, scope: &str) -> Option<&str> {
+        let key = lang::language_key(scope);
+        if let Some(leader) = self.comment_leaders.get(&key) {
+            return Some(leader);
+        }
+        return lang::comment_leader(&key);
+    }
+    /// 按选项调整 `MARKDOWN_RE`
+    pub fn markdown_re(&self) -> Regex {
+        return compile_cached(self.adjust(MARKDOWN_RE));
+    }
+    /// 按选项调整 `HEADING_RE`
+    fn heading_re(&self) -> Regex {
+        return compile_cached(self.adjust(HEADING_RE));
+    }
+    /// 匹配分组标题
+    fn group_re(&self) -> Option<Regex> {
+        let level = self.group_level?;
+        let re = format!(r"(?m)^\x23{{{}}}[\x20\t]+([^\n]+?)[\x20\t]*$", level);
+        return Some(Regex::new(&re).unwrap());
+    }
+    /// 按选项调整 `STRICT_HEADING_RE`
+    fn strict_heading_re(&self) -> Regex {
+        return compile_cached(self.adjust(STRICT_HEADING_RE));
+    }
+    /// 各正则表达式中只有标题部分含有 `/` 和开头的 `\x23`，
+    /// 分别替换为转义后的分隔符和对应个数的 `#`；启用分组时作用域是可选的
+    fn adjust(&self, re: &str) -> String {
+        let mut re = String::from(re);
+        if self.group_level.is_some() {
+            re = re
+                .replace(
+                    r"(?P<id>\S*)/(?P<prefix>\S+)/(?P<scope>\S+)",
+                    r"(?P<id>\S*?)/(?P<prefix>\S+?)(?:/(?P<scope>\S+))?",
+                )
+                .replace(r"\S*/\S+/\S+", r"\S*?/\S+?(?:/\S+)?");
+        }
+        let hashes = format!(r"\x23{{{}}}", self.heading_level);
+        return re
+            .replace(DEFAULT_FIELD_SEP, &regex::escape(&self.field_sep))
+            .replace(r"^\x23", &format!("^{}", hashes));
+    }
+
+    /// 按文件头部的 front matter 覆盖选项，支持 `heading-level`、
+    /// `keep-trailing-newline` 和 `trim-trailing-whitespace`
+    pub fn with_front_matter(
+        &self,
+        front_matter: &BTreeMap<String, String>,
+    ) -> Result<ParseOptions, String> {
+        let mut options = self.clone();
+        for (key, value) in front_matter.iter() {
+            let flag = || parse_bool(value).ok_or(format!("{}: expected true or false", key));
+            match key.as_str() {
+                "heading-level" => options.heading_level = parse_heading_level(value)?,
+                "keep-trailing-newline" => options.body.keep_trailing_newline = flag()?,
+                "trim-trailing-whitespace" => options.body.trim_trailing_whitespace = flag()?,
+                _ => {}
+            }
+        }
+        return Ok(options);
+    }
+}
+
+/// 省略描述的 Snippet 使用的默认描述
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DefaultDescription {
+    /// 不输出 `description` 字段
+    None,
+    /// 使用标识符
+    Id,
+    /// 使用前缀
+    Prefix,
+}
+
+impl DefaultDescription {
+    /// `--default-description` 可以接受的取值
+    pub const NAMES: &'static [&'static str] = &["none", "id", "prefix"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => return Some(DefaultDescription::None),
+            "id" => return Some(DefaultDescription::Id),
+            "prefix" => return Some(DefaultDescription::Prefix),
+            _ => return None,
+        }
+    }
+}
+
+/// 代码块的围栏
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FenceStyle {
+    Backtick,
+    Tilde,
+}
+
+impl FenceStyle {
+    fn marker(&self) -> &'static str {
+        match self {
+            FenceStyle::Backtick => return "```",
+            FenceStyle::Tilde => return "~~~",
+        }
+    }
+
+    /// 用于 `body` 的围栏：`body` 中含有该围栏时改用另一种，以免代码块提前结束
+    pub fn marker_for(&self, body: &str) -> &'static str {
+        let other = match self {
+            FenceStyle::Backtick => FenceStyle::Tilde,
+            FenceStyle::Tilde => FenceStyle::Backtick,
+        };
+        if body.contains(self.marker()) && !body.contains(other.marker()) {
+            return other.marker();
+        }
+        return self.marker();
+    }
+}
+
+/// 以 `style` 围起 `body` 的代码块，信息字符串为 `lang` 和其后以空格分隔的属性 `attrs`
+///
+/// `body` 的每一行（包括最后一行）都以换行结尾，为空时代码块中没有任何行。
+pub fn render_fence(style: FenceStyle, lang: &str, attrs: &str, body: &str) -> String {
+    let marker = style.marker_for(body);
+    let mut info = String::from(lang);
+    for attr in attrs.split_whitespace() {
+        info.push(' ');
+        info.push_str(attr);
+    }
+    return format!("{}{}\n{}{}", marker, info, body, marker);
+}
+
+/// 代码块信息字符串中写出的语言，其后总是写出代码块原有的属性
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InfoString {
+    /// 第一个作用域，没有作用域时沿用代码块原有的语言，与 `mdppet fmt` 一致
+    FirstScope,
+    /// 代码块原有的语言，`mdppet fmt` 用于标题之后有多个代码块的片段
+    Fence,
+    /// 不写语言
+    None,
+}
+
+/// `Snippet::to_markdown` 的选项
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderOptions {
+    /// 标题的级别，即开头 `#` 的个数
+    pub heading_level: usize,
+    /// 标题中分隔标识符、前缀和作用域的字符串，见 `ParseOptions::field_sep`
+    pub field_sep: String,
+    pub fence: FenceStyle,
+    pub info_string: InfoString,
+    /// 标题、元数据、描述和代码块之间的空行数，至少为 1
+    pub blank_lines: usize,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            heading_level: 1,
+            field_sep: String::from(DEFAULT_FIELD_SEP),
+            fence: FenceStyle::Backtick,
+            info_string: InfoString::FirstScope,
+            blank_lines: 1,
+        }
+    }
+}
+
+/// 标记为 `@deprecated` 的 Snippet 的处理方式：`--deprecated`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Deprecated {
+    /// 不写入输出
+    Skip,
+    /// 在描述末尾注明 `(deprecated)`
+    Annotate,
+    /// 照常写入
+    Include,
+}
+
+impl Deprecated {
+    /// `--deprecated` 可以接受的取值
+    pub const NAMES: &'static [&'static str] = &["skip", "annotate", "include"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "skip" => return Some(Deprecated::Skip),
+            "annotate" => return Some(Deprecated::Annotate),
+            "include" => return Some(Deprecated::Include),
+            _ => return None,
+        }
+    }
+}
+
+/// 补全体的整理方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BodyOptions {
+    /// 代码块以空行结尾时，在补全体末尾保留一个空字符串：`--keep-trailing-newline`
+    ///
+    /// 这样插入补全后光标会停在新的一行。
+    pub keep_trailing_newline: bool,
+    /// 去掉每一行末尾的空格和制表符，默认开启：`--no-trim-trailing-whitespace` 关闭
+    pub trim_trailing_whitespace: bool,
+}
+
+impl Default for BodyOptions {
+    fn default() -> Self {
+        BodyOptions {
+            keep_trailing_newline: false,
+            trim_trailing_whitespace: true,
+        }
+    }
+}
+
+impl BodyOptions {
+    /// 按代码块信息字符串中语言之后的属性覆盖选项，
+    /// 如 ```` ```rust keep-trailing-newline=true ````，无法识别的属性会被忽略
+    pub fn with_attrs(&self, attrs: &str) -> BodyOptions {
+        let mut options = *self;
+        for attr in attrs.split_whitespace() {
+            let (key, value) = match attr.split_once('=') {
+                Some((key, value)) => (key, parse_bool(value)),
+                None => (attr, Some(true)),
+            };
+            match (key, value) {
+                ("keep-trailing-newline", Some(value)) => options.keep_trailing_newline = value,
+                ("trim-trailing-whitespace", Some(value)) => {
+                    options.trim_trailing_whitespace = value
+                }
+                _ => {}
+            }
+        }
+        return options;
+    }
+
+    /// 将代码块的原始内容整理为补全体的各行
+    pub fn normalize(&self, raw: &str) -> Vec<String> {
+        let mut lines: Vec<String> = raw
+            .strip_suffix('\n')
+            .unwrap_or(raw)
+            .split('\n')
+            .map(|line| match self.trim_trailing_whitespace {
+                true => String::from(line.trim_end_matches([' ', '\t'])),
+                false => String::from(line),
+            })
+            .collect();
+        let is_blank = |line: &String| line.trim().is_empty();
+        let ends_with_blank = lines.last().is_some_and(is_blank);
+        while lines.last().is_some_and(is_blank) {
+            lines.pop();
+        }
+        // 空的代码块仍然是一行空字符串
+        if lines.is_empty() || (self.keep_trailing_newline && ends_with_blank) {
+            lines.push(String::new());
+        }
+        return lines;
+    }
+}
+
+/// 解析 front matter 和代码块属性中的布尔值
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim() {
+        "true" | "yes" | "on" => return Some(true),
+        "false" | "no" | "off" => return Some(false),
+        _ => return None,
+    }
+}
+
+/// Snippet 标题之后的一个代码块
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fence<'a> {
+    /// 信息字符串中的语言
+    pub lang: Option<&'a str>,
+    /// 语言之后的属性，见 `BodyOptions::with_attrs`
+    pub attrs: &'a str,
+    /// 代码块的原始内容
+    pub body: &'a str,
+}
+
+impl<'a> Fence<'a> {
+    fn from_captures(m: &regex::Captures<'a>) -> Self {
+        Fence {
+            lang: fence_group(m, "lang").map(|lang| lang.as_str()),
+            attrs: fence_group(m, "attrs").map_or("", |attrs| attrs.as_str()),
+            body: fence_group(m, "body").unwrap().as_str(),
+        }
+    }
+
+    /// 按 `options` 和代码块自身的属性整理出补全体
+    pub fn body_lines(&self, options: &BodyOptions) -> Vec<String> {
+        return options.with_attrs(self.attrs).normalize(self.body);
+    }
+}
+
+/// 解析 `--rename old=new`
+pub fn parse_rename(rename: &str) -> Result<(String, String), String> {
+    match rename.split_once('=') {
+        Some((old, new)) if !old.trim().is_empty() && !new.trim().is_empty() => {
+            return Ok((String::from(old.trim()), String::from(new.trim())))
+        }
+        _ => return Err(format!("expected old=new, got `{}`", rename)),
+    }
+}
+
+/// 解析 `--comment-leader lang=leader`，语言名称可以是别名；前缀保留空白，如 `sql=-- `
+pub fn parse_comment_leader(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((language, leader)) if !language.trim().is_empty() && !leader.trim().is_empty() => {
+            return Ok((lang::language_key(language), String::from(leader)))
+        }
+        _ => return Err(format!("expected lang=leader, got `{}`", value)),
+    }
+}
+
+/// 解析标题级别，必须是 1 到 6 之间的整数
+pub fn parse_heading_level(value: &str) -> Result<usize, String> {
+    match value.trim().parse::<usize>() {
+        Ok(level) if (1..=6).contains(&level) => return Ok(level),
+        _ => {
+            return Err(format!(
+                "invalid heading level `{}`, expected a number from 1 to 6",
+                value
+            ))
+        }
+    }
+}
+
+/// 拆分出文件开头以 `---` 围起的 front matter
+///
+/// front matter 中每行是一个 `key: value`，返回解析出的键值对和剩余的正文。
+/// 正文中的行数保持不变（front matter 被替换为空行），以便报告的行号与源文件一致。
+pub fn split_front_matter(text: &str) -> (BTreeMap<String, String>, String) {
+    let mut front_matter: BTreeMap<String, String> = BTreeMap::new();
+    let mut lines = text.split_inclusive('\n');
+    if lines.next().map(|line| line.trim_end()) != Some("---") {
+        return (front_matter, String::from(text));
+    }
+    let mut consumed = 1;
+    for line in lines {
+        consumed += 1;
+        if line.trim_end() == "---" {
+            let rest: String = text.split_inclusive('\n').skip(consumed).collect();
+            return (front_matter, "\n".repeat(consumed) + &rest);
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            front_matter.insert(String::from(key.trim()), String::from(value.trim()));
+        }
+    }
+    // 没有结束标记，不是 front matter
+    return (BTreeMap::new(), String::from(text));
+}
+
 /// # Snippet
 ///
 /// 一个 Snippet 对象，具有
@@ -36,10 +495,14 @@ const MARKDOWN_RE: &str = r#"((?msx)
 ///
 /// ## 示例
 ///
-/// ```rust
+/// ~~~rust
+/// use mdppet::snip::Snippet;
+///
 /// let markdown = r#"
-/// # a/b/rust
+/// ## a/b/rust
+///
 /// description
+///
 /// ```rust
 /// body
 /// ```
@@ -47,26 +510,101 @@ const MARKDOWN_RE: &str = r#"((?msx)
 ///
 /// let snip = Snippet::from_markdown(markdown);
 /// assert_eq!(snip.get_identifier(), &String::from("a"));
-/// assert_eq!(snip.get_prefix(), &String::from("b"));
+/// assert_eq!(snip.get_prefix(), "b");
 /// assert_eq!(snip.get_scope(), &String::from("rust"));
 /// assert_eq!(snip.get_description(), &vec![String::from("description")]);
 /// assert_eq!(snip.get_body(), &vec![String::from("body")]);
-/// ```
-#[derive(Debug)]
+/// ~~~
+#[derive(Debug, Clone)]
 pub struct Snippet {
     identifier: String,
     body: SnippetBody,
+    /// 由 `<!-- mdppet:output 文件名 -->` 或标题中的 `@file 文件名` 指定的输出文件，
+    /// 只在 `--out-dir` 模式下生效
+    output: Option<String>,
+    /// 标题下方 `[key=value]` 行中的元数据，不写入输出
+    metadata: BTreeMap<String, String>,
+    /// 由 `<!-- mdppet:extends 标识符 -->` 指定的基础 Snippet
+    extends: Option<String>,
+    /// 标题带有 `@draft` 时为真：只供其他 Snippet 继承，本身不写入输出
+    draft: bool,
+    /// 标题带有 `@deprecated` 时为真，按 `--deprecated` 跳过或在描述中注明
+    deprecated: bool,
+    /// 由 `<!-- mdppet:commentify -->` 指定，展开继承之后按语言加上注释前缀，见 `Snippet::commentify`
+    commentify: bool,
+    /// 标题带有 `@dedent` 时为真，补全体在解析时已去掉缩进，`to_markdown` 据此写出该标记
+    dedented: bool,
+    /// 标题带有 `@variants` 时为真，在解析时处理，`to_markdown` 据此写出该标记
+    variants: bool,
+    /// 代码块信息字符串中的语言，按语言拆分时为各自代码块的语言，见 `foreign_fence_lang`
+    fence_lang: Option<String>,
+    /// 代码块信息字符串中语言之后的属性，见 `BodyOptions::with_attrs`
+    fence_attrs: String,
+}
+
+/// 比较除元数据、代码块的信息字符串和只在解析时起作用的标记之外的所有字段：
+/// 元数据不写入 VSCode 的输出，只有元数据不同的同名 Snippet 不算作冲突；代码块的语言只用于检查，
+/// 由 `to_markdown` 重新生成的、或从别处复制而只改了代码块语言的 Snippet 仍视为相同；
+/// 代码块的属性、`@dedent`、`@variants` 和 `commentify` 在解析结束之前就已作用于补全体
+impl PartialEq for Snippet {
+    fn eq(&self, other: &Self) -> bool {
+        return self.key() == other.key();
+    }
+}
+
+impl Eq for Snippet {}
+
+impl Hash for Snippet {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct SnippetBody {
-    prefix: String,
+    /// 标题中以 `,` 分隔的多个前缀，见 `serialize_prefix`
+    #[serde(
+        serialize_with = "serialize_prefix",
+        deserialize_with = "deserialize_prefix"
+    )]
+    prefix: Vec<String>,
     scope: String,
     body: Vec<String>,
+    /// 没有描述时不输出该字段
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     description: Vec<String>,
+    /// 标题带有 `@file-template` 时为真，只在为真时输出
+    #[serde(rename = "isFileTemplate", default, skip_serializing_if = "is_false")]
+    is_file_template: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    return !*value;
 }
 
 impl Snippet {
+    /// 参与比较和散列的字段
+    #[allow(clippy::type_complexity)]
+    fn key(
+        &self,
+    ) -> (
+        &String,
+        &SnippetBody,
+        &Option<String>,
+        &Option<String>,
+        bool,
+        bool,
+    ) {
+        return (
+            &self.identifier,
+            &self.body,
+            &self.output,
+            &self.extends,
+            self.draft,
+            self.deprecated,
+        );
+    }
+
     pub fn new(
         identifier: &str,
         prefix: &str,
@@ -87,20 +625,52 @@ impl Snippet {
             description_new.push(String::from(*i));
         }
 
-        let body = SnippetBody::new(prefix_new, scope_new, body_new, description_new);
+        let body = SnippetBody::new(
+            split_prefixes(&prefix_new),
+            scope_new,
+            body_new,
+            description_new,
+        );
         Snippet {
             identifier: identifier_new,
             body,
+            output: None,
+            metadata: BTreeMap::new(),
+            extends: None,
+            draft: false,
+            deprecated: false,
+            commentify: false,
+            dedented: false,
+            variants: false,
+            fence_lang: None,
+            fence_attrs: String::new(),
         }
     }
 
+    /// 由各字段的文本构造 Snippet，并检查字段，见 `check_fields`
+    ///
+    /// 与从 Markdown 标题中解析出的一样，标识符去掉首尾的空白；只含空白的描述视为没有描述。
     pub fn from_text(
         identifier: &str,
         prefix: &str,
         scope: &str,
         body: &str,
         description: &str,
+    ) -> Result<Self, MdppetError> {
+        let snip = Snippet::from_text_unchecked(identifier, prefix, scope, body, description);
+        snip.check_fields()?;
+        return Ok(snip);
+    }
+
+    /// 同 `from_text`，但不检查字段；从 Markdown 解析时在整理补全体之后才检查
+    fn from_text_unchecked(
+        identifier: &str,
+        prefix: &str,
+        scope: &str,
+        body: &str,
+        description: &str,
     ) -> Self {
+        let identifier = identifier.trim();
         let body = body.trim_end();
         let description = description.trim_end();
         let mut body_v: Vec<String> = Vec::new();
@@ -108,12 +678,14 @@ impl Snippet {
             body_v.push(String::from(i));
         }
         let mut description_v: Vec<String> = Vec::new();
-        for i in description.split("\n") {
-            description_v.push(String::from(i));
+        if !description.trim().is_empty() {
+            for i in description.split("\n") {
+                description_v.push(String::from(i));
+            }
         }
 
         let body = SnippetBody::new(
-            String::from(prefix),
+            split_prefixes(prefix),
             String::from(scope),
             body_v,
             description_v,
@@ -121,18 +693,170 @@ impl Snippet {
         Snippet {
             identifier: String::from(identifier),
             body,
+            output: None,
+            metadata: BTreeMap::new(),
+            extends: None,
+            draft: false,
+            deprecated: false,
+            commentify: false,
+            dedented: false,
+            variants: false,
+            fence_lang: None,
+            fence_attrs: String::new(),
         }
     }
 
     pub fn from_markdown(text: &str) -> Self {
-        let re = Regex::new(MARKDOWN_RE).unwrap();
+        return Snippet::from_markdown_with(text, &ParseOptions::default());
+    }
+
+    /// 同 `from_markdown`，按 `options` 解析
+    ///
+    /// `text` 不是一个 Snippet 片段，或标题中的标记有误时 panic，见 `from_markdown_in_group`。
+    pub fn from_markdown_with(text: &str, options: &ParseOptions) -> Self {
+        return Snippet::from_markdown_in_group(text, options, None)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// 同 `from_markdown_with`，标题中没有作用域时使用所在分组的 `group_scope`
+    ///
+    /// 标题中的标记有误时返回错误，见 `apply_flags`。
+    pub fn from_markdown_in_group(
+        text: &str,
+        options: &ParseOptions,
+        group_scope: Option<&str>,
+    ) -> Result<Self, String> {
+        // 手工切出的片段前后可能带有空行或缩进
+        let text = text.trim();
+        let re = options.markdown_re();
         let m = re.captures(text).unwrap();
         let id = m.name("id").unwrap().as_str();
         let prefix = m.name("prefix").unwrap().as_str();
-        let scope = m.name("scope").unwrap().as_str();
-        let body = m.name("body").unwrap().as_str();
-        let description = m.name("description").unwrap().as_str();
-        return Snippet::from_text(id, prefix, scope, body, description);
+        let scope = match m.name("scope") {
+            Some(scope) => scope.as_str(),
+            None => group_scope.unwrap_or(""),
+        };
+        let fence = Fence::from_captures(&m);
+        let description = m.name("description").map_or("", |d| d.as_str());
+        let mut snip = Snippet::from_text_unchecked(id, prefix, scope, fence.body, description);
+        snip.body.body = fence.body_lines(&options.body);
+        snip.fence_lang = fence.lang.map(String::from);
+        snip.fence_attrs = String::from(fence.attrs);
+        if id.is_empty() {
+            snip.identifier = synthesize_identifier(&snip.body.prefix[0], &snip.body.body);
+        }
+        snip.metadata = parse_metadata(m.name("metadata").map_or("", |m| m.as_str()));
+        snip.apply_flags(m.name("flags").map_or("", |flags| flags.as_str()))?;
+        return Ok(snip);
+    }
+
+    /// 按片段的原文构造 Snippet，`m` 为 `ParseOptions::markdown_re` 的匹配结果
+    ///
+    /// 用于 `mdppet fmt` 经 `to_markdown` 重新写出片段：与 `from_markdown_in_group` 不同，
+    /// 补全体保持第一个代码块的原文，省略的标识符和作用域保持为空，`@dedent` 只记录下来而不去掉缩进。
+    pub fn from_segment(m: &regex::Captures) -> Self {
+        let fence = Fence::from_captures(m);
+        let scope = m.name("scope").map_or("", |scope| scope.as_str());
+        let description = m.name("description").map_or("", |d| d.as_str());
+        let mut snip = Snippet::from_text_unchecked(&m["id"], &m["prefix"], scope, "", description);
+        snip.metadata = parse_metadata(m.name("metadata").map_or("", |m| m.as_str()));
+        // 有误的标记使原文无法解析，由 `fmt` 对解析结果的比对发现
+        let _ = snip.apply_flags(m.name("flags").map_or("", |flags| flags.as_str()));
+        snip.body.body = match fence.body.strip_suffix('\n') {
+            Some(body) => body.split('\n').map(String::from).collect(),
+            None => Vec::new(),
+        };
+        snip.fence_lang = fence.lang.map(String::from);
+        snip.fence_attrs = String::from(fence.attrs);
+        return snip;
+    }
+
+    /// 检查 VSCode 虽然接受、但无法使用或容易混淆的字段
+    ///
+    /// - 前缀为空：只由 `,`、`-` 和空白组成的前缀无法触发补全
+    /// - 补全体为空：去掉空白之后没有内容
+    /// - 输出文件是绝对路径或含有 `..`：会写到输出目录之外
+    pub fn check_fields(&self) -> Result<(), MdppetError> {
+        let blank = |prefix: &String| {
+            prefix
+                .trim_matches(|c: char| c == ',' || c == '-' || c.is_whitespace())
+                .is_empty()
+        };
+        if self.body.prefix.iter().all(blank) {
+            return Err(MdppetError::EmptyPrefix(self.identifier.clone()));
+        }
+        if self.body.body.iter().all(|line| line.trim().is_empty()) {
+            return Err(MdppetError::EmptyBody(self.identifier.clone()));
+        }
+        if let Some(output) = &self.output {
+            let escapes = Path::new(output)
+                .components()
+                .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+            if escapes {
+                return Err(MdppetError::UnsafeOutput {
+                    identifier: self.identifier.clone(),
+                    output: output.clone(),
+                });
+            }
+        }
+        return Ok(());
+    }
+
+    /// 应用标题中作用域之后的标记，无法识别的标记会被忽略；
+    /// 只有 `@file` 带有参数，其他标记之后跟着参数时，应用其余的标记并返回错误
+    ///
+    /// - `@dedent`：去掉补全体各行共同的缩进，见 `dedent`
+    /// - `@file-template`：作为新建文件时的模板，输出 `"isFileTemplate": true`
+    /// - `@file <文件名>`：与 `<!-- mdppet:output 文件名 -->` 指令相同，写入输出目录下的该文件
+    /// - `@draft`：只供其他 Snippet 继承，本身不写入输出，见 `resolve_extends`
+    /// - `@deprecated`：已弃用，见 `Deprecated`
+    /// - `@variants`：其后的代码块总是按语言拆分，在解析时处理，见 `has_variant_fences`
+    pub fn apply_flags(&mut self, flags: &str) -> Result<(), String> {
+        let mut error: Option<String> = None;
+        let mut tokens = flags.split_whitespace().peekable();
+        while let Some(flag) = tokens.next() {
+            let argument = tokens.next_if(|token| !token.starts_with('@'));
+            match (flag, argument) {
+                ("@file", _) | (_, None) => {}
+                (_, Some(argument)) => {
+                    error.get_or_insert(format!(
+                        "snippet `{}`: `{}` does not take an argument, but `{}` follows it",
+                        self.identifier, flag, argument
+                    ));
+                    continue;
+                }
+            }
+            match (flag, argument) {
+                ("@dedent", _) => {
+                    self.dedented = true;
+                    self.dedent();
+                }
+                ("@file-template", _) => self.body.is_file_template = true,
+                ("@file", Some(path)) => self.output = Some(String::from(path)),
+                ("@draft", _) => self.draft = true,
+                ("@deprecated", _) => self.deprecated = true,
+                ("@variants", _) => self.variants = true,
+                _ => {}
+            }
+        }
+        return error.map_or(Ok(()), Err);
+    }
+
+    /// 去掉补全体中非空行共同的最小缩进（空格和制表符都算作一个字符）
+    pub fn dedent(&mut self) {
+        let indent = |line: &str| line.len() - line.trim_start_matches([' ', '\t']).len();
+        let common = self
+            .body
+            .body
+            .iter()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| indent(line))
+            .min()
+            .unwrap_or(0);
+        for line in self.body.body.iter_mut() {
+            let strip = common.min(indent(line));
+            line.drain(..strip);
+        }
     }
 
     pub fn get_identifier(&self) -> &String {
@@ -141,82 +865,1397 @@ impl Snippet {
     pub fn get_snippetbody(&self) -> &SnippetBody {
         return &self.body;
     }
-    pub fn get_prefix(&self) -> &String {
+    /// 以 `,` 连接的前缀，与标题中的写法相同
+    pub fn get_prefix(&self) -> String {
+        return self.body.prefix.join(",");
+    }
+    /// 逐个返回前缀，VSCode 中任一前缀都可以触发补全
+    pub fn prefixes(&self) -> &[String] {
         return &self.body.prefix;
     }
     pub fn get_scope(&self) -> &String {
         return &self.body.scope;
     }
+    /// 逐个返回作用域
+    ///
+    /// 标题中的作用域以 `,` 分隔，例如 `python,lua`；
+    /// `get_scope` 仍返回原始的连接形式。
+    pub fn scopes(&self) -> impl Iterator<Item = &str> {
+        return self
+            .body
+            .scope
+            .split(',')
+            .map(|scope| scope.trim())
+            .filter(|scope| !scope.is_empty());
+    }
+    /// 是否有任一作用域出现在 `filter` 中，语言别名视为相同（`rs` 即 `rust`）
+    ///
+    /// 对应 `--include-scopes` 和 `--exclude-scopes`。
+    pub fn in_scopes<S: AsRef<str>>(&self, filter: &[S]) -> bool {
+        let normalize = |scope: &str| lang::resolve(scope).unwrap_or(scope).to_lowercase();
+        let filter: Vec<String> = filter
+            .iter()
+            .map(|scope| normalize(scope.as_ref()))
+            .collect();
+        return self
+            .scopes()
+            .any(|scope| filter.contains(&normalize(scope)));
+    }
+    /// 元数据 `[tags=a,b]` 中是否有任一标签出现在 `filter` 中，用于 `mdppet.toml` 中目标的 `tags`
+    pub fn has_tag<S: AsRef<str>>(&self, filter: &[S]) -> bool {
+        let tags = match self.metadata.get("tags") {
+            Some(tags) => tags,
+            None => return false,
+        };
+        return tags
+            .split(',')
+            .map(|tag| tag.trim())
+            .any(|tag| filter.iter().any(|wanted| wanted.as_ref() == tag));
+    }
+    /// 前缀是其关键字的语言：`--warn-keyword-prefixes`
+    pub fn keyword_prefix_scopes(&self) -> Vec<&'static str> {
+        return self
+            .scopes()
+            .filter_map(lang::resolve)
+            .filter(|&id| {
+                self.body
+                    .prefix
+                    .iter()
+                    .any(|prefix| lang::keywords(id).contains(&prefix.as_str()))
+            })
+            .collect();
+    }
+    /// 代码块标注的语言是已知的语言、却不在作用域之中时返回该语言，通常是复制别的 Snippet 后忘了修改
+    ///
+    /// 作用域为空、代码块没有标注语言、语言无法识别或是纯文本（`text`）时不检查；
+    /// 作用域和语言的别名视为相同。
+    pub fn foreign_fence_lang(&self) -> Option<&'static str> {
+        let lang = lang::resolve(self.fence_lang.as_deref()?)?;
+        if lang == "plaintext"
+            || self.scopes().next().is_none()
+            || self
+                .scopes()
+                .any(|scope| lang::resolve(scope).unwrap_or(scope) == lang)
+        {
+            return None;
+        }
+        return Some(lang);
+    }
+    /// 见 `foreign_fence_lang`，将代码块标注的语言加入作用域：`--fix-scope-from-fence`
+    pub fn fix_scope_from_fence(&mut self) {
+        if let Some(lang) = self.foreign_fence_lang() {
+            let mut scopes: Vec<&str> = self.scopes().collect();
+            scopes.push(lang);
+            self.body.scope = scopes.join(",");
+        }
+    }
+    pub fn get_fence_lang(&self) -> Option<&str> {
+        return self.fence_lang.as_deref();
+    }
+    pub fn get_output(&self) -> Option<&str> {
+        return self.output.as_deref();
+    }
+    pub fn get_metadata(&self) -> &BTreeMap<String, String> {
+        return &self.metadata;
+    }
+    pub fn get_extends(&self) -> Option<&str> {
+        return self.extends.as_deref();
+    }
+    pub fn is_draft(&self) -> bool {
+        return self.draft;
+    }
+    pub fn is_deprecated(&self) -> bool {
+        return self.deprecated;
+    }
     pub fn get_body(&self) -> &Vec<String> {
         return &self.body.body;
     }
     pub fn get_description(&self) -> &Vec<String> {
         return &self.body.description;
     }
-}
 
-impl SnippetBody {
-    pub fn new(prefix: String, scope: String, body: Vec<String>, description: Vec<String>) -> Self {
-        SnippetBody {
-            prefix,
-            scope,
-            body,
-            description,
+    /// 列出与 `other` 不同的字段，每一项形如 `prefix: "a" != "b"`
+    ///
+    /// 涵盖参与比较的所有字段（见 `key`），两个 Snippet 不相等时结果不为空。
+    pub fn diff(&self, other: &Snippet) -> Vec<String> {
+        let mut fields: Vec<String> = Vec::new();
+        if self.identifier != other.identifier {
+            fields.push(format!(
+                "identifier: {:?} != {:?}",
+                self.identifier, other.identifier
+            ));
+        }
+        if self.body.prefix != other.body.prefix {
+            fields.push(format!(
+                "prefix: {:?} != {:?}",
+                self.body.prefix, other.body.prefix
+            ));
+        }
+        if self.body.scope != other.body.scope {
+            fields.push(format!(
+                "scope: {:?} != {:?}",
+                self.body.scope, other.body.scope
+            ));
+        }
+        if self.body.body != other.body.body {
+            fields.push(format!(
+                "body: {:?} != {:?}",
+                self.body.body, other.body.body
+            ));
         }
+        if self.body.description != other.body.description {
+            fields.push(format!(
+                "description: {:?} != {:?}",
+                self.body.description, other.body.description
+            ));
+        }
+        if self.body.is_file_template != other.body.is_file_template {
+            fields.push(format!(
+                "isFileTemplate: {:?} != {:?}",
+                self.body.is_file_template, other.body.is_file_template
+            ));
+        }
+        if self.output != other.output {
+            fields.push(format!("output: {:?} != {:?}", self.output, other.output));
+        }
+        if self.extends != other.extends {
+            fields.push(format!(
+                "extends: {:?} != {:?}",
+                self.extends, other.extends
+            ));
+        }
+        if self.draft != other.draft {
+            fields.push(format!("draft: {:?} != {:?}", self.draft, other.draft));
+        }
+        if self.deprecated != other.deprecated {
+            fields.push(format!(
+                "deprecated: {:?} != {:?}",
+                self.deprecated, other.deprecated
+            ));
+        }
+        return fields;
     }
-}
 
-pub fn get_snippet_segments<'a>(text: &'a String) -> Vec<&'a str> {
-    let mut segments: Vec<&str> = Vec::new();
-    let re = Regex::new(MARKDOWN_RE).unwrap();
-    for segment in re.find_iter(text.as_str()) {
-        segments.push(segment.as_str());
+    /// 按 `options` 将该 Snippet 写回 Markdown，用于 `--format markdown-index`
+    ///
+    /// 结果可以由 `from_markdown_with`（标题级别与 `options` 相同）解析回相等的 Snippet。
+    /// 补全体中含有所选的围栏时改用另一种围栏，两种都含有时无法保证能解析回来；
+    /// 继承关系已在解析时展开，不再写出。
+    pub fn to_markdown(&self, options: &RenderOptions) -> String {
+        let scopes: Vec<&str> = self.scopes().collect();
+        let mut heading = format!(
+            "{} {}{}{}",
+            "#".repeat(options.heading_level),
+            self.identifier,
+            options.field_sep,
+            self.get_prefix()
+        );
+        // 作用域由分组标题给出时标题中没有作用域
+        if !scopes.is_empty() {
+            heading.push_str(&options.field_sep);
+            heading.push_str(&scopes.join(","));
+        }
+        if self.dedented {
+            heading.push_str(" @dedent");
+        }
+        if self.body.is_file_template {
+            heading.push_str(" @file-template");
+        }
+        if let Some(output) = &self.output {
+            heading.push_str(&format!(" @file {}", output));
+        }
+        if self.draft {
+            heading.push_str(" @draft");
+        }
+        if self.deprecated {
+            heading.push_str(" @deprecated");
+        }
+        if self.variants {
+            heading.push_str(" @variants");
+        }
+        let mut parts: Vec<String> = vec![heading];
+        if !self.metadata.is_empty() {
+            let lines: Vec<String> = self
+                .metadata
+                .iter()
+                .map(|(key, value)| format!("[{}={}]", key, value))
+                .collect();
+            parts.push(lines.join("\n"));
+        }
+        if !self.body.description.is_empty() {
+            parts.push(self.body.description.join("\n"));
+        }
+        let fence_lang = self.fence_lang.as_deref();
+        let lang = match options.info_string {
+            InfoString::FirstScope => scopes.first().copied().or(fence_lang),
+            InfoString::Fence => fence_lang,
+            InfoString::None => None,
+        };
+        parts.push(render_fence(
+            options.fence,
+            lang.unwrap_or(""),
+            &self.fence_attrs,
+            &self
+                .body
+                .body
+                .iter()
+                .map(|line| format!("{}\n", line))
+                .collect::<String>(),
+        ));
+        let separator = "\n".repeat(options.blank_lines.max(1) + 1);
+        return parts.join(&separator) + "\n";
     }
-    return segments;
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use regex::Regex;
-    use std::fs::File;
-    use std::io::BufReader;
-    use std::io::Read;
-    use std::path::Path;
-    #[test]
-    fn test_markdown_re() {
-        let re = Regex::new(MARKDOWN_RE).unwrap();
-        let mut text: String = String::new();
 
-        {
-            let md1_path = Path::new("tests/test_markdown_re_text.1.md");
-            let md1_file = File::open(md1_path).unwrap();
-            let mut md1_reader = BufReader::new(md1_file);
-            md1_reader.read_to_string(&mut text).unwrap();
+    /// 为作用域中的每种语言生成一个 Snippet，补全体的每一行加上该语言的行注释前缀
+    ///
+    /// 对应 `<!-- mdppet:commentify -->`，在展开继承之后进行，继承来的行同样加上前缀。
+    /// 标识符加上 `-<作用域>`，作用域缩小为对应的语言；
+    /// 已经以该前缀（忽略缩进和前缀末尾的空白）开头的行保持不变，空行只加上去掉末尾空白的前缀。
+    /// 前缀见 `ParseOptions::comment_leader`，作用域为空或有语言没有前缀时返回错误。
+    pub fn commentify(&self, options: &ParseOptions) -> Result<Vec<Snippet>, String> {
+        if self.scopes().next().is_none() {
+            return Err(format!(
+                "snippet `{}` uses mdppet:commentify but has no scope",
+                self.identifier
+            ));
+        }
+        let mut variants: Vec<Snippet> = Vec::new();
+        for scope in self.scopes() {
+            let leader = options.comment_leader(scope).ok_or_else(|| {
+                format!(
+                    "no line comment leader is known for `{}` in snippet `{}`; add one with --comment-leader {}=...",
+                    scope, self.identifier, scope
+                )
+            })?;
+            let bare = leader.trim_end();
+            let mut variant = self.clone();
+            variant.commentify = false;
+            variant.identifier = format!("{}-{}", self.identifier, scope);
+            variant.body.scope = String::from(scope);
+            // 补全体由该语言的注释前缀生成，与代码块标注的语言无关；
+            // 按语言拆分出的 Snippet 保留各自代码块的语言，位置表据此找到对应的代码块
+            let resolve = |lang: &str| lang::resolve(lang).map(String::from);
+            variant.fence_lang = self
+                .fence_lang
+                .clone()
+                .filter(|lang| resolve(lang).is_some() && resolve(lang) == resolve(scope));
+            for line in variant.body.body.iter_mut() {
+                if line.trim().is_empty() {
+                    *line = String::from(bare);
+                } else if !line.trim_start().starts_with(bare) {
+                    line.insert_str(0, leader);
+                }
+            }
+            variants.push(variant);
         }
+        return Ok(variants);
+    }
 
-        assert_eq!(re.is_match(text.as_str()), true);
+    /// 继承已展开的基础 Snippet `base`
+    ///
+    /// 补全体代入 `base` 补全体中的 `{{slot}}`，该标记独占一行时每一行都带上它的缩进；
+    /// 没有该标记时接在 `base` 的补全体之后。没有描述或作用域时沿用 `base` 的，
+    /// 元数据中缺少的键也从 `base` 补齐。
+    pub fn inherit(&mut self, base: &Snippet) {
+        let own = std::mem::take(&mut self.body.body);
+        let mut body: Vec<String> = Vec::new();
+        let mut slotted = false;
+        for line in base.body.body.iter() {
+            let (before, after) = match line.split_once(SLOT_MARKER) {
+                Some(parts) => parts,
+                None => {
+                    body.push(line.clone());
+                    continue;
+                }
+            };
+            slotted = true;
+            let indent = if before.trim().is_empty() { before } else { "" };
+            let mut lines: Vec<String> = Vec::new();
+            for (index, own_line) in own.iter().enumerate() {
+                if index == 0 {
+                    lines.push(format!("{}{}", before, own_line));
+                } else if own_line.is_empty() {
+                    lines.push(String::new());
+                } else {
+                    lines.push(format!("{}{}", indent, own_line));
+                }
+            }
+            match lines.last_mut() {
+                Some(last) => last.push_str(after),
+                None => lines.push(format!("{}{}", before, after)),
+            }
+            body.extend(lines);
+        }
+        if !slotted {
+            body.extend(own);
+        }
+        self.body.body = body;
+        if self.body.description.is_empty() {
+            self.body.description = base.body.description.clone();
+        }
+        if self.body.scope.is_empty() {
+            self.body.scope = base.body.scope.clone();
+        }
+        for (key, value) in base.metadata.iter() {
+            self.metadata
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+    }
 
-        let m = re.captures(text.as_str()).unwrap();
-        assert_eq!(m.name("id").unwrap().as_str(), "hello");
-        assert_eq!(m.name("prefix").unwrap().as_str(), "hello");
-        assert_eq!(m.name("scope").unwrap().as_str(), "rust");
-        assert_eq!(
-            m.name("description").unwrap().as_str(),
-            "Rust 的 HelloWorld 代码\n"
-        );
-        assert_eq!(
-            m.name("body").unwrap().as_str(),
-            "println!(\"Hello World!\");\n"
-        );
+    /// 将描述中的行内 Markdown（强调、行内代码、链接）转换为纯文本
+    ///
+    /// 对应 `--plain-descriptions`。
+    pub fn plain_descriptions(&mut self) {
+        for line in self.body.description.iter_mut() {
+            *line = strip_inline_markdown(line);
+        }
     }
-    #[test]
-    fn test_snip_from_markdown() {
-        let text = read_text("tests/test_markdown_re_text.1.md");
 
-        let snip = Snippet::from_markdown(text.as_str());
+    /// 代码块是否像是各语言的版本：都标注了语言，语言各不相同，且都在作用域之中
+    ///
+    /// 标题之后跟随多个代码块时，只有满足这一条件或标题带有 `@variants` 时才按语言拆分，
+    /// 否则其后的代码块不属于该 Snippet。
+    pub fn has_variant_fences(&self, fences: &[Fence]) -> bool {
+        let scopes: Vec<&str> = self
+            .scopes()
+            .map(|scope| lang::resolve(scope).unwrap_or(scope))
+            .collect();
+        let mut langs: Vec<&str> = Vec::new();
+        for fence in fences.iter() {
+            let lang = match fence.lang {
+                Some(lang) => lang::resolve(lang).unwrap_or(lang),
+                None => return false,
+            };
+            if !scopes.contains(&lang) || langs.contains(&lang) {
+                return false;
+            }
+            langs.push(lang);
+        }
+        return true;
+    }
+
+    /// 按代码块的语言拆分为多个 Snippet
+    ///
+    /// 如 `# license/lic/python,rust` 之后跟随 ```` ```python ```` 和 ```` ```rust ````
+    /// 两个代码块时，生成 `license-python` 和 `license-rust`，作用域分别缩小为对应的语言。
+    /// 每个作用域与语言相同的代码块配对，按标题中作用域的顺序生成，与代码块的顺序无关；
+    /// 标题没有作用域时按代码块的顺序生成。带有 `mdppet:commentify` 时标识符保持不变，
+    /// 由 `commentify` 加上语言后缀。
+    /// 没有对应代码块的作用域使用唯一一个没有标注语言的代码块，没有这样的代码块时使用第一个代码块。
+    /// 所有代码块都没有标注语言时保持原样，只使用第一个代码块；
+    /// 语言不在作用域之内、同一语言有多个代码块，或有多个没有标注语言的代码块而无法确定共用哪一个时报错。
+    pub fn split_variants(
+        self,
+        fences: &[Fence],
+        options: &BodyOptions,
+    ) -> Result<Vec<Snippet>, String> {
+        if fences.iter().all(|fence| fence.lang.is_none()) {
+            return Ok(vec![self]);
+        }
+        let scopes: Vec<&str> = self
+            .scopes()
+            .map(|scope| lang::resolve(scope).unwrap_or(scope))
+            .collect();
+        let mut shared = fences.iter().filter(|fence| fence.lang.is_none());
+        let fallback = match (shared.next(), shared.next()) {
+            (Some(_), _) if scopes.is_empty() => {
+                return Err(format!(
+                    "snippet `{}` mixes code blocks with and without a language",
+                    self.identifier
+                ))
+            }
+            (Some(_), Some(_)) => {
+                return Err(format!(
+                    "snippet `{}` has more than one code block without a language, so it is unclear which one its other scopes share",
+                    self.identifier
+                ))
+            }
+            (Some(fence), None) => fence,
+            (None, _) => &fences[0],
+        };
+        let mut paired: Vec<(&str, &Fence)> = Vec::new();
+        for fence in fences.iter() {
+            let lang = match fence.lang {
+                Some(lang) => lang,
+                None => continue,
+            };
+            let scope = lang::resolve(lang).unwrap_or(lang);
+            if !scopes.is_empty() && !scopes.contains(&scope) {
+                return Err(format!(
+                    "snippet `{}`: code block language `{}` is not one of its scopes ({})",
+                    self.identifier, lang, self.body.scope
+                ));
+            }
+            if paired.iter().any(|(paired, _)| *paired == scope) {
+                return Err(format!(
+                    "snippet `{}` has more than one `{}` code block",
+                    self.identifier, scope
+                ));
+            }
+            paired.push((scope, fence));
+        }
+        if !scopes.is_empty() {
+            let mut ordered: Vec<(&str, &Fence)> = Vec::new();
+            for scope in scopes.iter() {
+                match paired.iter().find(|(paired, _)| paired == scope) {
+                    Some(pair) => ordered.push(*pair),
+                    None => ordered.push((scope, fallback)),
+                }
+            }
+            paired = ordered;
+        }
+        let mut variants: Vec<Snippet> = Vec::new();
+        for (scope, fence) in paired {
+            let body = fence.body_lines(options);
+            let identifier = match self.commentify {
+                true => self.identifier.clone(),
+                false => format!("{}-{}", self.identifier, scope),
+            };
+            variants.push(Snippet {
+                identifier,
+                body: SnippetBody::new(
+                    self.body.prefix.clone(),
+                    String::from(scope),
+                    body,
+                    self.body.description.clone(),
+                ),
+                output: self.output.clone(),
+                metadata: self.metadata.clone(),
+                extends: self.extends.clone(),
+                draft: self.draft,
+                deprecated: self.deprecated,
+                commentify: self.commentify,
+                dedented: self.dedented,
+                // 拆分之后各自只有一个代码块
+                variants: false,
+                // 共用其他代码块的作用域不记录代码块的语言，以免被当作标错了语言
+                fence_lang: fence
+                    .lang
+                    .filter(|lang| lang::resolve(lang).unwrap_or(lang) == scope)
+                    .map(String::from),
+                fence_attrs: String::from(fence.attrs),
+            });
+        }
+        return Ok(variants);
+    }
+
+    /// 将补全体中未定义的 `{{name}}` 占位符转换为 tabstop
+    ///
+    /// 对应 `--template-placeholders`，须在 `expand_vars` 之前调用。
+    pub fn template_placeholders(&mut self, vars: &Vars) {
+        crate::vars::to_tabstops(&mut self.body.body, vars);
+    }
+
+    /// 展开描述和补全体中的构建时变量 `{{name}}`
+    ///
+    /// 在 `--plain-descriptions` 等变换之前进行，因此变量的值可以包含 VSCode 的 `$1`、`${name}` 等语法。
+    pub fn expand_vars(&mut self, vars: &Vars) -> Result<(), MdppetError> {
+        let identifier = &self.identifier;
+        let lines = self
+            .body
+            .description
+            .iter_mut()
+            .chain(self.body.body.iter_mut());
+        for line in lines {
+            *line = vars
+                .expand(line)
+                .map_err(|name| MdppetError::UndefinedVariable {
+                    identifier: identifier.clone(),
+                    name,
+                })?;
+        }
+        return Ok(());
+    }
+
+    /// 按 `renames` 更换输出中的标识符：`--rename`，不在其中的标识符保持不变
+    pub fn rename(&mut self, renames: &BTreeMap<String, String>) {
+        if let Some(identifier) = renames.get(&self.identifier) {
+            self.identifier = identifier.clone();
+        }
+    }
+
+    /// 没有描述时按 `mode` 补上默认的描述：`--default-description`
+    pub fn default_description(&mut self, mode: DefaultDescription) {
+        if !self.body.description.is_empty() {
+            return;
+        }
+        match mode {
+            DefaultDescription::None => {}
+            DefaultDescription::Id => self.body.description = vec![self.identifier.clone()],
+            DefaultDescription::Prefix => self.body.description = vec![self.get_prefix()],
+        }
+    }
+
+    /// 在描述的最后一行末尾注明 `(deprecated)`，没有描述时以它作为描述
+    ///
+    /// 对应 `--deprecated annotate`，须在 `default_description` 之后调用。
+    pub fn annotate_deprecated(&mut self) {
+        match self.body.description.last_mut() {
+            Some(line) => line.push_str(" (deprecated)"),
+            None => self.body.description = vec![String::from("(deprecated)")],
+        }
+    }
+
+    /// 检查补全体中会让编辑器出错的内容，不需要任何选项即会进行
+    ///
+    /// - 制表符以外的 ASCII 控制字符给出警告，每行只报告第一个，CRLF 行尾的 `\r` 不算在内；
+    /// - 行数超过 `max_lines` 时给出警告，为 0 时不检查；
+    /// - NUL 字节说明文件已经损坏，作为错误返回。
+    ///
+    /// 返回警告的代码和说明，以及错误；行号是补全体中的行号，位置是该行中的字节偏移，都从 1 开始。
+    pub fn check_body(&self, max_lines: usize) -> (Vec<(&'static str, String)>, Vec<MdppetError>) {
+        let mut warnings: Vec<(&'static str, String)> = Vec::new();
+        let mut errors: Vec<MdppetError> = Vec::new();
+        for (index, line) in self.body.body.iter().enumerate() {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            if let Some(offset) = line.find('\0') {
+                errors.push(MdppetError::NulByte {
+                    identifier: self.identifier.clone(),
+                    line: index + 1,
+                    column: offset + 1,
+                });
+                continue;
+            }
+            let control = line
+                .char_indices()
+                .find(|(_, c)| c.is_ascii_control() && *c != '\t');
+            if let Some((offset, c)) = control {
+                warnings.push((
+                    "control-character",
+                    format!(
+                        "snippet `{}` has control character 0x{:02X} in line {} of its body at byte {}; editors may not insert it correctly",
+                        self.identifier,
+                        c as u32,
+                        index + 1,
+                        offset + 1
+                    ),
+                ));
+            }
+        }
+        if max_lines > 0 && self.body.body.len() > max_lines {
+            warnings.push((
+                "long-body",
+                format!(
+                    "snippet `{}` has {} body lines, more than the limit of {} (--max-body-lines)",
+                    self.identifier,
+                    self.body.body.len(),
+                    max_lines
+                ),
+            ));
+        }
+        return (warnings, errors);
+    }
+
+    /// 将补全体中字面的 `\n` 拆分为多行，`\t` 替换为制表符，`\\` 替换为 `\`
+    ///
+    /// 对应 `--expand-escapes`，其他反斜杠保持原样。
+    pub fn expand_escapes(&mut self) {
+        let mut body: Vec<String> = Vec::new();
+        for line in self.body.body.iter() {
+            let mut current = String::new();
+            let mut chars = line.chars();
+            while let Some(c) = chars.next() {
+                if c != '\\' {
+                    current.push(c);
+                    continue;
+                }
+                match chars.next() {
+                    Some('n') => body.push(std::mem::take(&mut current)),
+                    Some('t') => current.push('\t'),
+                    Some('\\') => current.push('\\'),
+                    Some(other) => {
+                        current.push('\\');
+                        current.push(other);
+                    }
+                    None => current.push('\\'),
+                }
+            }
+            body.push(current);
+        }
+        self.body.body = body;
+    }
+
+    /// 去掉补全体开头和结尾的空白行，中间的空行保持不变
+    ///
+    /// 对应 `--trim-blank-body-lines`。
+    pub fn trim_blank_body_lines(&mut self) {
+        let body = &mut self.body.body;
+        while body.last().is_some_and(|line| line.trim().is_empty()) {
+            body.pop();
+        }
+        let leading = body
+            .iter()
+            .take_while(|line| line.trim().is_empty())
+            .count();
+        body.drain(..leading);
+    }
+}
+
+impl SnippetBody {
+    pub fn new(
+        prefix: Vec<String>,
+        scope: String,
+        body: Vec<String>,
+        description: Vec<String>,
+    ) -> Self {
+        SnippetBody {
+            prefix,
+            scope,
+            body,
+            description,
+            is_file_template: false,
+        }
+    }
+
+    /// 复制一份，作用域替换为 `scope`
+    pub fn with_scope(&self, scope: String) -> Self {
+        let mut body = self.clone();
+        body.scope = scope;
+        return body;
+    }
+
+    /// 按 `renames` 更换前缀：`mdppet rename --prefix`
+    pub fn rename_prefix(&mut self, renames: &BTreeMap<String, String>) {
+        for prefix in self.prefix.iter_mut() {
+            if let Some(renamed) = renames.get(prefix) {
+                *prefix = renamed.clone();
+            }
+        }
+    }
+}
+
+/// 解析标题下方的元数据行，键和值前后的空白被去掉，重复的键以最后一个为准
+pub fn parse_metadata(text: &str) -> BTreeMap<String, String> {
+    let re = Regex::new(METADATA_RE).unwrap();
+    let mut metadata: BTreeMap<String, String> = BTreeMap::new();
+    for m in re.captures_iter(text) {
+        metadata.insert(String::from(m[1].trim()), String::from(m[2].trim()));
+    }
+    return metadata;
+}
+
+/// 为省略了标识符的 Snippet 生成标识符：`{前缀}-{散列}`
+///
+/// 前缀转换为小写，字母和数字之外的字符替换为 `-`；散列取补全体的 FNV-1a 散列的前 8 位十六进制数。
+/// 只由前缀和补全体决定，因此内容不变时每次生成的标识符都相同。
+pub fn synthesize_identifier(prefix: &str, body: &[String]) -> String {
+    let mut slug = String::new();
+    for c in prefix.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    let slug = if slug.is_empty() { "snippet" } else { slug };
+    let hash = fnv1a(0xcbf2_9ce4_8422_2325, body.join("\n").as_bytes());
+    return format!("{}-{:08x}", slug, hash >> 32);
+}
+
+/// 64 位 FNV-1a 散列，`seed` 为初始值
+pub fn fnv1a(seed: u64, bytes: &[u8]) -> u64 {
+    let mut hash = seed;
+    for byte in bytes.iter() {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    return hash;
+}
+
+/// 将标题中的前缀字段按 `,` 拆分为多个前缀，与作用域的写法相同
+pub fn split_prefixes(prefix: &str) -> Vec<String> {
+    let prefixes: Vec<String> = prefix
+        .split(',')
+        .map(str::trim)
+        .filter(|prefix| !prefix.is_empty())
+        .map(String::from)
+        .collect();
+    if prefixes.is_empty() {
+        return vec![String::from(prefix)];
+    }
+    return prefixes;
+}
+
+/// 只有一个前缀时序列化为字符串，有多个时序列化为数组
+pub fn serialize_prefix<S: Serializer>(
+    prefix: &[String],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match prefix {
+        [single] => return serializer.serialize_str(single),
+        _ => return prefix.serialize(serializer),
+    }
+}
+
+/// `serialize_prefix` 的逆操作，读取已生成的输出时使用
+fn deserialize_prefix<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Prefix {
+        One(String),
+        Many(Vec<String>),
+    }
+    match Prefix::deserialize(deserializer)? {
+        Prefix::One(prefix) => return Ok(vec![prefix]),
+        Prefix::Many(prefixes) => return Ok(prefixes),
+    }
+}
+
+pub fn get_snippet_segments(text: &str) -> Vec<&str> {
+    return get_snippet_segments_with(text, &ParseOptions::default());
+}
+
+/// 同 `get_snippet_segments`，按 `options` 切分
+pub fn get_snippet_segments_with<'a>(text: &'a str, options: &ParseOptions) -> Vec<&'a str> {
+    return get_snippet_segment_spans_with(text, options)
+        .into_iter()
+        .map(|(_, segment)| segment)
+        .collect();
+}
+
+/// 同 `get_snippet_segments`，同时返回每个片段在 `text` 中的字节范围，供编辑器定位 Snippet
+pub fn get_snippet_segment_spans(text: &str) -> Vec<(Range<usize>, &str)> {
+    return get_snippet_segment_spans_with(text, &ParseOptions::default());
+}
+
+/// 同 `get_snippet_segment_spans`，按 `options` 切分
+pub fn get_snippet_segment_spans_with<'a>(
+    text: &'a str,
+    options: &ParseOptions,
+) -> Vec<(Range<usize>, &'a str)> {
+    let mut segments: Vec<(Range<usize>, &str)> = Vec::new();
+    for segment in split_segments(text, options).0 {
+        let whole = segment.get(0).unwrap();
+        segments.push((whole.range(), whole.as_str()));
+    }
+    return segments;
+}
+
+/// `text` 中有结尾的代码块所占的范围，从开头的 ```` ``` ```` 或 `~~~` 所在行到同样的结尾所在行
+///
+/// 代码块中形如 Snippet 标题的行只是补全体的内容。但如果这样的行之后的下一个 ```` ``` ```` 带有语言，
+/// 它更可能是下一个 Snippet 的代码块的开头：此时认为当前代码块缺少结尾，该行是真正的标题，
+/// 当前代码块不计入结果，由 `split_segments` 报告为 `UnterminatedFence`。
+fn fence_spans(text: &str, heading_re: &Regex) -> Vec<Range<usize>> {
+    let mut lines: Vec<(usize, &str)> = Vec::new();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        lines.push((offset, line));
+        offset += line.len();
+    }
+    let marker = |line: &str| {
+        let line = line.trim_start();
+        return ["```", "~~~"]
+            .iter()
+            .find(|marker| line.starts_with(*marker))
+            .copied();
+    };
+    let is_fence = |line: &str| marker(line).is_some();
+    let mut spans: Vec<Range<usize>> = Vec::new();
+    let mut open: Option<(usize, &str)> = None;
+    for (index, (offset, line)) in lines.iter().enumerate() {
+        let (start, open_marker) = match open {
+            Some(open) => open,
+            None => {
+                if let Some(marker) = marker(line) {
+                    open = Some((*offset, marker));
+                }
+                continue;
+            }
+        };
+        if marker(line) == Some(open_marker) {
+            spans.push(start..offset + line.len());
+            open = None;
+        } else if heading_re.is_match(line.trim_end_matches(['\r', '\n'])) {
+            let next_fence = lines[index + 1..].iter().find(|(_, line)| is_fence(line));
+            if next_fence.is_some_and(|(_, line)| !line.trim()[3..].trim().is_empty()) {
+                open = None;
+            }
+        }
+    }
+    return spans;
+}
+
+/// 切分出 `text` 中的 Snippet 片段，补全体不会越过下一个 Snippet 标题
+///
+/// 先找出所有代码块的范围（见 `fence_spans`），只有代码块之外的标题才是片段的开头，
+/// 代码块中形如标题的行保持原样。
+/// 正则不支持前瞻，代码块缺少结尾的 ```` ``` ```` 时，非贪婪的补全体会一直延伸到下一个代码块的开头，
+/// 吞掉其间的 Snippet。补全体中出现代码块之外的标题时丢弃该片段，从这个标题处继续切分；
+/// 第二个返回值是这些片段的起始位置和标题行。
+pub fn split_segments<'t>(
+    text: &'t str,
+    options: &ParseOptions,
+) -> (Vec<regex::Captures<'t>>, Vec<(usize, &'t str)>) {
+    let re = options.markdown_re();
+    let heading_re = options.heading_re();
+    let spans = fence_spans(text, &heading_re);
+    let fenced = |offset: usize| spans.iter().find(|span| span.contains(&offset));
+    let mut segments: Vec<regex::Captures> = Vec::new();
+    let mut unterminated: Vec<(usize, &str)> = Vec::new();
+    let mut start = 0;
+    while let Some(m) = re.captures_at(text, start) {
+        let whole = m.get(0).unwrap();
+        let body = fence_group(&m, "body").unwrap();
+        if let Some(span) = fenced(whole.start()) {
+            start = span.end;
+            continue;
+        }
+        let mut inner: Option<regex::Match> = None;
+        let mut from = body.start();
+        while let Some(heading) = heading_re
+            .find_at(text, from)
+            .filter(|heading| heading.start() < body.end())
+        {
+            if fenced(heading.start()).is_none() {
+                inner = Some(heading);
+                break;
+            }
+            from = heading.end();
+        }
+        match inner {
+            Some(heading) => {
+                let line = whole.as_str().lines().next().unwrap_or("");
+                unterminated.push((whole.start(), line.trim_end()));
+                start = heading.start();
+            }
+            None => {
+                start = whole.end();
+                segments.push(m);
+            }
+        }
+    }
+    return (segments, unterminated);
+}
+
+/// 解析 `text` 中的所有 Snippet
+///
+/// 启用分组时，每个 Snippet 从它之前最近的分组标题继承默认作用域；
+/// 无法识别为语言的分组标题会清除默认作用域。
+///
+/// 一个标题之后跟随多个标注了语言的代码块时，每个代码块生成一个 Snippet，
+/// 见 `Snippet::split_variants`。
+pub fn parse_snippets(text: &str, options: &ParseOptions) -> Result<Vec<Snippet>, String> {
+    let (snips, mut errors) = parse_snippets_collecting(text, options);
+    if !errors.is_empty() {
+        return Err(errors.remove(0).1.to_string());
+    }
+    return Ok(snips.into_iter().map(|(_, snip)| snip).collect());
+}
+
+/// 附带行号（从 1 开始）的值
+pub type Located<T> = (usize, T);
+
+/// 同 `parse_snippets`，但出错的片段不会中止解析
+///
+/// 返回解析成功的 Snippet 和所有错误，每一项附带片段标题所在的行号（从 1 开始）。
+/// 代码块没有结尾、延伸到下一个 Snippet 标题的片段记为 `UnterminatedFence`，见 `split_segments`。
+/// 最后按 `mdppet:extends` 指令展开继承，见 `resolve_extends`，再按 `mdppet:commentify` 加上注释前缀。
+pub fn parse_snippets_collecting(
+    text: &str,
+    options: &ParseOptions,
+) -> (Vec<Located<Snippet>>, Vec<Located<MdppetError>>) {
+    let mut groups: Vec<(usize, Option<&str>)> = Vec::new();
+    if let Some(group_re) = options.group_re() {
+        for m in group_re.captures_iter(text) {
+            let scope = lang::resolve(m.get(1).unwrap().as_str());
+            groups.push((m.get(0).unwrap().start(), scope));
+        }
+    }
+
+    let directive_re = Regex::new(DIRECTIVE_RE).unwrap();
+    let mut snips: Vec<Located<Snippet>> = Vec::new();
+    let (segments, unterminated) = split_segments(text, options);
+    let mut errors: Vec<Located<MdppetError>> = unterminated
+        .into_iter()
+        .map(|(start, heading)| {
+            let e = MdppetError::UnterminatedFence(String::from(heading));
+            return (line_col(text, start).0, e);
+        })
+        .collect();
+    let (mut line, mut counted) = (1, 0);
+    for segment in segments.iter().map(|m| m.get(0).unwrap()) {
+        line += text[counted..segment.start()].matches('\n').count();
+        counted = segment.start();
+        let group_scope = groups
+            .iter()
+            .take_while(|(start, _)| *start < segment.start())
+            .last()
+            .and_then(|(_, scope)| *scope);
+        let mut snip = match Snippet::from_markdown_in_group(segment.as_str(), options, group_scope)
+        {
+            Ok(snip) => snip,
+            Err(message) => {
+                errors.push((line, MdppetError::Syntax(message)));
+                continue;
+            }
+        };
+        let directives = text[..segment.start()]
+            .trim_end()
+            .rsplit('\n')
+            .map_while(|line| directive_re.captures(line));
+        for m in directives {
+            let argument = m.name("arg").map(|arg| String::from(arg.as_str()));
+            match &m["name"] {
+                "output" if argument.is_some() => snip.output = argument,
+                "extends" if argument.is_some() => snip.extends = argument,
+                "commentify" => snip.commentify = true,
+                _ => {}
+            }
+        }
+        let following = following_fences(&text[segment.end()..]);
+        if following.is_empty() {
+            snips.push((line, snip));
+            continue;
+        }
+        let m = options
+            .markdown_re()
+            .captures(segment.as_str().trim())
+            .unwrap();
+        let mut fences = vec![Fence::from_captures(&m)];
+        fences.extend(following);
+        let flags = m.name("flags").map_or("", |flags| flags.as_str());
+        // 其后的代码块不像是各语言的版本（如作为示例的 ```json）时不拆分，只使用第一个代码块
+        if !flags.split_whitespace().any(|flag| flag == "@variants")
+            && !snip.has_variant_fences(&fences)
+        {
+            snips.push((line, snip));
+            continue;
+        }
+        match snip.split_variants(&fences, &options.body) {
+            Ok(variants) => {
+                for mut variant in variants {
+                    // 已由 `from_markdown_in_group` 检查过
+                    let _ = variant.apply_flags(flags);
+                    snips.push((line, variant));
+                }
+            }
+            Err(message) => errors.push((line, MdppetError::Syntax(message))),
+        }
+    }
+    let (resolved, extends_errors) = resolve_extends(snips);
+    errors.extend(extends_errors);
+    let mut snips: Vec<Located<Snippet>> = Vec::new();
+    for (line, snip) in resolved {
+        let variants = if snip.commentify {
+            match snip.commentify(options) {
+                Ok(variants) => variants,
+                Err(message) => {
+                    errors.push((line, MdppetError::Syntax(message)));
+                    continue;
+                }
+            }
+        } else {
+            vec![snip]
+        };
+        for snip in variants {
+            match snip.check_fields() {
+                Ok(()) => snips.push((line, snip)),
+                Err(e) => errors.push((line, e)),
+            }
+        }
+    }
+    errors.sort_by_key(|(line, _)| *line);
+    return (snips, errors);
+}
+
+/// 展开 `resolve_extends` 时每个 Snippet 的状态
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ExtendState {
+    Pending,
+    /// 正在展开它的基础 Snippet，再次遇到即为循环继承
+    Visiting,
+    Done,
+}
+
+/// `resolve_extends` 的中间结果
+struct Extending {
+    states: Vec<ExtendState>,
+    /// 每个 Snippet 展开的结果，出错时为 `None`
+    results: Vec<Option<Snippet>>,
+    /// 正在展开的 Snippet，用于报告循环继承
+    chain: Vec<usize>,
+    errors: Vec<Located<MdppetError>>,
+}
+
+/// 展开 `mdppet:extends` 继承关系，并去掉标记为 `@draft` 的 Snippet
+///
+/// 基础 Snippet 按标识符在同一文件（含 include 的内容）中查找，同名时取第一个；
+/// 按依赖顺序展开，先展开基础 Snippet 自身的继承，再由派生的 Snippet 继承，见 `Snippet::inherit`。
+/// 找不到基础 Snippet 或循环继承时，出错的 Snippet 及继承它的 Snippet 都被丢弃，错误只报告一次。
+pub fn resolve_extends(
+    snips: Vec<Located<Snippet>>,
+) -> (Vec<Located<Snippet>>, Vec<Located<MdppetError>>) {
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    for (i, (_, snip)) in snips.iter().enumerate() {
+        index.entry(snip.identifier.as_str()).or_insert(i);
+    }
+    let mut extending = Extending {
+        states: vec![ExtendState::Pending; snips.len()],
+        results: vec![None; snips.len()],
+        chain: Vec::new(),
+        errors: Vec::new(),
+    };
+    for i in 0..snips.len() {
+        extending.extend(i, &snips, &index);
+    }
+
+    let mut resolved: Vec<Located<Snippet>> = Vec::new();
+    for ((line, _), result) in snips.iter().zip(extending.results) {
+        if let Some(snip) = result.filter(|snip| !snip.draft) {
+            resolved.push((*line, snip));
+        }
+    }
+    return (resolved, extending.errors);
+}
+
+impl Extending {
+    /// 展开第 `i` 个 Snippet 的继承
+    fn extend(
+        &mut self,
+        i: usize,
+        snips: &[Located<Snippet>],
+        index: &HashMap<&str, usize>,
+    ) -> Option<Snippet> {
+        if self.states[i] == ExtendState::Done {
+            return self.results[i].clone();
+        }
+        let (line, snip) = &snips[i];
+        self.states[i] = ExtendState::Visiting;
+        self.chain.push(i);
+        let result = match snip.extends.as_deref() {
+            None => Some(snip.clone()),
+            Some(base) => match index.get(base) {
+                None => {
+                    let e = MdppetError::UnknownBase {
+                        identifier: snip.identifier.clone(),
+                        base: String::from(base),
+                    };
+                    self.errors.push((*line, e));
+                    None
+                }
+                Some(&j) if self.states[j] == ExtendState::Visiting => {
+                    let start = self.chain.iter().position(|&k| k == j).unwrap();
+                    let mut cycle: Vec<String> = self.chain[start..]
+                        .iter()
+                        .map(|&k| snips[k].1.identifier.clone())
+                        .collect();
+                    cycle.push(String::from(base));
+                    self.errors.push((*line, MdppetError::ExtendsCycle(cycle)));
+                    None
+                }
+                Some(&j) => self.extend(j, snips, index).map(|base| {
+                    let mut derived = snip.clone();
+                    derived.inherit(&base);
+                    return derived;
+                }),
+            },
+        };
+        self.chain.pop();
+        self.states[i] = ExtendState::Done;
+        self.results[i] = result.clone();
+        return result;
+    }
+}
+
+/// 找出 `text` 开头连续的代码块
+fn following_fences(text: &str) -> Vec<Fence<'_>> {
+    let re = compile_cached(String::from(FOLLOWING_FENCE_RE));
+    let mut fences: Vec<Fence> = Vec::new();
+    let mut rest = text;
+    while let Some(m) = re.captures(rest) {
+        fences.push(Fence::from_captures(&m));
+        rest = &rest[m.get(0).unwrap().end()..];
+    }
+    return fences;
+}
+
+/// `dedup_snippets` 的结果
+#[derive(Debug)]
+pub struct Deduped {
+    /// 保留下来的 Snippet，附带其来源
+    pub kept: Vec<(String, Snippet)>,
+    /// 被合并的完全相同的重复定义的数量
+    pub collapsed: usize,
+    /// 标识符相同而内容不同的定义，每一项列出不同的字段
+    pub conflicts: Vec<MdppetError>,
+}
+
+/// 合并来自多个来源的 Snippet，`snips` 中每一项附带其来源（文件名）
+///
+/// 内容完全相同的重复定义只保留第一份；标识符相同而内容不同时同样保留第一份，
+/// 并在 `conflicts` 中报告。
+pub fn dedup_snippets(snips: Vec<(String, Snippet)>) -> Deduped {
+    let mut deduped = Deduped {
+        kept: Vec::new(),
+        collapsed: 0,
+        conflicts: Vec::new(),
+    };
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for (source, snip) in snips {
+        match seen.get(snip.get_identifier()) {
+            Some(&index) => {
+                let (first_source, first) = &deduped.kept[index];
+                if *first != snip {
+                    deduped.conflicts.push(MdppetError::ConflictingSnippet {
+                        identifier: snip.identifier.clone(),
+                        sources: (first_source.clone(), source),
+                        diff: first.diff(&snip),
+                    });
+                } else {
+                    deduped.collapsed += 1;
+                }
+            }
+            None => {
+                seen.insert(snip.identifier.clone(), deduped.kept.len());
+                deduped.kept.push((source, snip));
+            }
+        }
+    }
+    return deduped;
+}
+
+/// 解析过程中发现的可疑之处，`line` 从 1 开始计数
+#[derive(Debug, PartialEq)]
+pub struct SegmentWarning {
+    pub line: usize,
+    /// 诊断信息的代码，见 `Diagnostic::code`
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// 检查片段的边界是否清晰
+///
+/// 两个标题之间缺少空行时，正则可能把其中一个标题当作描述，或者直接丢弃它。
+/// 对每一个看起来像标题、却没有成为片段开头的行给出警告（代码块中的行除外）。
+pub fn check_segment_boundaries(text: &str, options: &ParseOptions) -> Vec<SegmentWarning> {
+    let mut warnings: Vec<SegmentWarning> = Vec::new();
+    let heading_re = options.heading_re();
+    let spans = fence_spans(text, &heading_re);
+    let (segments, unterminated) = split_segments(text, options);
+    for heading in heading_re.find_iter(text) {
+        let offset = heading.start();
+        if spans.iter().any(|span| span.contains(&offset)) {
+            continue;
+        }
+        // 由 `parse_snippets_collecting` 报告为错误
+        if unterminated.iter().any(|(start, _)| *start == offset) {
+            continue;
+        }
+        let mut message: Option<String> = Some(String::from(
+            "this line looks like a snippet heading but no snippet was parsed from it; the block may be malformed, separate headings with a blank line",
+        ));
+        for m in segments.iter() {
+            if m.get(0).unwrap().start() == offset
+                || fence_group(m, "body").unwrap().range().contains(&offset)
+            {
+                message = None;
+                break;
+            }
+            if m.name("description")
+                .is_some_and(|d| d.range().contains(&offset))
+            {
+                message = Some(format!(
+                    "this line looks like a snippet heading but was parsed as the description of `{}` (line {}); the block may be malformed, separate headings with a blank line",
+                    m.name("id").unwrap().as_str(),
+                    line_col(text, m.get(0).unwrap().start()).0
+                ));
+                break;
+            }
+        }
+        if let Some(message) = message {
+            warnings.push(SegmentWarning {
+                line: line_col(text, offset).0,
+                code: "malformed-segment",
+                message,
+            });
+        }
+    }
+    return warnings;
+}
+
+/// 文件中有省略了标识符的 Snippet 时给出一条警告，位于第一个这样的标题
+pub fn check_synthesized_ids(text: &str, options: &ParseOptions) -> Option<SegmentWarning> {
+    let headings: Vec<usize> = split_segments(text, options)
+        .0
+        .into_iter()
+        .filter(|m| m.name("id").is_some_and(|id| id.as_str().is_empty()))
+        .map(|m| m.get(0).unwrap().start())
+        .collect();
+    let first = *headings.first()?;
+    return Some(SegmentWarning {
+        line: line_col(text, first).0,
+        code: "synthesized-id",
+        message: format!(
+            "{} snippet(s) in this file have no identifier; identifiers were generated from the prefix and body and change when the body changes",
+            headings.len()
+        ),
+    });
+}
+
+/// 找出只有在宽松匹配下才能识别的标题：`--strict`
+///
+/// 如 `#  id/prefix/scope`、`#\tid/prefix/scope`、`# id/prefix/scope #` 或结尾带有空白。
+pub fn check_heading_style(text: &str, options: &ParseOptions) -> Vec<SegmentWarning> {
+    let strict_re = options.strict_heading_re();
+    let mut warnings: Vec<SegmentWarning> = Vec::new();
+    for segment in split_segments(text, options).0 {
+        let segment = segment.get(0).unwrap();
+        let heading = segment.as_str().lines().next().unwrap_or("");
+        if !strict_re.is_match(heading) {
+            warnings.push(SegmentWarning {
+                line: line_col(text, segment.start()).0,
+                code: "heading-style",
+                message: format!(
+                    "non-standard snippet heading `{}`; write it as `{} id{}prefix{}scope` with a single space and nothing after the scope",
+                    heading.trim_end(),
+                    "#".repeat(options.heading_level),
+                    options.field_sep,
+                    options.field_sep
+                ),
+            });
+        }
+    }
+    return warnings;
+}
+
+/// 去掉代码块之外的 HTML 注释 `<!-- ... -->`
+///
+/// 只包含注释的行会被整行删除，以免在描述中留下空行；
+/// 代码块（以 ```` ``` ```` 或 `~~~` 围起的部分）中的内容保持原样。
+/// `mdppet:output` 和 `mdppet:extends` 指令所在的行也会保留，由 `parse_snippets` 处理。
+pub fn strip_html_comments(text: &str) -> String {
+    return strip_html_comments_mapped(text).0;
+}
+
+/// 同 `strip_html_comments`，同时返回结果中每一行在原文中的行号（从 1 开始）
+pub fn strip_html_comments_mapped(text: &str) -> (String, Vec<usize>) {
+    let mut stripped = String::with_capacity(text.len());
+    let mut origins: Vec<usize> = Vec::new();
+    let directive_re = Regex::new(DIRECTIVE_RE).unwrap();
+    let mut fence: Option<&str> = None;
+    let mut in_comment = false;
+    for (index, line) in text.split_inclusive('\n').enumerate() {
+        if let Some(marker) = fence {
+            stripped.push_str(line);
+            origins.push(index + 1);
+            if line.trim_start().starts_with(marker) {
+                fence = None;
+            }
+            continue;
+        }
+        if !in_comment {
+            let trimmed = line.trim_start();
+            if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                fence = Some(&trimmed[..3]);
+                stripped.push_str(line);
+                origins.push(index + 1);
+                continue;
+            }
+            if directive_re.is_match(line.trim_end_matches(['\r', '\n'])) {
+                stripped.push_str(line);
+                origins.push(index + 1);
+                continue;
+            }
+        }
+
+        let mut rest = line;
+        let mut kept = String::new();
+        let mut had_comment = in_comment;
+        loop {
+            if in_comment {
+                match rest.find("-->") {
+                    Some(end) => {
+                        rest = &rest[end + 3..];
+                        in_comment = false;
+                    }
+                    None => {
+                        rest = "";
+                        break;
+                    }
+                }
+            }
+            match rest.find("<!--") {
+                Some(start) => {
+                    kept.push_str(&rest[..start]);
+                    rest = &rest[start + 4..];
+                    in_comment = true;
+                    had_comment = true;
+                }
+                None => break,
+            }
+        }
+        kept.push_str(rest);
+        if had_comment && kept.trim().is_empty() {
+            continue;
+        }
+        if had_comment && line.ends_with('\n') && !kept.ends_with('\n') {
+            kept.push('\n');
+        }
+        stripped.push_str(&kept);
+        origins.push(index + 1);
+    }
+    return (stripped, origins);
+}
+
+/// 将字节偏移量换算为从 1 开始的行号和列号
+///
+/// 列号按字符计数，因此多字节字符（中文、emoji 等）只占一列。
+pub fn line_col(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = match before.rfind('\n') {
+        Some(i) => i + 1,
+        None => 0,
+    };
+    let col = before[line_start..].chars().count() + 1;
+    return (line, col);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::io::Read;
+    use std::path::Path;
+    #[test]
+    fn test_markdown_re() {
+        let re = Regex::new(MARKDOWN_RE).unwrap();
+        let mut text: String = String::new();
+
+        {
+            let md1_path = Path::new("tests/test_markdown_re_text.1.md");
+            let md1_file = File::open(md1_path).unwrap();
+            let mut md1_reader = BufReader::new(md1_file);
+            md1_reader.read_to_string(&mut text).unwrap();
+        }
+
+        assert!(re.is_match(text.as_str()));
+
+        let m = re.captures(text.as_str()).unwrap();
+        assert_eq!(m.name("id").unwrap().as_str(), "hello");
+        assert_eq!(m.name("prefix").unwrap().as_str(), "hello");
+        assert_eq!(m.name("scope").unwrap().as_str(), "rust");
+        assert_eq!(
+            m.name("description").unwrap().as_str(),
+            "Rust 的 HelloWorld 代码\n"
+        );
+        assert_eq!(
+            m.name("body").unwrap().as_str(),
+            "println!(\"Hello World!\");\n"
+        );
+    }
+    #[test]
+    fn test_snip_from_markdown() {
+        let text = read_text("tests/test_markdown_re_text.1.md");
+
+        let snip = Snippet::from_markdown(text.as_str());
         assert_eq!(snip.get_identifier(), &String::from("hello"));
-        assert_eq!(snip.get_prefix(), &String::from("hello"));
+        assert_eq!(snip.get_prefix(), "hello");
         assert_eq!(snip.get_scope(), &String::from("rust"));
         assert_eq!(
             snip.get_body(),
@@ -228,6 +2267,15 @@ mod tests {
         );
     }
     #[test]
+    fn test_snip_from_markdown_surrounding_blank_lines() {
+        let text = "\n\n  # hello/hi/rust\n\n打招呼\n\n```rust\nprintln!(\"hi\");\n```\n\n\n";
+        let snip = Snippet::from_markdown(text);
+        assert_eq!(snip.get_identifier(), "hello");
+        assert_eq!(snip.get_prefix(), "hi");
+        assert_eq!(snip.get_body(), &vec![String::from("println!(\"hi\");")]);
+        assert_eq!(snip.get_description(), &vec![String::from("打招呼")]);
+    }
+    #[test]
     fn test_multi_snip_markdown_1() {
         let text: String = read_text("tests/test_markdown.2.md");
 
@@ -318,6 +2366,1094 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_snip_scopes() {
+        let text: String = read_text("tests/test_markdown.2.md");
+        let segments = get_snippet_segments(&text);
+
+        let snip = Snippet::from_markdown(segments[2]);
+        let mut scopes: Vec<&str> = Vec::new();
+        for scope in snip.scopes() {
+            scopes.push(scope);
+        }
+        assert_eq!(scopes, vec!["python", "lua"]);
+        assert_eq!(snip.get_scope().as_str(), "python,lua");
+
+        let snip = Snippet::from_markdown(segments[0]);
+        assert_eq!(snip.scopes().collect::<Vec<&str>>(), vec!["c"]);
+    }
+
+    #[test]
+    fn test_strip_html_comments_mapped() {
+        let text = "a\n<!-- x -->\nb <!-- y\nz -->\nc\n";
+        let (stripped, origins) = strip_html_comments_mapped(text);
+        assert_eq!(stripped, "a\nb \nc\n");
+        assert_eq!(origins, vec![1, 3, 5]);
+    }
+    #[test]
+    fn test_field_sep() {
+        let text = "# src/main.rs::main::rust\n\ndesp\n\n```rust\nfn main() {}\n```\n\n# a/b/c\n\nignored\n\n```\nx\n```\n";
+        let options = ParseOptions {
+            field_sep: String::from("::"),
+            ..ParseOptions::default()
+        };
+
+        let segments = get_snippet_segments_with(text, &options);
+        assert_eq!(segments.len(), 1);
+        let snip = Snippet::from_markdown_with(segments[0], &options);
+        assert_eq!(snip.get_identifier().as_str(), "src/main.rs");
+        assert_eq!(snip.get_prefix().as_str(), "main");
+        assert_eq!(snip.get_scope().as_str(), "rust");
+        assert_eq!(snip.get_body(), &vec![String::from("fn main() {}")]);
+    }
+    #[test]
+    fn test_heading_level() {
+        let text = read_text("tests/test_markdown_level2.md");
+        let options = ParseOptions {
+            heading_level: 2,
+            ..ParseOptions::default()
+        };
+
+        let snips: Vec<Snippet> = get_snippet_segments_with(&text, &options)
+            .iter()
+            .map(|&md_text| Snippet::from_markdown_with(md_text, &options))
+            .collect();
+        let ids: Vec<&str> = snips.iter().map(|s| s.get_identifier().as_str()).collect();
+        assert_eq!(ids, vec!["first", "second"]);
+        assert!(check_segment_boundaries(&text, &options).is_empty());
+
+        // 默认级别下，二级标题和一级的文档标题都不会被当作 Snippet
+        assert!(get_snippet_segments(&text).is_empty());
+    }
+    #[test]
+    fn test_group_scope() {
+        let text = read_text("tests/test_markdown_groups.md");
+        let options = ParseOptions {
+            heading_level: 3,
+            group_level: Some(2),
+            ..ParseOptions::default()
+        };
+
+        let snips = parse_snippets(&text, &options).unwrap();
+        let scopes: Vec<(&str, &str, &str)> = snips
+            .iter()
+            .map(|s| {
+                (
+                    s.get_identifier().as_str(),
+                    s.prefixes()[0].as_str(),
+                    s.get_scope().as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            scopes,
+            vec![
+                ("main", "main", "python"),
+                ("print", "pr", "lua"),
+                ("fn", "fn", "rust"),
+                ("test", "test", "rust"),
+            ]
+        );
+        assert!(check_segment_boundaries(&text, &options).is_empty());
+    }
+    #[test]
+    fn test_in_scopes() {
+        let text = read_text("tests/test_markdown.2.md");
+        let snips: Vec<Snippet> = get_snippet_segments(&text)
+            .iter()
+            .map(|&md| Snippet::from_markdown(md))
+            .collect();
+        let included: Vec<&str> = snips
+            .iter()
+            .filter(|s| s.in_scopes(&["lua", "c"]))
+            .map(|s| s.get_identifier().as_str())
+            .collect();
+        assert_eq!(included, vec!["a", "abc"]);
+        let excluded: Vec<&str> = snips
+            .iter()
+            .filter(|s| !s.in_scopes(&["py"]))
+            .map(|s| s.get_identifier().as_str())
+            .collect();
+        assert_eq!(excluded, vec!["a", "e"]);
+    }
+    #[test]
+    fn test_foreign_fence_lang() {
+        let text = read_text("tests/test_markdown_fence_scope.md");
+        let mut snips = parse_snippets(&text, &ParseOptions::default()).unwrap();
+        let fence_langs: Vec<Option<&str>> = snips.iter().map(|s| s.get_fence_lang()).collect();
+        assert_eq!(
+            fence_langs,
+            vec![Some("rust"), Some("python"), None, Some("text")]
+        );
+        let foreign: Vec<Option<&str>> = snips.iter().map(|s| s.foreign_fence_lang()).collect();
+        assert_eq!(foreign, vec![None, Some("python"), None, None]);
+        let original = snips[1].clone();
+        snips[1].fix_scope_from_fence();
+        assert_eq!(snips[1].get_scope(), "rust,python");
+        assert_eq!(snips[1].foreign_fence_lang(), None);
+        assert_ne!(snips[1], original);
+        let retagged = text.replace("```python", "```py");
+        let retagged = parse_snippets(&retagged, &ParseOptions::default()).unwrap();
+        assert_eq!(retagged[1], original, "代码块的语言不应参与比较");
+    }
+    #[test]
+    fn test_trailing_newline() {
+        let text = read_text("tests/test_markdown_trailing.md");
+        let bodies = |options: &ParseOptions| -> Vec<String> {
+            parse_snippets(&text, options)
+                .unwrap()
+                .iter()
+                .map(|s| serde_json::to_string(s.get_body()).unwrap())
+                .collect()
+        };
+
+        assert_eq!(
+            bodies(&ParseOptions::default()),
+            vec![
+                r#"["let a = 1;"]"#,
+                r#"["let b = 2;"]"#,
+                "[\"let c = 3;\\t\"]"
+            ]
+        );
+        let keep = ParseOptions {
+            body: BodyOptions {
+                keep_trailing_newline: true,
+                ..BodyOptions::default()
+            },
+            ..ParseOptions::default()
+        };
+        assert_eq!(
+            bodies(&keep),
+            vec![
+                r#"["let a = 1;",""]"#,
+                r#"["let b = 2;"]"#,
+                "[\"let c = 3;\\t\"]"
+            ]
+        );
+    }
+    #[test]
+    fn test_front_matter_body_options() {
+        let mut front_matter = BTreeMap::new();
+        front_matter.insert(String::from("keep-trailing-newline"), String::from("yes"));
+        let options = ParseOptions::default()
+            .with_front_matter(&front_matter)
+            .unwrap();
+        assert!(options.body.keep_trailing_newline);
+        front_matter.insert(
+            String::from("trim-trailing-whitespace"),
+            String::from("maybe"),
+        );
+        assert!(ParseOptions::default()
+            .with_front_matter(&front_matter)
+            .is_err());
+    }
+    #[test]
+    fn test_lenient_headings() {
+        let cases = [
+            ("tests/test_markdown_heading_spaces.md", "spaces", "sp"),
+            ("tests/test_markdown_heading_tab.md", "tab", "tab"),
+            ("tests/test_markdown_heading_closed.md", "closed", "cl"),
+            ("tests/test_markdown_heading_trailing.md", "trailing", "tr"),
+        ];
+        let options = ParseOptions::default();
+        for (path, id, prefix) in cases.iter() {
+            let text = read_text(path);
+            let snips = parse_snippets(&text, &options).unwrap();
+            assert_eq!(snips.len(), 1, "{} 应当只解析出一个 Snippet", path);
+            assert_eq!(snips[0].get_identifier(), id);
+            assert_eq!(snips[0].get_prefix(), *prefix);
+            assert_eq!(snips[0].get_scope(), "rust");
+            let warnings = check_heading_style(&text, &options);
+            assert_eq!(warnings.len(), 1, "{} 应当在 --strict 下给出警告", path);
+            assert_eq!(warnings[0].line, 1);
+        }
+        let text = read_text("tests/test_markdown.2.md");
+        assert!(check_heading_style(&text, &options).is_empty());
+    }
+    #[test]
+    fn test_no_description() {
+        let text = read_text("tests/test_markdown_no_description.md");
+        let mut snips = parse_snippets(&text, &ParseOptions::default()).unwrap();
+        assert_eq!(snips.len(), 2);
+        assert!(snips[0].get_description().is_empty());
+        assert_eq!(snips[0].get_body(), &vec![String::from("let x = 1;")]);
+        assert_eq!(
+            snips[1].get_description(),
+            &vec![String::from("有描述"), String::from("`code` 开头的描述行")]
+        );
+        let json = serde_json::to_value(snips[0].get_snippetbody()).unwrap();
+        assert!(json.get("description").is_none(), "空描述不应输出该字段");
+
+        snips[0].default_description(DefaultDescription::Prefix);
+        assert_eq!(snips[0].get_description(), &vec![String::from("nd")]);
+        snips[1].default_description(DefaultDescription::Id);
+        assert_eq!(snips[1].get_description().len(), 2);
+        assert!(check_segment_boundaries(&text, &ParseOptions::default()).is_empty());
+    }
+    #[test]
+    fn test_dedent_flag() {
+        let text = "# over/ov/python @dedent\n\n缩进过多\n\n```python\n        if x:\n\n            y()\n        z()\n```\n\n# keep/kp/python\n\n保留缩进\n\n```python\n    x = 1\n```\n";
+        let snips = parse_snippets(text, &ParseOptions::default()).unwrap();
+        assert_eq!(snips.len(), 2);
+        assert_eq!(snips[0].get_scope(), "python");
+        assert_eq!(
+            snips[0].get_body(),
+            &vec![
+                String::from("if x:"),
+                String::from(""),
+                String::from("    y()"),
+                String::from("z()"),
+            ]
+        );
+        assert_eq!(snips[1].get_body(), &vec![String::from("    x = 1")]);
+        assert!(check_heading_style(text, &ParseOptions::default()).is_empty());
+    }
+    #[test]
+    fn test_check_body() {
+        let snip = Snippet::from_text(
+            "ctl",
+            "ctl",
+            "rust",
+            "a\u{7}b\u{1b}\n\tok\r\nx\0y\n\u{7f}",
+            "",
+        )
+        .unwrap();
+        let (warnings, errors) = snip.check_body(3);
+        let codes: Vec<&str> = warnings.iter().map(|(code, _)| *code).collect();
+        assert_eq!(
+            codes,
+            vec!["control-character", "control-character", "long-body"]
+        );
+        assert!(warnings[0]
+            .1
+            .contains("0x07 in line 1 of its body at byte 2"));
+        assert!(warnings[1].1.contains("0x7F in line 4"));
+        assert!(warnings[2].1.contains("4 body lines"));
+        match errors.as_slice() {
+            [MdppetError::NulByte {
+                identifier,
+                line,
+                column,
+            }] => assert_eq!((identifier.as_str(), *line, *column), ("ctl", 3, 2)),
+            other => panic!("应当报告一个 NUL 字节，实际为 {:?}", other),
+        }
+        let (warnings, errors) = snip.check_body(0);
+        assert_eq!((warnings.len(), errors.len()), (2, 1));
+    }
+    #[test]
+    fn test_to_markdown_round_trip() {
+        let sources = [
+            "# log/log/rust\n\n[author=me]\n[since=1.0]\n\n打印日志\n`log!` 宏\n第三行\n\n```rust\nlog!(\"{}\", $1);\n```\n",
+            "# hash/hash/python,shell\n\n```python\n# a/b/c\n# 注释\n\n  $0\n```\n",
+            "# md/md/markdown @file-template @file docs.json\n\n代码块\n\n```markdown\n~~~rust\nfn main() {}\n~~~\n```\n",
+            "# tick/tick/markdown @deprecated\n\n~~~markdown\n```\ncode\n```\n~~~\n",
+            "# base/base/rust @draft\n\n```\n{{slot}}\n```\n",
+        ];
+        let snips: Vec<Snippet> = sources
+            .iter()
+            .map(|text| Snippet::from_markdown(text))
+            .collect();
+        assert_eq!(snips[2].get_body().len(), 3);
+        assert_eq!(snips[3].get_body().len(), 3, "补全体中的 ``` 应由 ~~~ 围起");
+        for heading_level in [1, 3] {
+            for fence in [FenceStyle::Backtick, FenceStyle::Tilde] {
+                for info_string in [InfoString::FirstScope, InfoString::Fence, InfoString::None] {
+                    for blank_lines in [1, 2] {
+                        let options = RenderOptions {
+                            heading_level,
+                            fence,
+                            info_string,
+                            blank_lines,
+                            ..RenderOptions::default()
+                        };
+                        let parse = ParseOptions {
+                            heading_level,
+                            ..ParseOptions::default()
+                        };
+                        for snip in snips.iter() {
+                            let text = snip.to_markdown(&options);
+                            assert_eq!(
+                                &Snippet::from_markdown_with(&text, &parse),
+                                snip,
+                                "{:?} 写出的内容应能解析回来：\n{}",
+                                options,
+                                text
+                            );
+                        }
+                        let document: Vec<String> = snips
+                            .iter()
+                            .map(|snip| snip.to_markdown(&options))
+                            .collect();
+                        let parsed = parse_snippets(&document.join("\n"), &parse).unwrap();
+                        let expected: Vec<&Snippet> =
+                            snips.iter().filter(|snip| !snip.is_draft()).collect();
+                        assert_eq!(parsed.iter().collect::<Vec<&Snippet>>(), expected);
+                    }
+                }
+            }
+        }
+        let text = snips[0].to_markdown(&RenderOptions {
+            fence: FenceStyle::Tilde,
+            info_string: InfoString::None,
+            blank_lines: 2,
+            ..RenderOptions::default()
+        });
+        assert!(text.starts_with("# log/log/rust\n\n\n[author=me]\n[since=1.0]\n\n\n打印日志"));
+        assert!(text.ends_with("\n~~~\nlog!(\"{}\", $1);\n~~~\n"));
+    }
+    #[test]
+    fn test_empty_prefix() {
+        match Snippet::from_text("a", "-", "rust", "x", "") {
+            Err(MdppetError::EmptyPrefix(identifier)) => assert_eq!(identifier, "a"),
+            other => panic!("应当报告空的前缀，实际为 {:?}", other),
+        }
+        assert!(Snippet::from_text("a", ",", "rust", "x", "").is_err());
+        assert!(Snippet::from_text("a", "-x", "rust", "x", "").is_ok());
+        let (snips, errors) =
+            parse_snippets_collecting("# a/-/rust\n\n```rust\nx\n```\n", &ParseOptions::default());
+        assert!(snips.is_empty());
+        assert_eq!(errors[0].1.code(), "empty-prefix");
+    }
+    #[test]
+    fn test_empty_body() {
+        match Snippet::from_text("a", "a", "rust", " \n\t\n", "") {
+            Err(MdppetError::EmptyBody(identifier)) => assert_eq!(identifier, "a"),
+            other => panic!("应当报告空的补全体，实际为 {:?}", other),
+        }
+        let text = "# a/a/rust\n\n```rust\n   \n```\n\n# b/b/rust\n\n```rust\nb\n```\n";
+        let (snips, errors) = parse_snippets_collecting(text, &ParseOptions::default());
+        assert_eq!(snips.len(), 1);
+        assert_eq!(snips[0].1.get_identifier(), "b");
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].0, errors[0].1.code()), (1, "empty-body"));
+    }
+    #[test]
+    fn test_untrimmed_identifier() {
+        let snip = Snippet::from_text(" a\t", "a", "rust", "x", "").unwrap();
+        assert_eq!(snip.get_identifier(), "a");
+        let snip = Snippet::from_text("a b", "a", "rust", "x", "").unwrap();
+        assert_eq!(snip.get_identifier(), "a b");
+    }
+    #[test]
+    fn test_blank_description() {
+        let snip = Snippet::from_text("a", "a", "rust", "x", " \n\t").unwrap();
+        assert!(snip.get_snippetbody().description.is_empty());
+        let json = serde_json::to_value(snip.get_snippetbody()).unwrap();
+        assert!(json.get("description").is_none(), "只含空白的描述不应输出");
+    }
+    #[test]
+    fn test_deprecated() {
+        let text = "# new/new/rust\n\n```rust\nnew\n```\n\n# old/old/rust @deprecated\n\n旧的\n\n```rust\nold\n```\n";
+        let mut snips = parse_snippets(text, &ParseOptions::default()).unwrap();
+        assert!(!snips[0].is_deprecated());
+        assert!(snips[1].is_deprecated());
+        assert!(snips[1]
+            .to_markdown(&RenderOptions::default())
+            .starts_with("# old/old/rust @deprecated\n"));
+        snips[1].annotate_deprecated();
+        assert_eq!(
+            snips[1].get_description(),
+            &vec![String::from("旧的 (deprecated)")]
+        );
+        snips[0].annotate_deprecated();
+        assert_eq!(
+            snips[0].get_description(),
+            &vec![String::from("(deprecated)")]
+        );
+        assert_eq!(Deprecated::from_name("skip"), Some(Deprecated::Skip));
+        assert_eq!(Deprecated::from_name("drop"), None);
+    }
+    #[test]
+    fn test_metadata() {
+        let text = "# log/log/rust\n\n[author=me]\n[ since = 1.0 ]\n打印日志\n\n```rust\nlog!()\n```\n\n# bare/bare/rust\n[tag=x]\n\n```rust\nx\n```\n";
+        let snips = parse_snippets(text, &ParseOptions::default()).unwrap();
+        assert_eq!(snips.len(), 2);
+        let metadata: Vec<(&str, &str)> = snips[0]
+            .get_metadata()
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(metadata, vec![("author", "me"), ("since", "1.0")]);
+        assert_eq!(snips[0].get_description(), &vec![String::from("打印日志")]);
+        assert_eq!(snips[1].get_metadata()["tag"], "x");
+        assert!(!snips[1].has_tag(&["x"]), "只有 `tags` 键表示标签");
+        assert!(snips[1].get_description().is_empty());
+        let value = serde_json::to_value(snips[0].get_snippetbody()).unwrap();
+        assert!(!value.to_string().contains("author"), "元数据不应写入输出");
+    }
+    #[test]
+    fn test_file_template_flag() {
+        let text = "# main/main/rust @file-template\n\n```rust\nfn main() {}\n```\n\n# plain/plain/rust\n\n```rust\nx\n```\n";
+        let snips = parse_snippets(text, &ParseOptions::default()).unwrap();
+        let template = serde_json::to_value(snips[0].get_snippetbody()).unwrap();
+        assert_eq!(template["isFileTemplate"], serde_json::Value::Bool(true));
+        let plain = serde_json::to_value(snips[1].get_snippetbody()).unwrap();
+        assert!(
+            plain.get("isFileTemplate").is_none(),
+            "未标记的 Snippet 不应输出该字段"
+        );
+    }
+    #[test]
+    fn test_file_flag() {
+        let text = "# a/a/rust @file a.json @dedent\n\n```rust\n  a\n```\n\n# b/b/rust @file\n\n```rust\nb\n```\n\n<!-- mdppet:output c.json -->\n# c/c/rust @file b.json\n\n```rust\nc\n```\n";
+        let snips = parse_snippets(text, &ParseOptions::default()).unwrap();
+        assert_eq!(snips[0].get_output(), Some("a.json"));
+        assert_eq!(snips[0].get_body(), &vec![String::from("a")]);
+        assert_eq!(snips[1].get_output(), None, "没有参数的 @file 应被忽略");
+        assert_eq!(snips[2].get_output(), Some("c.json"), "指令优先于 @file");
+        let text = "# a/a/rust @deprecated foo @draft\n\n```rust\na\n```\n\n# b/b/rust\n\n```rust\nb\n```\n";
+        let (snips, errors) = parse_snippets_collecting(text, &ParseOptions::default());
+        assert_eq!(snips.len(), 1);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 1);
+        assert_eq!(
+            errors[0].1.to_string(),
+            "snippet `a`: `@deprecated` does not take an argument, but `foo` follows it"
+        );
+        let mut snip = Snippet::from_text("a", "a", "rust", "x", "").unwrap();
+        assert!(snip.apply_flags("@draft x.json @deprecated").is_err());
+        assert!(
+            !snip.is_draft() && snip.is_deprecated(),
+            "其余的标记照常应用"
+        );
+
+        let text = "# a/a/rust @file ./io/a.json\n\n```rust\na\n```\n\n# b/b/rust @file ../b.json\n\n```rust\nb\n```\n\n<!-- mdppet:output /tmp/c.json -->\n# c/c/rust\n\n```rust\nc\n```\n";
+        let (snips, errors) = parse_snippets_collecting(text, &ParseOptions::default());
+        assert_eq!(snips.len(), 1);
+        let errors: Vec<(usize, &str)> = errors.iter().map(|(line, e)| (*line, e.code())).collect();
+        assert_eq!(errors, vec![(7, "unsafe-output"), (14, "unsafe-output")]);
+    }
+    #[test]
+    fn test_language_variants() {
+        let text = read_text("tests/test_markdown_variants.md");
+        let snips = parse_snippets(&text, &ParseOptions::default()).unwrap();
+        let variants: Vec<(&str, &str, &Vec<String>)> = snips
+            .iter()
+            .map(|s| {
+                (
+                    s.get_identifier().as_str(),
+                    s.get_scope().as_str(),
+                    s.get_body(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            variants,
+            vec![
+                (
+                    "license-python",
+                    "python",
+                    &vec![String::from("# SPDX-License-Identifier: MIT")]
+                ),
+                (
+                    "license-rust",
+                    "rust",
+                    &vec![String::from("// SPDX-License-Identifier: MIT")]
+                ),
+                ("hello", "rust", &vec![String::from("println!(\"Hello\");")]),
+            ]
+        );
+        assert_eq!(snips[0].get_prefix(), "lic");
+        assert_eq!(snips[1].get_description(), snips[0].get_description());
+    }
+    #[test]
+    fn test_language_variants_by_scope() {
+        let text = "# sum/sum/rust,py\n\n```python\nsum(xs)\n```\n\n```rs\nxs.iter().sum()\n```\n";
+        let snips = parse_snippets(text, &ParseOptions::default()).unwrap();
+        let variants: Vec<(&str, &str, &str)> = snips
+            .iter()
+            .map(|s| {
+                (
+                    s.get_identifier().as_str(),
+                    s.get_scope().as_str(),
+                    s.get_body()[0].as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            variants,
+            vec![
+                ("sum-rust", "rust", "xs.iter().sum()"),
+                ("sum-python", "python", "sum(xs)")
+            ]
+        );
+        let duplicate = "# a/a/rust,python @variants\n\n```rust\na\n```\n\n```rs\nb\n```\n";
+        assert_eq!(
+            parse_snippets(duplicate, &ParseOptions::default()),
+            Err(String::from(
+                "snippet `a` has more than one `rust` code block"
+            ))
+        );
+        let missing = "# a/a/rust,python,lua\n\n```rust\na\n```\n\n```python\nb\n```\n";
+        let snips = parse_snippets(missing, &ParseOptions::default()).unwrap();
+        let bodies: Vec<(&str, &str)> = snips
+            .iter()
+            .map(|s| (s.get_identifier().as_str(), s.get_body()[0].as_str()))
+            .collect();
+        assert_eq!(
+            bodies,
+            vec![("a-rust", "a"), ("a-python", "b"), ("a-lua", "a")],
+            "没有对应代码块的作用域应使用第一个代码块"
+        );
+        assert_eq!(snips[2].get_fence_lang(), None);
+    }
+    #[test]
+    fn test_language_variants_single_fence() {
+        let snip = Snippet::from_markdown("# a/a/python,rust\n\n```python\nx\n```\n");
+        let fences = [Fence {
+            lang: Some("python"),
+            attrs: "",
+            body: "x\n",
+        }];
+        let variants = snip
+            .split_variants(&fences, &BodyOptions::default())
+            .unwrap();
+        let ids: Vec<&str> = variants
+            .iter()
+            .map(|s| s.get_identifier().as_str())
+            .collect();
+        assert_eq!(ids, vec!["a-python", "a-rust"]);
+        assert_eq!(variants[1].get_body(), &vec![String::from("x")]);
+
+        let text = "# a/a/python,rust,lua @variants\n\n```python\nx\n```\n\n```\nshared\n```\n\n# b/b/python,rust\n\n```python\ny\n```\n";
+        let snips = parse_snippets(text, &ParseOptions::default()).unwrap();
+        let bodies: Vec<(&str, &str)> = snips
+            .iter()
+            .map(|s| (s.get_identifier().as_str(), s.get_body()[0].as_str()))
+            .collect();
+        assert_eq!(
+            bodies,
+            vec![
+                ("a-python", "x"),
+                ("a-rust", "shared"),
+                ("a-lua", "shared"),
+                ("b", "y")
+            ],
+            "没有对应代码块的作用域应使用没有标注语言的代码块，只有一个代码块时不拆分"
+        );
+    }
+    #[test]
+    fn test_language_variants_mixed() {
+        let text = read_text("tests/test_markdown_variants_mixed.md");
+        let snips = parse_snippets(&text, &ParseOptions::default()).unwrap();
+        assert_eq!(snips.len(), 1, "没有 @variants 时不应拆分");
+        assert_eq!(snips[0].get_identifier(), "license");
+        assert_eq!(
+            snips[0].get_body(),
+            &vec![String::from("# SPDX-License-Identifier: MIT")]
+        );
+        let opted_in = text.replace("python,rust", "python,rust @variants");
+        let snips = parse_snippets(&opted_in, &ParseOptions::default()).unwrap();
+        let bodies: Vec<(&str, &str)> = snips
+            .iter()
+            .map(|s| (s.get_identifier().as_str(), s.get_body()[0].as_str()))
+            .collect();
+        assert_eq!(
+            bodies,
+            vec![
+                ("license-python", "# SPDX-License-Identifier: MIT"),
+                ("license-rust", "// SPDX-License-Identifier: MIT")
+            ]
+        );
+        let ambiguous = format!("{}\n```\nmore\n```\n", opted_in);
+        assert_eq!(
+            parse_snippets(&ambiguous, &ParseOptions::default()),
+            Err(String::from(
+                "snippet `license` has more than one code block without a language, so it is unclear which one its other scopes share"
+            ))
+        );
+        let unscoped = Snippet::new("a", "a", "", &vec!["a"], &vec![]);
+        let fences = [
+            Fence {
+                lang: Some("rust"),
+                attrs: "",
+                body: "a\n",
+            },
+            Fence {
+                lang: None,
+                attrs: "",
+                body: "b\n",
+            },
+        ];
+        assert_eq!(
+            unscoped
+                .split_variants(&fences, &BodyOptions::default())
+                .unwrap_err(),
+            "snippet `a` mixes code blocks with and without a language",
+            "没有作用域时没有需要共用代码块的作用域"
+        );
+    }
+    #[test]
+    fn test_trailing_example_fence() {
+        let text = "# a/a/rust\n\n```rust\nx\n```\n\n```json\n{}\n```\n\n# b/b/rust,python\n\n```rust\ny\n```\n\n```rust\nexample\n```\n";
+        let snips = parse_snippets(text, &ParseOptions::default()).unwrap();
+        let ids: Vec<&str> = snips.iter().map(|s| s.get_identifier().as_str()).collect();
+        assert_eq!(ids, vec!["a", "b"], "其后的示例代码块不应拆分");
+        assert_eq!(snips[1].get_body(), &vec![String::from("y")]);
+    }
+    #[test]
+    fn test_front_matter_heading_level() {
+        let text = read_text("tests/test_markdown_front_matter.md");
+        let (front_matter, body) = split_front_matter(&text);
+        assert_eq!(front_matter["heading-level"], "2");
+        assert_eq!(body.lines().count(), text.lines().count());
+
+        let options = ParseOptions::default()
+            .with_front_matter(&front_matter)
+            .unwrap();
+        assert_eq!(options.heading_level, 2);
+        let segments = get_snippet_segments_with(&body, &options);
+        assert_eq!(segments.len(), 1);
+        let snip = Snippet::from_markdown_with(segments[0], &options);
+        assert_eq!(snip.get_identifier().as_str(), "fm");
+
+        let mut invalid = BTreeMap::new();
+        invalid.insert(String::from("heading-level"), String::from("seven"));
+        assert!(ParseOptions::default().with_front_matter(&invalid).is_err());
+    }
+    #[test]
+    fn test_plain_descriptions() {
+        let text = "# a/b/rust\n\nUse **bold** and `code`\nsee [docs](https://example.com)\n\n```rust\nlet **a** = 1;\n```\n";
+        let mut snip = Snippet::from_markdown(text);
+        snip.plain_descriptions();
+        assert_eq!(
+            snip.get_description(),
+            &vec![String::from("Use bold and code"), String::from("see docs")]
+        );
+        assert_eq!(snip.get_body(), &vec![String::from("let **a** = 1;")]);
+    }
+    #[test]
+    fn test_trim_blank_body_lines() {
+        let text = "# a/b/rust\n\ndesp\n\n```rust\n\n  \nlet a = 1;\n\nlet b = 2;\n\t\n\n```\n";
+        let mut snip = Snippet::from_markdown(text);
+        assert_eq!(snip.get_body()[0], "");
+
+        snip.trim_blank_body_lines();
+        assert_eq!(
+            snip.get_body(),
+            &vec![
+                String::from("let a = 1;"),
+                String::from(""),
+                String::from("let b = 2;")
+            ]
+        );
+    }
+    #[test]
+    fn test_expand_escapes() {
+        let text = "# a/b/rust\n\n```rust\nif x {\\n\\tfoo(\"\\\\n\");\\n} \\d\n```\n";
+        let mut snip = Snippet::from_markdown(text);
+        assert_eq!(snip.get_body().len(), 1);
+        snip.expand_escapes();
+        assert_eq!(
+            snip.get_body(),
+            &vec![
+                String::from("if x {"),
+                String::from("\tfoo(\"\\n\");"),
+                String::from("} \\d")
+            ]
+        );
+    }
+    #[test]
+    fn test_dedup_identical() {
+        let text: String = read_text("tests/test_markdown.2.md");
+        let mut snips: Vec<(String, Snippet)> = Vec::new();
+        for source in ["one.md", "two.md"].iter() {
+            for md in get_snippet_segments(&text) {
+                snips.push((String::from(*source), Snippet::from_markdown(md)));
+            }
+        }
+
+        let deduped = dedup_snippets(snips);
+        assert_eq!(deduped.collapsed, 3);
+        assert_eq!(deduped.kept.len(), 3);
+        assert!(deduped.kept.iter().all(|(source, _)| source == "one.md"));
+        assert!(deduped.conflicts.is_empty());
+    }
+    #[test]
+    fn test_dedup_conflict() {
+        let first = Snippet::from_text("a", "b", "rust", "body", "desp").unwrap();
+        let second = Snippet::from_text("a", "b", "rust", "other body", "desp").unwrap();
+        assert_ne!(first, second);
+        let snips = vec![
+            (String::from("one.md"), first),
+            (String::from("two.md"), second),
+        ];
+
+        let mut deduped = dedup_snippets(snips);
+        assert_eq!(deduped.kept.len(), 1);
+        assert_eq!(deduped.kept[0].1.get_body(), &vec![String::from("body")]);
+        match deduped.conflicts.pop() {
+            Some(MdppetError::ConflictingSnippet {
+                identifier,
+                sources,
+                diff,
+            }) => {
+                assert_eq!(identifier, "a");
+                assert_eq!(sources, (String::from("one.md"), String::from("two.md")));
+                assert_eq!(diff, vec!["body: [\"body\"] != [\"other body\"]"]);
+            }
+            other => panic!("应当报告冲突，实际为 {:?}", other),
+        }
+
+        let first = Snippet::from_text("a", "b", "rust", "body", "desp").unwrap();
+        let mut second = first.clone();
+        second.draft = true;
+        second
+            .metadata
+            .insert(String::from("author"), String::from("me"));
+        assert_ne!(first, second);
+        assert_eq!(first.diff(&second), vec!["draft: false != true"]);
+        second.draft = false;
+        assert_eq!(first, second, "只有元数据不同时不算作冲突");
+        let deduped = dedup_snippets(vec![
+            (String::from("one.md"), first),
+            (String::from("two.md"), second),
+        ]);
+        assert_eq!(deduped.kept.len(), 1);
+        assert!(deduped.conflicts.is_empty());
+    }
+    #[test]
+    fn test_synthesized_identifier() {
+        let text = "# /Fn.Main,fm/rust\n\n```rust\nfn main() {}\n```\n\n# /fn/rust\n\n```rust\nfn f() {}\n```\n";
+        let options = ParseOptions::default();
+        let first = parse_snippets(text, &options).unwrap();
+        let second = parse_snippets(text, &options).unwrap();
+        assert_eq!(first, second, "同样的输入应当生成同样的标识符");
+        assert_eq!(first[0].get_identifier(), "fn-main-aa244faa");
+        assert_eq!(first[0].get_prefix(), "Fn.Main,fm");
+        assert_ne!(first[1].get_identifier(), first[0].get_identifier());
+        let warning = check_synthesized_ids(text, &options).unwrap();
+        assert_eq!((warning.line, warning.code), (1, "synthesized-id"));
+        assert!(warning.message.starts_with("2 snippet(s)"));
+        assert!(check_synthesized_ids("# a/b/c\n\n```\nx\n```\n", &options).is_none());
+    }
+    #[test]
+    fn test_synthesized_identifier_collision() {
+        let synthesized = Snippet::from_markdown("# /fn/rust\n\n```rust\nfn f() {}\n```\n");
+        let id = synthesized.get_identifier().clone();
+        let explicit =
+            Snippet::from_markdown(&format!("# {}/fn/rust\n\n```rust\nfn g() {{}}\n```\n", id));
+        let deduped = dedup_snippets(vec![
+            (String::from("one.md"), synthesized),
+            (String::from("two.md"), explicit),
+        ]);
+        assert_eq!(deduped.kept.len(), 1);
+        match deduped.conflicts.as_slice() {
+            [MdppetError::ConflictingSnippet { identifier, .. }] => assert_eq!(identifier, &id),
+            other => panic!("应当报告冲突，实际为 {:?}", other),
+        }
+    }
+    #[test]
+    fn test_keyword_prefix_scopes() {
+        let snip = Snippet::from_text("a", "for", "rust,python,yaml", "x", "").unwrap();
+        assert_eq!(snip.keyword_prefix_scopes(), vec!["rust", "python"]);
+        let snip = Snippet::from_text("a", "def", "rs", "x", "").unwrap();
+        assert!(snip.keyword_prefix_scopes().is_empty());
+        let snip = Snippet::from_text("a", "lp,loop", "rs", "x", "").unwrap();
+        assert_eq!(snip.keyword_prefix_scopes(), vec!["rust"]);
+    }
+    #[test]
+    fn test_multi_prefix() {
+        let snip = Snippet::from_markdown("# log/log,print/rust\n\n```rust\nprintln!();\n```\n");
+        assert_eq!(
+            snip.prefixes(),
+            &[String::from("log"), String::from("print")]
+        );
+        assert_eq!(snip.get_prefix(), "log,print");
+        let value = serde_json::to_value(snip.get_snippetbody()).unwrap();
+        assert_eq!(value["prefix"], serde_json::json!(["log", "print"]));
+        let parsed: SnippetBody = serde_json::from_value(value).unwrap();
+        assert_eq!(&parsed, snip.get_snippetbody());
+
+        let single = Snippet::from_text("a", "x", "rust", "x", "").unwrap();
+        let value = serde_json::to_value(single.get_snippetbody()).unwrap();
+        assert_eq!(value["prefix"], "x");
+    }
+    #[test]
+    fn test_parse_rename() {
+        assert_eq!(
+            parse_rename("fn=rust-function"),
+            Ok((String::from("fn"), String::from("rust-function")))
+        );
+        assert!(parse_rename("fn").is_err());
+        assert!(parse_rename("=x").is_err());
+    }
+    #[test]
+    fn test_line_col() {
+        let text = "# 函数/fn/rust\n\n定义 🦀 函数\n";
+        assert_eq!(line_col(text, 0), (1, 1));
+        let offset = text.find("fn/").unwrap();
+        assert_eq!(line_col(text, offset), (1, 6));
+        let offset = text.find("函数\n").unwrap();
+        assert_eq!(line_col(text, offset), (3, 6));
+    }
+
+    #[test]
+    fn test_strip_html_comments() {
+        let text = read_text("tests/test_markdown_comment.md");
+        let text = strip_html_comments(&text);
+        assert!(!text.contains("TODO"));
+
+        let snips: Vec<Snippet> = get_snippet_segments(&text)
+            .iter()
+            .map(|&md_text| Snippet::from_markdown(md_text))
+            .collect();
+        assert_eq!(snips.len(), 2);
+        assert_eq!(
+            snips[0].get_description(),
+            &vec![String::from("first line"), String::from("second line")]
+        );
+        assert_eq!(
+            snips[1].get_description(),
+            &vec![String::from("inline  comment")]
+        );
+        assert_eq!(
+            snips[1].get_body(),
+            &vec![String::from("<!-- kept inside the fence -->")]
+        );
+    }
+
+    #[test]
+    fn test_check_segment_boundaries() {
+        let text = read_text("tests/test_markdown_crammed.md");
+        let warnings = check_segment_boundaries(&text, &ParseOptions::default());
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line, 1);
+        assert!(warnings[0].message.contains("may be malformed"));
+
+        let text = "# a/b/c\n\n# d/e/f\n\n```\nbody\n```\n";
+        let warnings = check_segment_boundaries(text, &ParseOptions::default());
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line, 3);
+        assert!(warnings[0].message.contains("description of `a` (line 1)"));
+
+        let text = read_text("tests/test_markdown.2.md");
+        assert!(check_segment_boundaries(&text, &ParseOptions::default()).is_empty());
+    }
+    #[test]
+    fn test_commentify() {
+        let text = read_text("tests/test_markdown_commentify.md");
+        let snips = parse_snippets(&text, &ParseOptions::default()).unwrap();
+        let variants: Vec<(&str, &str, &Vec<String>)> = snips
+            .iter()
+            .map(|s| {
+                (
+                    s.get_identifier().as_str(),
+                    s.get_scope().as_str(),
+                    s.get_body(),
+                )
+            })
+            .collect();
+        let body = |lines: &[&str]| lines.iter().map(|&line| String::from(line)).collect();
+        let python: Vec<String> = body(&[
+            "# Copyright (c) ${1:2024} ${2:me}",
+            "#",
+            "# 已经是 Python 注释",
+            "# -- 已经是 Lua 注释",
+        ]);
+        let rust: Vec<String> = body(&[
+            "// Copyright (c) ${1:2024} ${2:me}",
+            "//",
+            "// # 已经是 Python 注释",
+            "// -- 已经是 Lua 注释",
+        ]);
+        let lua: Vec<String> = body(&[
+            "-- Copyright (c) ${1:2024} ${2:me}",
+            "--",
+            "-- # 已经是 Python 注释",
+            "-- 已经是 Lua 注释",
+        ]);
+        assert_eq!(
+            variants,
+            vec![
+                ("header-python", "python", &python),
+                ("header-rust", "rust", &rust),
+                ("header-lua", "lua", &lua),
+            ]
+        );
+        assert_eq!(snips[0].get_description(), &vec![String::from("文件头")]);
+    }
+    #[test]
+    fn test_commentify_leaders() {
+        let text = "<!-- mdppet:commentify -->\n# h/h/elixir,sql\n\n```\nx\n```\n";
+        let error = parse_snippets(text, &ParseOptions::default()).unwrap_err();
+        assert!(error.contains("--comment-leader elixir=..."));
+
+        let mut options = ParseOptions::default();
+        for value in ["Elixir=# ", "sql=REM "].iter() {
+            let (language, leader) = parse_comment_leader(value).unwrap();
+            options.comment_leaders.insert(language, leader);
+        }
+        let snips = parse_snippets(text, &options).unwrap();
+        assert_eq!(snips[0].get_body(), &vec![String::from("# x")]);
+        assert_eq!(
+            snips[1].get_body(),
+            &vec![String::from("REM x")],
+            "应优先使用指定的前缀"
+        );
+        assert!(parse_comment_leader("sql").is_err());
+        assert!(parse_comment_leader("sql= ").is_err());
+    }
+    #[test]
+    fn test_commentify_extends() {
+        let text = "# base/base/python,rust @draft\n\n```\nCopyright\n```\n\n<!-- mdppet:extends base -->\n<!-- mdppet:commentify -->\n# header/hdr/python,rust\n\n```\nAuthor\n```\n\n<!-- mdppet:commentify -->\n# lic/lic/python,rust\n\n```python\nMIT python\n```\n\n```rust\nMIT rust\n```\n\n<!-- mdppet:extends lic -->\n# lic2/lic2/python\n\n```\nextra\n```\n";
+        let snips = parse_snippets(text, &ParseOptions::default()).unwrap();
+        let bodies: Vec<(&str, Vec<&str>)> = snips
+            .iter()
+            .map(|s| {
+                let body = s.get_body().iter().map(|line| line.as_str()).collect();
+                return (s.get_identifier().as_str(), body);
+            })
+            .collect();
+        assert_eq!(
+            bodies,
+            vec![
+                ("header-python", vec!["# Copyright", "# Author"]),
+                ("header-rust", vec!["// Copyright", "// Author"]),
+                ("lic-python", vec!["# MIT python"]),
+                ("lic-rust", vec!["// MIT rust"]),
+                ("lic2", vec!["MIT python", "extra"]),
+            ],
+            "继承来的行也应加上前缀，按语言拆分的代码块应各自加上前缀，其他 Snippet 仍可继承原标识符"
+        );
+    }
+    #[test]
+    fn test_extends() {
+        let text = read_text("tests/test_markdown_extends.md");
+        let (snips, errors) = parse_snippets_collecting(&text, &ParseOptions::default());
+        assert!(errors.is_empty());
+        let ids: Vec<&str> = snips
+            .iter()
+            .map(|(_, s)| s.get_identifier().as_str())
+            .collect();
+        assert_eq!(
+            ids,
+            vec!["point", "point3"],
+            "@draft 的基础 Snippet 不应输出"
+        );
+        let point = &snips[0].1;
+        assert_eq!(
+            point.get_body(),
+            &vec![
+                String::from("#[derive(${1:Debug})]"),
+                String::from("pub struct ${2:Name} {"),
+                String::from("    x: f64,"),
+                String::from(""),
+                String::from("    y: f64,"),
+                String::from("}"),
+            ]
+        );
+        assert_eq!(point.get_description(), &vec![String::from("结构体的骨架")]);
+        assert_eq!(point.get_extends(), Some("struct"));
+        let point3 = &snips[1].1;
+        assert_eq!(&point3.get_body()[..6], &point.get_body()[..]);
+        assert_eq!(point3.get_body()[6], "impl ${2:Name} {}");
+        assert_eq!(point3.get_description(), &vec![String::from("三维的点")]);
+
+        let base = Snippet::from_text("b", "b", "rust", "f({{slot}}, x)", "基础").unwrap();
+        let mut derived = Snippet::from_text("d", "d", "", "a,\nb", "").unwrap();
+        derived.inherit(&base);
+        assert_eq!(
+            derived.get_body(),
+            &vec![String::from("f(a,"), String::from("b, x)")]
+        );
+        assert_eq!(derived.get_scope(), "rust");
+    }
+    #[test]
+    fn test_extends_errors() {
+        let text = "<!-- mdppet:extends b -->\n# a/a/rust\n\n```rust\na\n```\n\n<!-- mdppet:extends a -->\n# b/b/rust\n\n```rust\nb\n```\n\n<!-- mdppet:extends a -->\n# c/c/rust\n\n```rust\nc\n```\n\n<!-- mdppet:extends nope -->\n# d/d/rust\n\n```rust\nd\n```\n\n# e/e/rust\n\n```rust\ne\n```\n";
+        let (snips, errors) = parse_snippets_collecting(text, &ParseOptions::default());
+        assert_eq!(
+            snips.len(),
+            1,
+            "循环继承中的 Snippet 及继承它们的 Snippet 都应被丢弃"
+        );
+        assert_eq!(snips[0].1.get_identifier(), "e");
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].0, 9);
+        assert_eq!(errors[0].1.to_string(), "extends cycle: a -> b -> a");
+        assert_eq!(errors[1].0, 23);
+        assert_eq!(errors[1].1.code(), "unknown-base");
+        assert_eq!(errors[1].1.identifier(), Some("d"));
+    }
+    #[test]
+    fn test_unterminated_fence() {
+        let text = read_text("tests/test_markdown_unterminated.md");
+        let options = ParseOptions::default();
+        let (snips, errors) = parse_snippets_collecting(&text, &options);
+        assert_eq!(snips.len(), 1, "缺少结尾的代码块不应吞掉下一个 Snippet");
+        assert_eq!(snips[0].0, 8);
+        assert_eq!(snips[0].1.get_identifier(), "closed");
+        assert_eq!(
+            snips[0].1.get_body(),
+            &vec![String::from("let closed = 2;")]
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 1);
+        assert_eq!(errors[0].1.code(), "unterminated-fence");
+        assert!(errors[0].1.to_string().contains("`# open/open/rust`"));
+        assert!(check_segment_boundaries(&text, &options).is_empty());
+    }
+    #[test]
+    fn test_segment_spans() {
+        let text = read_text("tests/test_markdown.2.md");
+        let spans = get_snippet_segment_spans(&text);
+        assert_eq!(spans.len(), 3);
+        let segments: Vec<&str> = spans.iter().map(|(_, segment)| *segment).collect();
+        assert_eq!(segments, get_snippet_segments(&text));
+        for (range, segment) in spans.iter() {
+            assert_eq!(&text[range.clone()], *segment);
+            assert!(segment.starts_with("# "));
+        }
+        assert!(spans
+            .windows(2)
+            .all(|pair| pair[0].0.end <= pair[1].0.start));
+        assert_eq!(spans[0].0.start, 0);
+    }
+    #[test]
+    fn test_fake_heading_in_fence() {
+        let text = read_text("tests/test_markdown_fake_heading.md");
+        let options = ParseOptions::default();
+        let (snips, errors) = parse_snippets_collecting(&text, &options);
+        assert!(errors.is_empty(), "代码块中的标题不应报错：{:?}", errors);
+        let ids: Vec<&str> = snips
+            .iter()
+            .map(|(_, snip)| snip.get_identifier().as_str())
+            .collect();
+        assert_eq!(ids, vec!["readme", "fn"]);
+        assert_eq!(
+            snips[0].1.get_body(),
+            &vec![
+                String::from("# name/prefix/scope"),
+                String::from(""),
+                String::from("说明")
+            ]
+        );
+        assert_eq!(snips[1].1.get_description().len(), 2);
+        assert!(check_segment_boundaries(&text, &options).is_empty());
+        assert_eq!(fence_spans(&text, &options.heading_re()).len(), 3);
+    }
+    #[test]
+    fn test_backtick_description() {
+        let text = read_text("tests/test_markdown_backtick_description.md");
+        let snips = parse_snippets(&text, &ParseOptions::default()).unwrap();
+        assert_eq!(snips.len(), 2);
+        assert_eq!(snips[0].get_identifier(), "let");
+        assert_eq!(
+            snips[0].get_description(),
+            &vec![
+                String::from("`let` 绑定变量"),
+                String::from("``mut`` 表示可变")
+            ]
+        );
+        assert_eq!(
+            snips[0].get_body(),
+            &vec![String::from("let ${1:x} = ${2:value};")]
+        );
+        assert_eq!(snips[1].get_identifier(), "loop");
+        assert!(snips[1].get_description().is_empty());
+    }
+
     fn read_text(path: &str) -> String {
         let mut text: String = String::new();
         {
@@ -0,0 +1,209 @@
+use crate::archive;
+use crate::error::MdppetError;
+use crate::snip::split_front_matter;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// include 指令：`<!-- mdppet:include ./loops.md -->`，须独占一行
+const INCLUDE_RE: &str =
+    r"^[\x20\t]*<!--[\x20\t]*mdppet:include[\x20\t]+(\S+)[\x20\t]*-->[\x20\t]*$";
+
+/// include 的最大嵌套层数
+pub const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// 读取 Markdown 源文件，`file` 也可以是压缩包中的条目，如 `collection.zip/a.md`
+///
+/// 文件必须是 UTF-8 编码，开头的 BOM（如记事本保存的文件）会被去掉。
+pub fn read_source(file: &Path) -> Result<String, MdppetError> {
+    let bytes = match archive::split(file) {
+        Some((archive, member)) => archive::read(&archive, &member)?,
+        None => fs::read(file).map_err(|e| MdppetError::Io(file.to_path_buf(), e))?,
+    };
+    let text = String::from_utf8(bytes)
+        .map_err(|e| MdppetError::InvalidUtf8(file.to_path_buf(), e.utf8_error().valid_up_to()))?;
+    match text.strip_prefix('\u{FEFF}') {
+        Some(stripped) => return Ok(String::from(stripped)),
+        None => return Ok(text),
+    }
+}
+
+/// 展开 include 之后的文档中，每一行实际来自哪个文件的哪一行
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    lines: Vec<(PathBuf, usize)>,
+}
+
+impl SourceMap {
+    /// 查找文档中第 `line` 行（从 1 开始）的来源
+    pub fn locate(&self, line: usize) -> (&Path, usize) {
+        let (path, line) = &self.lines[line - 1];
+        return (path, *line);
+    }
+
+    /// 文档删去部分行之后的来源，`origins[i]` 是删减后第 `i + 1` 行在删减前的行号
+    pub fn remap(&self, origins: &[usize]) -> SourceMap {
+        let lines = origins
+            .iter()
+            .map(|&line| self.lines[line - 1].clone())
+            .collect();
+        return SourceMap { lines };
+    }
+}
+
+/// 一个已展开 include 的源文件
+#[derive(Debug)]
+pub struct Source {
+    /// 入口文件的 front matter，被包含的文件的 front matter 会被忽略
+    pub front_matter: BTreeMap<String, String>,
+    pub text: String,
+    pub map: SourceMap,
+}
+
+/// 读取 `path`，并将其中的 include 指令替换为被包含文件的内容
+///
+/// 被包含文件的路径相对于包含它的文件；循环包含或嵌套过深时报错。
+/// 代码块中的指令不会被展开。
+pub fn load(path: &Path) -> Result<Source, MdppetError> {
+    let (front_matter, text) = split_front_matter(&read_source(path)?);
+    let canonical = canonicalize(path)?;
+    let mut stack = vec![(canonical, path.to_path_buf())];
+    let mut source = Source {
+        front_matter,
+        text: String::with_capacity(text.len()),
+        map: SourceMap::default(),
+    };
+    expand(path, &text, &mut stack, &mut source)?;
+    return Ok(source);
+}
+
+/// 用于检测循环包含的规范路径，压缩包中的条目只按字面规范化
+fn canonicalize(path: &Path) -> Result<PathBuf, MdppetError> {
+    if archive::split(path).is_some() {
+        return Ok(archive::normalize(path));
+    }
+    return fs::canonicalize(path).map_err(|e| MdppetError::Io(path.to_path_buf(), e));
+}
+
+fn expand(
+    path: &Path,
+    text: &str,
+    stack: &mut Vec<(PathBuf, PathBuf)>,
+    source: &mut Source,
+) -> Result<(), MdppetError> {
+    let include_re = Regex::new(INCLUDE_RE).unwrap();
+    let mut fence: Option<&str> = None;
+    for (index, line) in text.split_inclusive('\n').enumerate() {
+        let trimmed = line.trim_start();
+        match fence {
+            Some(marker) => {
+                if trimmed.starts_with(marker) {
+                    fence = None;
+                }
+            }
+            None => {
+                if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                    fence = Some(&trimmed[..3]);
+                } else if let Some(m) = include_re.captures(line.trim_end()) {
+                    let target = path.parent().unwrap_or(Path::new("")).join(&m[1]);
+                    include(&target, stack, source)?;
+                    continue;
+                }
+            }
+        }
+        source.text.push_str(line);
+        if !line.ends_with('\n') {
+            source.text.push('\n');
+        }
+        source.map.lines.push((path.to_path_buf(), index + 1));
+    }
+    return Ok(());
+}
+
+fn include(
+    path: &Path,
+    stack: &mut Vec<(PathBuf, PathBuf)>,
+    source: &mut Source,
+) -> Result<(), MdppetError> {
+    let canonical = canonicalize(path)?;
+    if let Some(start) = stack.iter().position(|(seen, _)| *seen == canonical) {
+        let mut chain: Vec<PathBuf> = stack[start..].iter().map(|(_, p)| p.clone()).collect();
+        chain.push(path.to_path_buf());
+        return Err(MdppetError::IncludeCycle(chain));
+    }
+    if stack.len() > MAX_INCLUDE_DEPTH {
+        return Err(MdppetError::IncludeDepth(
+            path.to_path_buf(),
+            MAX_INCLUDE_DEPTH,
+        ));
+    }
+    let (_, text) = split_front_matter(&read_source(path)?);
+    stack.push((canonical, path.to_path_buf()));
+    expand(path, &text, stack, source)?;
+    stack.pop();
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snip;
+
+    #[test]
+    fn test_read_source_strips_bom() {
+        let text = read_source(Path::new("tests/test_markdown_bom.md")).unwrap();
+        assert!(text.starts_with("# bom/bom/rust"));
+
+        let segments = snip::get_snippet_segments(&text);
+        assert_eq!(segments.len(), 1);
+        let snip = snip::Snippet::from_markdown(segments[0]);
+        assert_eq!(snip.get_identifier().as_str(), "bom");
+    }
+    #[test]
+    fn test_read_source_invalid_utf8() {
+        let path = Path::new("tests/test_markdown_gbk.md");
+        match read_source(path) {
+            Err(e @ MdppetError::InvalidUtf8(..)) => assert_eq!(
+                e.to_string(),
+                "tests/test_markdown_gbk.md: input is not valid UTF-8 (first invalid byte at offset 16): consider converting the file encoding"
+            ),
+            other => panic!("应当报告编码错误，实际为 {:?}", other),
+        }
+    }
+    #[test]
+    fn test_nested_include() {
+        let source = load(Path::new("tests/include/main.md")).unwrap();
+        let ids: Vec<String> = snip::get_snippet_segments(&source.text)
+            .iter()
+            .map(|&md| snip::Snippet::from_markdown(md).get_identifier().clone())
+            .collect();
+        assert_eq!(ids, vec!["main", "loop", "deep", "after"]);
+        // 代码块中的指令保持原样
+        assert!(source
+            .text
+            .contains("<!-- mdppet:include ./not-expanded.md -->"));
+
+        let warnings = snip::check_segment_boundaries(&source.text, &Default::default());
+        assert_eq!(warnings.len(), 1);
+        let (file, line) = source.map.locate(warnings[0].line);
+        assert_eq!(file, Path::new("tests/include/./nested/../nested/deep.md"));
+        assert_eq!(line, 11);
+    }
+    #[test]
+    fn test_include_cycle() {
+        match load(Path::new("tests/include/cycle_a.md")) {
+            Err(e @ MdppetError::IncludeCycle(_)) => assert_eq!(
+                e.to_string(),
+                "include cycle: tests/include/cycle_a.md -> tests/include/./cycle_b.md -> tests/include/./cycle_a.md"
+            ),
+            other => panic!("应当报告循环包含，实际为 {:?}", other),
+        }
+    }
+    #[test]
+    fn test_remap() {
+        let source = load(Path::new("tests/include/main.md")).unwrap();
+        let map = source.map.remap(&[1, 3]);
+        assert_eq!(map.locate(2), (Path::new("tests/include/main.md"), 3));
+    }
+}
@@ -0,0 +1,312 @@
+use crate::error::MdppetError;
+use crate::snip::{self, ParseOptions, Snippet};
+use regex::Regex;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// 一个 Snippet 在源文件中的位置
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Location {
+    pub file: String,
+    /// 标题所在的行
+    pub heading_line: usize,
+    /// 补全体的第一行和最后一行，不含代码块的开头和结尾
+    pub body_start_line: usize,
+    pub body_end_line: usize,
+}
+
+impl Location {
+    /// 同一标识符有多个（相同的）定义时保留该值最小的一个，使位置表不随源文件的顺序和 `--jobs` 改变
+    fn order(&self) -> (&String, usize) {
+        return (&self.file, self.heading_line);
+    }
+}
+
+/// `--emit-sourcemap` 写出的位置表：每个输出文件中的标识符到 Snippet 的位置
+///
+/// 不同的源文件可以定义同一个标识符，`--out-dir` 时它们写入不同的输出文件，
+/// 因此位置先按源文件记录，写入输出时再从该输出的源文件中查找。
+#[derive(Debug, Default)]
+pub struct SnippetMap {
+    /// 命令行中的源文件 → 标识符 → 位置；位置中的 `file` 可能是被包含的文件
+    locations: BTreeMap<String, BTreeMap<String, Location>>,
+    /// 输出文件 → 标识符 → 位置
+    outputs: BTreeMap<String, BTreeMap<String, Location>>,
+}
+
+impl SnippetMap {
+    pub fn new() -> Self {
+        return SnippetMap::default();
+    }
+
+    /// 记录源文件 `src` 中 `identifier` 的位置，已记录过的标识符保留文件名和行号较小的位置
+    pub fn insert(&mut self, src: &Path, identifier: &str, location: Location) {
+        let locations = self.locations.entry(src.display().to_string()).or_default();
+        match locations.get_mut(identifier) {
+            Some(existing) => {
+                if location.order() < existing.order() {
+                    *existing = location;
+                }
+            }
+            None => {
+                locations.insert(String::from(identifier), location);
+            }
+        }
+    }
+
+    /// 并入另一张位置表，规则与 `insert` 相同
+    pub fn merge(&mut self, other: SnippetMap) {
+        for (src, locations) in other.locations {
+            for (identifier, location) in locations {
+                self.insert(Path::new(&src), &identifier, location);
+            }
+        }
+    }
+
+    /// 是否没有记录任何输出文件；增量构建中跳过的输出同样会被记录
+    pub fn is_empty(&self) -> bool {
+        return self.outputs.is_empty();
+    }
+
+    /// 记录由 `sources` 生成的 `snips` 写入了 `dest`
+    pub fn record_output<'a>(
+        &mut self,
+        dest: &Path,
+        sources: &[PathBuf],
+        snips: impl Iterator<Item = &'a Snippet>,
+    ) {
+        let locations = &self.locations;
+        let written = self.outputs.entry(dest.display().to_string()).or_default();
+        for snip in snips {
+            let location = sources
+                .iter()
+                .filter_map(|src| {
+                    return locations
+                        .get(&src.display().to_string())?
+                        .get(snip.get_identifier());
+                })
+                .min_by(|a, b| a.order().cmp(&b.order()));
+            if let Some(location) = location {
+                written.insert(snip.get_identifier().clone(), location.clone());
+            }
+        }
+    }
+
+    /// 以带缩进的 JSON 对象写入 `path`，只含写入了输出的 Snippet
+    ///
+    /// 给出 `relative_to` 时输出文件和源文件的路径都改为相对于该目录，见 `relative_path`。
+    pub fn write(&self, path: &Path, relative_to: Option<&Path>) -> Result<(), MdppetError> {
+        let relative = |file: &str| match relative_to {
+            Some(base) => relative_path(Path::new(file), base).display().to_string(),
+            None => String::from(file),
+        };
+        let mut written: BTreeMap<String, BTreeMap<&String, Location>> = BTreeMap::new();
+        for (dest, locations) in self.outputs.iter() {
+            let entries = written.entry(relative(dest)).or_default();
+            for (identifier, location) in locations.iter() {
+                let location = Location {
+                    file: relative(&location.file),
+                    ..location.clone()
+                };
+                entries.insert(identifier, location);
+            }
+        }
+        let text = serde_json::to_string_pretty(&written).unwrap() + "\n";
+        let io_err = |e| MdppetError::Io(path.to_path_buf(), e);
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent).map_err(io_err)?;
+        }
+        return fs::write(path, text).map_err(io_err);
+    }
+}
+
+/// 将 `path` 改写为相对于目录 `base` 的路径：`--relative-to`
+///
+/// 两者都先按当前目录转为绝对路径，并按字面消去 `.` 和 `..`，不访问文件系统，
+/// 因此不解析符号链接；没有共同前缀（如 Windows 上位于不同盘符）时返回绝对路径。
+pub fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let path = lexical_absolute(path);
+    let base = lexical_absolute(base);
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common == 0 {
+        return path;
+    }
+    let mut relative = PathBuf::new();
+    for _ in base.components().skip(common) {
+        relative.push("..");
+    }
+    for component in path.components().skip(common) {
+        relative.push(component);
+    }
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    return relative;
+}
+
+/// 按当前目录转为绝对路径，并按字面消去 `.` 和 `..`
+fn lexical_absolute(path: &Path) -> PathBuf {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut normalized = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    return normalized;
+}
+
+/// 一个代码块的语言以及补全体的第一行和最后一行
+type FenceLines<'a> = (Option<&'a str>, usize, usize);
+
+/// `text` 中每个 Snippet 标题所在的行，以及标题之后的代码块所在的行
+///
+/// 与 `parse_snippets_collecting` 一样，代码块包括标题之后紧跟的所有代码块。
+pub fn fence_lines<'a>(
+    text: &'a str,
+    options: &ParseOptions,
+) -> BTreeMap<usize, Vec<FenceLines<'a>>> {
+    let fence_re = Regex::new(snip::FOLLOWING_FENCE_RE).unwrap();
+    // `offset` 是 `m` 所匹配的文本在 `text` 中的起始位置
+    let fence = |m: &regex::Captures<'a>, offset: usize| {
+        let body = snip::fence_group(m, "body").unwrap();
+        let content = body.as_str().strip_suffix('\n').unwrap_or(body.as_str());
+        let start = offset + body.start();
+        return (
+            snip::fence_group(m, "lang").map(|lang| lang.as_str()),
+            snip::line_col(text, start).0,
+            snip::line_col(text, start + content.len()).0,
+        );
+    };
+    let mut headings: BTreeMap<usize, Vec<FenceLines>> = BTreeMap::new();
+    for m in snip::split_segments(text, options).0 {
+        let whole = m.get(0).unwrap();
+        let mut fences: Vec<FenceLines> = vec![fence(&m, 0)];
+        let mut consumed = whole.end();
+        while let Some(f) = fence_re.captures(&text[consumed..]) {
+            fences.push(fence(&f, consumed));
+            consumed += f.get(0).unwrap().end();
+        }
+        headings.insert(snip::line_col(text, whole.start()).0, fences);
+    }
+    return headings;
+}
+
+/// 代码块标注的语言为 `fence_lang` 的 Snippet 的补全体所在的行，见 `Snippet::get_fence_lang`
+///
+/// 按语言拆分的 Snippet 取语言相同的代码块，没有对应代码块的作用域取没有标注语言的代码块，
+/// 都没有时取第一个代码块，与 `Snippet::split_variants` 一致；
+/// 没有拆分时其后的代码块不属于该 Snippet，同样取第一个代码块。
+pub fn body_lines(fences: &[FenceLines], fence_lang: Option<&str>) -> Option<(usize, usize)> {
+    let fence = fences
+        .iter()
+        .find(|(lang, _, _)| *lang == fence_lang)
+        .or_else(|| fences.first())?;
+    return Some((fence.1, fence.2));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fence_lines() {
+        let text = std::fs::read_to_string("tests/test_markdown_variants.md").unwrap();
+        let fences = fence_lines(&text, &ParseOptions::default());
+        assert_eq!(fences.keys().copied().collect::<Vec<usize>>(), vec![1, 13]);
+        assert_eq!(
+            fences[&1],
+            vec![(Some("python"), 6, 6), (Some("rust"), 10, 10)]
+        );
+        assert_eq!(body_lines(&fences[&1], Some("rust")), Some((10, 10)));
+        assert_eq!(body_lines(&fences[&1], Some("python")), Some((6, 6)));
+        assert_eq!(body_lines(&fences[&1], None), Some((6, 6)));
+        assert_eq!(body_lines(&fences[&13], Some("rust")), Some((18, 18)));
+    }
+    #[test]
+    fn test_record_output() {
+        let snip = Snippet::from_text("a", "a", "rust", "x", "").unwrap();
+        let location = Location {
+            file: String::from("a.md"),
+            heading_line: 1,
+            body_start_line: 4,
+            body_end_line: 4,
+        };
+        let mut map = SnippetMap::new();
+        map.insert(Path::new("a.md"), "a", location.clone());
+        map.insert(
+            Path::new("a.md"),
+            "a",
+            Location {
+                heading_line: 9,
+                ..location.clone()
+            },
+        );
+        map.insert(
+            Path::new("a.md"),
+            "a",
+            Location {
+                file: String::from("0.md"),
+                ..location.clone()
+            },
+        );
+        map.insert(
+            Path::new("b.md"),
+            "a",
+            Location {
+                file: String::from("b.md"),
+                ..location
+            },
+        );
+        let (a, b) = (PathBuf::from("a.md"), PathBuf::from("b.md"));
+        let snips = || std::iter::once(&snip);
+        map.record_output(Path::new("a.json"), std::slice::from_ref(&a), snips());
+        map.record_output(Path::new("b.json"), std::slice::from_ref(&b), snips());
+        map.record_output(Path::new("all.json"), &[b, a], snips());
+        let file = |dest: &str| map.outputs[dest]["a"].order();
+        assert_eq!(
+            file("a.json"),
+            (&String::from("0.md"), 1),
+            "应当保留文件名和行号最小的定义"
+        );
+        assert_eq!(
+            file("b.json"),
+            (&String::from("b.md"), 1),
+            "应当取该输出的源文件中的定义"
+        );
+        assert_eq!(file("all.json"), (&String::from("0.md"), 1));
+    }
+    #[test]
+    fn test_relative_path() {
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(
+            relative_path(Path::new("out/rust.json"), Path::new("out")),
+            PathBuf::from("rust.json")
+        );
+        assert_eq!(
+            relative_path(Path::new("./src/../tests/a.md"), Path::new("out/zed")),
+            PathBuf::from("../../tests/a.md")
+        );
+        assert_eq!(
+            relative_path(&cwd.join("a.md"), Path::new(".")),
+            PathBuf::from("a.md")
+        );
+        assert_eq!(
+            relative_path(Path::new("out"), Path::new("out/")),
+            PathBuf::from(".")
+        );
+    }
+}
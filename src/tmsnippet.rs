@@ -0,0 +1,159 @@
+use crate::lang;
+use crate::snip::{fnv1a, Snippet};
+use regex::Regex;
+use serde_json::{Map, Value};
+
+/// XML 声明，必须位于文件开头
+pub const XML_DECLARATION: &str = r#"<?xml version="1.0" encoding="UTF-8"?>"#;
+
+const DOCTYPE: &str = r#"<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">"#;
+
+/// plist 中的一对 `<key>` 和 `<string>`
+const ENTRY_RE: &str = r"<key>([^<]*)</key>\s*<string>([^<]*)</string>";
+
+/// 将一个 Snippet 写成 TextMate 的 `.tmSnippet` plist，不含 XML 声明
+///
+/// 没有描述时以标识符作为 `name`；没有作用域时省略 `scope`，即在所有语言中可用。
+/// `tabTrigger` 只能有一个，有多个前缀时取第一个。
+pub fn render(snip: &Snippet) -> String {
+    let name = if snip.get_description().is_empty() {
+        snip.get_identifier().clone()
+    } else {
+        snip.get_description().join(" ")
+    };
+    let mut entries: Vec<(&str, String)> =
+        vec![("content", snip.get_body().join("\n")), ("name", name)];
+    let selectors: Vec<String> = snip.scopes().map(lang::textmate_selector).collect();
+    if !selectors.is_empty() {
+        entries.push(("scope", selectors.join(", ")));
+    }
+    entries.push(("tabTrigger", snip.prefixes()[0].clone()));
+    entries.push(("uuid", uuid(snip.get_identifier())));
+
+    let mut text = format!("{}\n<plist version=\"1.0\">\n<dict>\n", DOCTYPE);
+    for (key, value) in entries.iter() {
+        text.push_str(&format!(
+            "\t<key>{}</key>\n\t<string>{}</string>\n",
+            key,
+            escape(value)
+        ));
+    }
+    text.push_str("</dict>\n</plist>\n");
+    return text;
+}
+
+/// 将 `render` 生成的 plist 解析回与 JSON 输出等价的值，以 `uuid` 作为键
+pub fn parse(text: &str) -> Result<Value, String> {
+    if !text.contains("<plist") {
+        return Err(String::from("expected a <plist> element"));
+    }
+    let entry_re = Regex::new(ENTRY_RE).unwrap();
+    let mut fields: Map<String, Value> = Map::new();
+    let mut uuid = String::new();
+    for m in entry_re.captures_iter(text) {
+        let value = unescape(&m[2]);
+        match &m[1] {
+            "content" => {
+                let lines = value.split('\n').map(Value::from).collect();
+                fields.insert(String::from("body"), Value::Array(lines));
+            }
+            "name" => {
+                fields.insert(String::from("description"), Value::from(value));
+            }
+            "scope" => {
+                fields.insert(String::from("scope"), Value::from(value));
+            }
+            "tabTrigger" => {
+                fields.insert(String::from("prefix"), Value::from(value));
+            }
+            "uuid" => uuid = value,
+            _ => {}
+        }
+    }
+    let mut snippets: Map<String, Value> = Map::new();
+    snippets.insert(uuid, Value::Object(fields));
+    return Ok(Value::Object(snippets));
+}
+
+/// 由标识符导出的 UUID，重新生成时保持不变
+///
+/// 取两轮 FNV-1a 散列共 128 位，按 RFC 9562 标记为自定义的第 8 版。
+pub fn uuid(identifier: &str) -> String {
+    let bytes = identifier.as_bytes();
+    let high = fnv1a(0xcbf2_9ce4_8422_2325, bytes);
+    let low = fnv1a(high, bytes);
+    let high = (high & !0xF000) | 0x8000;
+    let low = (low & 0x3FFF_FFFF_FFFF_FFFF) | 0x8000_0000_0000_0000;
+    return format!(
+        "{:08X}-{:04X}-{:04X}-{:04X}-{:012X}",
+        high >> 32,
+        (high >> 16) & 0xFFFF,
+        high & 0xFFFF,
+        low >> 48,
+        low & 0xFFFF_FFFF_FFFF
+    );
+}
+
+/// 转义 XML 文本节点中的字符
+///
+/// `\r` 写作字符引用，以免被 XML 解析器规范化为 `\n`；
+/// XML 1.0 不允许出现的控制字符替换为 U+FFFD。
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '\r' => escaped.push_str("&#13;"),
+            '\t' | '\n' => escaped.push(c),
+            c if c < '\u{20}' => escaped.push('\u{FFFD}'),
+            c => escaped.push(c),
+        }
+    }
+    return escaped;
+}
+
+fn unescape(text: &str) -> String {
+    return text
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#13;", "\r")
+        .replace("&amp;", "&");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        let text = "if a < b && c > d {\r\n\t\u{7}}";
+        assert_eq!(
+            escape(text),
+            "if a &lt; b &amp;&amp; c &gt; d {&#13;\n\t\u{FFFD}}"
+        );
+        assert_eq!(unescape(&escape("&lt; is <")), "&lt; is <");
+    }
+    #[test]
+    fn test_uuid() {
+        let id = uuid("fn");
+        assert_eq!(id, uuid("fn"));
+        assert_ne!(id, uuid("fm"));
+        assert_eq!(id.len(), 36);
+        assert_eq!(&id[14..15], "8");
+        assert!("89AB".contains(&id[19..20]));
+    }
+    #[test]
+    fn test_parse_round_trip() {
+        let snip = Snippet::from_text("cmp", "cmp", "rust", "a < b\n&c", "").unwrap();
+        let value = parse(&render(&snip)).unwrap();
+        let fields = &value[uuid("cmp").as_str()];
+        assert_eq!(fields["prefix"], "cmp");
+        assert_eq!(fields["body"], serde_json::json!(["a < b", "&c"]));
+        assert_eq!(fields["scope"], "source.rust");
+        assert_eq!(fields["description"], "cmp");
+    }
+}
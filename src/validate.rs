@@ -0,0 +1,140 @@
+use crate::output::Format;
+use serde_json::{Map, Value};
+
+/// 检查 `render` 按 `format` 生成的文本，对应 `--validate`
+pub fn validate_rendered(text: &str, format: Format) -> Vec<String> {
+    match format.parse(text) {
+        Ok(value) => return validate_snippets(&value),
+        Err(e) => return vec![format!("output cannot be parsed back: {}", e)],
+    }
+}
+
+/// 按 VSCode 的 snippet JSON schema 检查生成的结果
+///
+/// 每个值都必须是对象：`prefix` 和 `body` 为字符串或字符串数组，
+/// 可选的 `description` 同样为字符串或字符串数组，可选的 `scope` 为字符串，
+/// 可选的 `isFileTemplate` 为布尔值，其余字段不作要求。
+/// 返回所有违例，每一项形如 `` `标识符` at /JSON/指针: 说明 ``。
+pub fn validate_snippets(value: &Value) -> Vec<String> {
+    let mut violations: Vec<String> = Vec::new();
+    let map = match value.as_object() {
+        Some(map) => map,
+        None => {
+            violations.push(String::from("at /: expected an object of snippets"));
+            return violations;
+        }
+    };
+    for (identifier, snippet) in map.iter() {
+        let pointer = format!("/{}", escape_pointer(identifier));
+        let mut report = |field: &str, message: &str| {
+            let pointer = match field {
+                "" => pointer.clone(),
+                field => format!("{}/{}", pointer, field),
+            };
+            violations.push(format!("`{}` at {}: {}", identifier, pointer, message));
+        };
+        let fields = match snippet.as_object() {
+            Some(fields) => fields,
+            None => {
+                report("", "expected an object");
+                continue;
+            }
+        };
+        check_field(fields, "prefix", true, STRINGS, &mut report);
+        check_field(fields, "body", true, STRINGS, &mut report);
+        check_field(fields, "description", false, STRINGS, &mut report);
+        check_field(fields, "scope", false, STRING, &mut report);
+        check_field(fields, "isFileTemplate", false, BOOLEAN, &mut report);
+    }
+    return violations;
+}
+
+/// 字段的类型约束及其说明
+type Kind = (fn(&Value) -> bool, &'static str);
+
+const STRINGS: Kind = (is_string_or_strings, "a string or an array of strings");
+const STRING: Kind = (Value::is_string, "a string");
+const BOOLEAN: Kind = (Value::is_boolean, "a boolean");
+
+fn check_field<F: FnMut(&str, &str)>(
+    fields: &Map<String, Value>,
+    name: &str,
+    required: bool,
+    (valid, expected): Kind,
+    report: &mut F,
+) {
+    match fields.get(name) {
+        None if required => report("", &format!("missing required field `{}`", name)),
+        Some(value) if !valid(value) => report(name, &format!("expected {}", expected)),
+        _ => {}
+    }
+}
+
+fn is_string_or_strings(value: &Value) -> bool {
+    match value {
+        Value::String(_) => return true,
+        Value::Array(items) => return items.iter().all(Value::is_string),
+        _ => return false,
+    }
+}
+
+/// 按 RFC 6901 转义 JSON 指针中的一段
+fn escape_pointer(token: &str) -> String {
+    return token.replace('~', "~0").replace('/', "~1");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{render, JsonStyle};
+    use crate::snip::Snippet;
+    use serde::ser::{Serialize, SerializeMap, Serializer};
+
+    /// 故意写错字段名和类型的序列化实现
+    struct BrokenBody;
+
+    impl Serialize for BrokenBody {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut map = serializer.serialize_map(Some(3))?;
+            map.serialize_entry("prefix", &42)?;
+            map.serialize_entry("bodies", &["x"])?;
+            map.serialize_entry("scope", &["rust"])?;
+            return map.end();
+        }
+    }
+
+    #[test]
+    fn test_validate_rendered() {
+        let snips = vec![Snippet::from_text("a/b", "ab", "rust", "x", "desc").unwrap()];
+        for format in [
+            Format::Json,
+            Format::CodeSnippets,
+            Format::Toml,
+            Format::Yaml,
+            Format::Zed,
+            Format::TmSnippet,
+            Format::MarkdownIndex,
+            Format::JsonFull,
+            Format::Ndjson,
+        ]
+        .iter()
+        {
+            let text = render(&snips, *format, JsonStyle::default());
+            assert_eq!(validate_rendered(&text, *format), Vec::<String>::new());
+        }
+    }
+    #[test]
+    fn test_validate_broken_serialize() {
+        let mut map = std::collections::BTreeMap::new();
+        map.insert("a/b", BrokenBody);
+        let value = serde_json::to_value(&map).unwrap();
+        assert_eq!(
+            validate_snippets(&value),
+            vec![
+                "`a/b` at /a~1b/prefix: expected a string or an array of strings",
+                "`a/b` at /a~1b: missing required field `body`",
+                "`a/b` at /a~1b/scope: expected a string",
+            ]
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use regex::{Captures, Regex};
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 构建时变量 `{{name}}`，`\{{` 表示字面的 `{{`
+const VAR_RE: &str = r"\\\{\{|\{\{[\x20\t]*([A-Za-z_][A-Za-z0-9_.-]*)[\x20\t]*\}\}";
+
+/// 生成时展开的变量，区别于 VSCode 在插入时展开的 `$CURRENT_YEAR` 等变量
+#[derive(Debug, Clone)]
+pub struct Vars {
+    values: BTreeMap<String, String>,
+    /// 未定义的变量原样保留，而不是报错
+    pub allow_undefined: bool,
+}
+
+impl Vars {
+    /// 只包含内置变量 `mdppet_version`，给出 `date` 时还包含变量 `date`
+    ///
+    /// `date` 使输出随构建的日期变化，因此只在 `--date-var` 时定义，见 `build_date`。
+    pub fn builtin(date: Option<SystemTime>) -> Self {
+        let mut values = BTreeMap::new();
+        if let Some(date) = date {
+            values.insert(String::from("date"), crate::output::date(date));
+        }
+        values.insert(
+            String::from("mdppet_version"),
+            String::from(env!("CARGO_PKG_VERSION")),
+        );
+        Vars {
+            values,
+            allow_undefined: false,
+        }
+    }
+
+    /// 定义变量，覆盖同名的内置变量
+    pub fn define(&mut self, name: String, value: String) {
+        self.values.insert(name, value);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        return self.values.get(name).map(String::as_str);
+    }
+
+    /// 展开 `text` 中的变量，遇到未定义的变量时返回其名称
+    pub fn expand(&self, text: &str) -> Result<String, String> {
+        let var_re = Regex::new(VAR_RE).unwrap();
+        let mut undefined: Option<String> = None;
+        let expanded = var_re.replace_all(text, |caps: &Captures| match caps.get(1) {
+            None => String::from("{{"),
+            Some(name) => match self.get(name.as_str()) {
+                Some(value) => String::from(value),
+                None => {
+                    if undefined.is_none() {
+                        undefined = Some(String::from(name.as_str()));
+                    }
+                    String::from(&caps[0])
+                }
+            },
+        });
+        match undefined {
+            Some(name) if !self.allow_undefined => return Err(name),
+            _ => return Ok(expanded.into_owned()),
+        }
+    }
+}
+
+/// 将补全体中未定义的 `{{name}}` 转换为 VSCode 的 tabstop `${1:name}`
+///
+/// 编号按首次出现的顺序分配，从补全体中已有的最大 tabstop 编号（见 `placeholder::max_index`）之后开始，以免与之冲突；
+/// 同名的占位符再次出现时写作同一个编号，由 VSCode 同步编辑。
+/// 已定义的变量和转义的 `\{{` 保持不变，留给 `Vars::expand` 处理。
+pub fn to_tabstops(lines: &mut [String], vars: &Vars) {
+    let var_re = Regex::new(VAR_RE).unwrap();
+    let offset = crate::placeholder::max_index(&crate::placeholder::parse(&lines.join("\n")));
+    let mut numbers: Vec<String> = Vec::new();
+    for line in lines.iter_mut() {
+        let replaced = var_re.replace_all(line, |caps: &Captures| match caps.get(1) {
+            Some(name) if vars.get(name.as_str()).is_none() => {
+                let name = name.as_str();
+                match numbers.iter().position(|seen| seen == name) {
+                    Some(index) => format!("${}", offset + index + 1),
+                    None => {
+                        numbers.push(String::from(name));
+                        format!("${{{}:{}}}", offset + numbers.len(), name)
+                    }
+                }
+            }
+            _ => String::from(&caps[0]),
+        });
+        *line = replaced.into_owned();
+    }
+}
+
+/// 解析 `--define key=value`
+pub fn parse_define(definition: &str) -> Result<(String, String), String> {
+    let name_re = Regex::new(r"^[A-Za-z_][A-Za-z0-9_.-]*$").unwrap();
+    match definition.split_once('=') {
+        Some((name, value)) if name_re.is_match(name) => {
+            return Ok((String::from(name), String::from(value)))
+        }
+        Some((name, _)) => return Err(format!("invalid variable name `{}`", name)),
+        None => return Err(format!("expected key=value, got `{}`", definition)),
+    }
+}
+
+/// 变量 `date` 使用的时间：设置了 `SOURCE_DATE_EPOCH`（秒数）时使用它，否则为 `now`
+///
+/// 见 <https://reproducible-builds.org/specs/source-date-epoch/>，无法解析的值被忽略。
+pub fn build_date(source_date_epoch: Option<&str>, now: SystemTime) -> SystemTime {
+    match source_date_epoch.and_then(|secs| secs.trim().parse::<u64>().ok()) {
+        Some(secs) => return UNIX_EPOCH + Duration::from_secs(secs),
+        None => return now,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> Vars {
+        let mut vars = Vars::builtin(Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)));
+        vars.define(String::from("team"), String::from("core"));
+        return vars;
+    }
+
+    #[test]
+    fn test_expand_defined() {
+        assert_eq!(
+            vars().expand("// (c) {{team}}, {{ date }}").unwrap(),
+            "// (c) core, 2023-11-14"
+        );
+        assert_eq!(
+            vars().expand("{{mdppet_version}}").unwrap(),
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+    #[test]
+    fn test_expand_undefined() {
+        assert_eq!(
+            vars().expand("{{team}} {{nope}}"),
+            Err(String::from("nope"))
+        );
+        let mut vars = vars();
+        vars.allow_undefined = true;
+        assert_eq!(vars.expand("{{team}} {{nope}}").unwrap(), "core {{nope}}");
+    }
+    #[test]
+    fn test_date_opt_in() {
+        assert_eq!(
+            Vars::builtin(None).expand("{{date}}"),
+            Err(String::from("date")),
+            "没有 --date-var 时不应定义 date"
+        );
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(build_date(None, now), now);
+        assert_eq!(build_date(Some("oops"), now), now);
+        let fixed = build_date(Some("86400"), now);
+        assert_eq!(
+            Vars::builtin(Some(fixed)).expand("{{date}}").unwrap(),
+            "1970-01-02"
+        );
+    }
+    #[test]
+    fn test_expand_escaped() {
+        assert_eq!(
+            vars().expand(r"\{{team}} is {{team}}").unwrap(),
+            "{{team}} is core"
+        );
+        assert_eq!(vars().expand(r"\{{nope}}").unwrap(), "{{nope}}");
+    }
+    #[test]
+    fn test_to_tabstops() {
+        let mut lines = vec![
+            String::from("fn {{name}}({{arg}}: {{ty}}) {"),
+            String::from("    // {{team}}: {{name}} \\{{raw}}"),
+            String::from("}"),
+        ];
+        to_tabstops(&mut lines, &vars());
+        assert_eq!(
+            lines,
+            vec![
+                "fn ${1:name}(${2:arg}: ${3:ty}) {",
+                "    // {{team}}: $1 \\{{raw}}",
+                "}"
+            ]
+        );
+        let mut lines = vec![
+            String::from("let ${2:x} = {{value}}; \\$9 $0"),
+            String::from("${1} {{value}}"),
+        ];
+        to_tabstops(&mut lines, &vars());
+        assert_eq!(lines, vec!["let ${2:x} = ${3:value}; \\$9 $0", "${1} $3"]);
+        let mut lines = vec![String::from(
+            "${1:${2}} ${3|\\$8,b|} ${3/(.*)/$9/} {{value}}",
+        )];
+        to_tabstops(&mut lines, &vars());
+        assert_eq!(
+            lines,
+            vec!["${1:${2}} ${3|\\$8,b|} ${3/(.*)/$9/} ${4:value}"]
+        );
+    }
+    #[test]
+    fn test_parse_define() {
+        assert_eq!(
+            parse_define("team=a=b"),
+            Ok((String::from("team"), String::from("a=b")))
+        );
+        assert!(parse_define("team").is_err());
+        assert!(parse_define("1x=y").is_err());
+    }
+}
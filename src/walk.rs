@@ -0,0 +1,192 @@
+use crate::error::MdppetError;
+use ignore::gitignore::Gitignore;
+use ignore::{Match, WalkBuilder};
+use std::collections::BTreeSet;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// 遍历目录时的选项：`--no-ignore`、`--hidden`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WalkOptions {
+    /// 遵循 `.gitignore` 和 `.ignore` 文件，不在 git 仓库中也是如此
+    pub ignore: bool,
+    /// 包括以 `.` 开头的文件和目录
+    pub hidden: bool,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        WalkOptions {
+            ignore: true,
+            hidden: false,
+        }
+    }
+}
+
+/// 按相同优先级查找的忽略文件，先出现的优先
+const IGNORE_FILES: &[&str] = &[".ignore", ".gitignore"];
+
+/// 没有在各级目录的忽略文件中找到对应的规则，如 git 的全局排除规则
+const UNKNOWN_RULE: &str = "matched an ignore rule (use --no-ignore to include it)";
+
+/// 递归列出 `dir` 中的 `.md` 文件，按路径排序
+pub fn markdown_files(dir: &Path, options: &WalkOptions) -> Result<Vec<PathBuf>, MdppetError> {
+    let mut found: Vec<PathBuf> = Vec::new();
+    for (path, is_dir) in walk(dir, options)? {
+        if !is_dir && is_markdown(&path) {
+            found.push(path);
+        }
+    }
+    found.sort();
+    return Ok(found);
+}
+
+/// 遍历 `dir` 时被跳过的 `.md` 文件及原因，供 `-v` 列出
+///
+/// 整个目录被跳过时只列出该目录；不含 `.md` 文件的目录（如 `.git`）不列出。
+pub fn skipped(dir: &Path, options: &WalkOptions) -> Result<Vec<(PathBuf, String)>, MdppetError> {
+    let visited: BTreeSet<PathBuf> = walk(dir, options)?
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect();
+    let everything = WalkOptions {
+        ignore: false,
+        hidden: true,
+    };
+    let mut skipped_dirs: Vec<PathBuf> = Vec::new();
+    let mut skipped_files: Vec<PathBuf> = Vec::new();
+    let mut files_in_dirs: Vec<PathBuf> = Vec::new();
+    for (path, is_dir) in walk(dir, &everything)? {
+        if visited.contains(&path) || (!is_dir && !is_markdown(&path)) {
+            continue;
+        }
+        if skipped_dirs.iter().any(|d| path.starts_with(d)) {
+            if !is_dir {
+                files_in_dirs.push(path);
+            }
+        } else if is_dir {
+            skipped_dirs.push(path);
+        } else {
+            skipped_files.push(path);
+        }
+    }
+    skipped_dirs.retain(|d| files_in_dirs.iter().any(|file| file.starts_with(d)));
+
+    let mut skipped: Vec<(PathBuf, String)> = Vec::new();
+    for path in skipped_dirs.into_iter().chain(skipped_files) {
+        let reason = reason(dir, &path, options);
+        skipped.push((path, reason));
+    }
+    skipped.sort();
+    return Ok(skipped);
+}
+
+/// 遍历 `dir`，返回访问到的路径以及它是否为目录，不含 `dir` 本身
+fn walk(dir: &Path, options: &WalkOptions) -> Result<Vec<(PathBuf, bool)>, MdppetError> {
+    let mut builder = WalkBuilder::new(dir);
+    builder
+        .standard_filters(options.ignore)
+        .hidden(!options.hidden)
+        .require_git(false)
+        .follow_links(true);
+    let mut paths: Vec<(PathBuf, bool)> = Vec::new();
+    for entry in builder.build() {
+        let entry = entry.map_err(|e| walk_error(dir, e))?;
+        if entry.depth() == 0 {
+            continue;
+        }
+        let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+        paths.push((entry.into_path(), is_dir));
+    }
+    return Ok(paths);
+}
+
+fn walk_error(dir: &Path, e: ignore::Error) -> MdppetError {
+    let message = e.to_string();
+    let e = e
+        .into_io_error()
+        .unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidData, message));
+    return MdppetError::Io(dir.to_path_buf(), e);
+}
+
+/// `path` 被跳过的原因：隐藏的文件，或命中的忽略规则及其所在的文件
+fn reason(dir: &Path, path: &Path, options: &WalkOptions) -> String {
+    let relative = path.strip_prefix(dir).unwrap_or(path);
+    let hidden = relative
+        .components()
+        .any(|c| c.as_os_str().to_string_lossy().starts_with('.'));
+    if hidden && !options.hidden {
+        return String::from("hidden (use --hidden to include it)");
+    }
+    let is_dir = path.is_dir();
+    for ancestor in path.ancestors().skip(1) {
+        for name in IGNORE_FILES.iter() {
+            let file = ancestor.join(name);
+            if !file.is_file() {
+                continue;
+            }
+            let (gitignore, _) = Gitignore::new(&file);
+            match gitignore.matched_path_or_any_parents(path, is_dir) {
+                Match::Ignore(glob) => {
+                    return format!(
+                        "ignored by `{}` in {} (use --no-ignore to include it)",
+                        glob.original(),
+                        file.display()
+                    );
+                }
+                Match::Whitelist(_) => return String::from(UNKNOWN_RULE),
+                Match::None => {}
+            }
+        }
+    }
+    return String::from(UNKNOWN_RULE);
+}
+
+fn is_markdown(path: &Path) -> bool {
+    return path.extension().is_some_and(|ext| ext == "md");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_skipped_reasons() {
+        let dir = std::env::temp_dir().join(format!("mdppet-walk-{}", std::process::id()));
+        fs::create_dir_all(dir.join("archive").join("old")).unwrap();
+        fs::create_dir_all(dir.join(".drafts")).unwrap();
+        fs::write(dir.join(".gitignore"), "archive/\n*.tmp.md\n").unwrap();
+        fs::write(dir.join("a.md"), "").unwrap();
+        fs::write(dir.join("b.tmp.md"), "").unwrap();
+        fs::write(dir.join("archive").join("old").join("c.md"), "").unwrap();
+        fs::write(dir.join(".drafts").join("d.md"), "").unwrap();
+
+        let options = WalkOptions::default();
+        assert_eq!(
+            markdown_files(&dir, &options).unwrap(),
+            vec![dir.join("a.md")]
+        );
+        let reported = skipped(&dir, &options).unwrap();
+        let paths: Vec<&PathBuf> = reported.iter().map(|(path, _)| path).collect();
+        assert_eq!(
+            paths,
+            vec![
+                &dir.join(".drafts"),
+                &dir.join("archive"),
+                &dir.join("b.tmp.md")
+            ]
+        );
+        assert!(reported[0].1.starts_with("hidden"));
+        assert!(reported[1].1.starts_with("ignored by `archive/`"));
+        assert!(reported[2].1.starts_with("ignored by `*.tmp.md`"));
+
+        let everything = WalkOptions {
+            ignore: false,
+            hidden: true,
+        };
+        assert_eq!(markdown_files(&dir, &everything).unwrap().len(), 4);
+        assert!(skipped(&dir, &everything).unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
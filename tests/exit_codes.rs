@@ -0,0 +1,841 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const BIN: &str = env!("CARGO_BIN_EXE_mdppet");
+
+/// 目录中所有文件的相对路径和内容
+fn read_tree(dir: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+    let mut files: BTreeMap<PathBuf, Vec<u8>> = BTreeMap::new();
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            for (name, bytes) in read_tree(&path) {
+                files.insert(Path::new(path.file_name().unwrap()).join(name), bytes);
+            }
+        } else {
+            let bytes = std::fs::read(&path).unwrap();
+            files.insert(PathBuf::from(path.file_name().unwrap()), bytes);
+        }
+    }
+    files
+}
+
+#[test]
+fn test_exit_code_conflicting_duplicates() {
+    let dest = std::env::temp_dir().join(format!("mdppet-exit-{}.json", std::process::id()));
+    let status = Command::new(BIN)
+        .args(["tests/test_markdown_conflict.md", "-o"])
+        .arg(&dest)
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(3));
+    assert!(!dest.exists(), "校验失败时不应写出文件");
+}
+#[test]
+fn test_color() {
+    let run = |color: &str, no_color: &str| {
+        let output = Command::new(BIN)
+            .args(["tests/does-not-exist.md", "--list", "--color", color])
+            .env("NO_COLOR", no_color)
+            .output()
+            .unwrap();
+        String::from_utf8(output.stderr).unwrap()
+    };
+    assert!(run("always", "1").contains("\x1b[1m\x1b[31merror:\x1b[0m"));
+    assert!(!run("never", "").contains('\x1b'));
+    assert!(!run("auto", "1").contains('\x1b'), "NO_COLOR 应当关闭着色");
+}
+#[test]
+fn test_exit_code_missing_source() {
+    let status = Command::new(BIN)
+        .args(["tests/does-not-exist.md", "--list"])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(1));
+}
+#[test]
+fn test_reports_every_error() {
+    let dest = std::env::temp_dir().join(format!("mdppet-errors-{}.json", std::process::id()));
+    let output = Command::new(BIN)
+        .args(["tests/test_markdown_errors.md", "-o"])
+        .arg(&dest)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr.contains(
+        "test_markdown_errors.md:9: snippet `mixed` has more than one code block without a language"
+    ));
+    assert!(
+        stderr.contains("test_markdown_errors.md:23: snippet `foreign`: code block language `lua`")
+    );
+    assert!(
+        stderr.contains("test_markdown_errors.md:33: snippet `undefined` uses undefined variable")
+    );
+    assert!(stderr.contains("found 3 error(s)"));
+    assert!(!dest.exists(), "存在错误时不应写出文件");
+
+    let output = Command::new(BIN)
+        .args(["tests/test_markdown_errors.md", "--fail-fast", "-o"])
+        .arg(&dest)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stderr.matches(": error: tests/").count(), 1);
+    assert!(!dest.exists());
+
+    let status = Command::new(BIN)
+        .args(["tests/test_markdown_errors.md", "--keep-going", "-o"])
+        .arg(&dest)
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(2));
+    let written = std::fs::read_to_string(&dest).unwrap();
+    assert!(written.contains("\"good\""));
+    std::fs::remove_file(&dest).unwrap();
+}
+#[test]
+fn test_fmt_check() {
+    let src = std::env::temp_dir().join(format!("mdppet-fmt-{}.md", std::process::id()));
+    std::fs::copy("tests/test_markdown_fmt.md", &src).unwrap();
+    let status = Command::new(BIN)
+        .args(["fmt", "--check"])
+        .arg(&src)
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(3));
+    let original = std::fs::read_to_string("tests/test_markdown_fmt.md").unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&src).unwrap(),
+        original,
+        "--check 不应写入"
+    );
+
+    let status = Command::new(BIN).arg("fmt").arg(&src).status().unwrap();
+    assert_eq!(status.code(), Some(0));
+    let status = Command::new(BIN)
+        .args(["fmt", "--check"])
+        .arg(&src)
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(0));
+    std::fs::remove_file(&src).unwrap();
+}
+#[test]
+fn test_normalize_in_place() {
+    let src = std::env::temp_dir().join(format!("mdppet-normalize-{}.md", std::process::id()));
+    std::fs::copy("tests/test_markdown_variants.md", &src).unwrap();
+    let printed = Command::new(BIN)
+        .arg("normalize")
+        .arg(&src)
+        .output()
+        .unwrap();
+    assert_eq!(printed.status.code(), Some(0));
+    let original = std::fs::read_to_string("tests/test_markdown_variants.md").unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&src).unwrap(),
+        original,
+        "没有 --in-place 时不应写入"
+    );
+
+    let normalize = || {
+        Command::new(BIN)
+            .args(["normalize", "--in-place"])
+            .arg(&src)
+            .output()
+            .unwrap()
+    };
+    let first = normalize();
+    assert_eq!(first.status.code(), Some(0));
+    let normalized = std::fs::read_to_string(&src).unwrap();
+    assert_eq!(normalized.as_bytes(), &printed.stdout[..]);
+    assert!(normalized.contains("# license-rust/lic/rust\n"));
+
+    let second = normalize();
+    assert_eq!(second.status.code(), Some(0));
+    assert!(second.stdout.is_empty(), "第二次整理不应改变文件");
+    assert_eq!(std::fs::read_to_string(&src).unwrap(), normalized);
+
+    std::fs::copy("tests/test_markdown_commentify.md", &src).unwrap();
+    assert_eq!(normalize().status.code(), Some(0));
+    let normalized = std::fs::read_to_string(&src).unwrap();
+    assert!(
+        normalized.contains("<!-- mdppet:commentify -->"),
+        "指令应当保留"
+    );
+    assert!(!normalized.contains("-rust/"), "不应展开 commentify");
+    std::fs::remove_file(&src).unwrap();
+}
+#[test]
+fn test_expand() {
+    let expand = |args: &[&str]| {
+        Command::new(BIN)
+            .args(["expand", "tests/test_markdown_expand.md", "--quiet"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let by_id = expand(&["--id", "newfn"]);
+    assert_eq!(by_id.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8(by_id.stdout).unwrap(),
+        "// <TM_FILENAME> by <TM_DIRECTORY>\nfn name(arg: i32) -> i32 {\n    name(arg);\n    \n}\n"
+    );
+    let by_prefix = expand(&["--prefix", "fn"]);
+    assert_eq!(by_prefix.status.code(), Some(0));
+    let stdout = String::from_utf8(by_prefix.stdout).unwrap();
+    assert!(stdout.starts_with("--- newfn\n// <TM_FILENAME>"));
+    assert!(stdout.ends_with("--- pyfn\ndef name():\n    pass\n"));
+    let missing = expand(&["--prefix", "nope"]);
+    assert_eq!(missing.status.code(), Some(2));
+    assert!(String::from_utf8(missing.stderr)
+        .unwrap()
+        .contains("no snippet with prefix `nope`"));
+}
+#[test]
+fn test_fence_scope() {
+    let dest = std::env::temp_dir().join(format!("mdppet-fence-{}.json", std::process::id()));
+    let build = |fix: bool| {
+        let mut command = Command::new(BIN);
+        command
+            .args(["tests/test_markdown_fence_scope.md", "--quiet", "-o"])
+            .arg(&dest);
+        if fix {
+            command.arg("--fix-scope-from-fence");
+        }
+        let output = command.output().unwrap();
+        assert_eq!(output.status.code(), Some(0));
+        String::from_utf8(output.stderr).unwrap()
+    };
+    let stderr = build(false);
+    assert_eq!(stderr.matches("mdppet: warning:").count(), 1, "{}", stderr);
+    assert!(stderr.contains(
+        "tests/test_markdown_fence_scope.md:7: code block of snippet `disagree` is marked `python`, which is not one of its scopes (rust)"
+    ));
+    let stderr = build(true);
+    assert!(!stderr.contains("mdppet: warning:"), "{}", stderr);
+    let text = std::fs::read_to_string(&dest).unwrap();
+    let map: serde_json::Value = serde_json::from_str(text.split_once('\n').unwrap().1).unwrap();
+    assert_eq!(map["disagree"]["scope"], "rust,python");
+    assert_eq!(map["plain"]["scope"], "rust");
+    std::fs::remove_file(&dest).unwrap();
+}
+#[test]
+fn test_split_parts() {
+    let dir = std::env::temp_dir().join(format!("mdppet-parts-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("out")).unwrap();
+    let mut text = String::new();
+    for i in (0..50).rev() {
+        text.push_str(&format!(
+            "# s{:02}/p{}/rust\n\n```rust\nx{}\n```\n\n",
+            i, i, i
+        ));
+    }
+    std::fs::write(dir.join("src.md"), text).unwrap();
+    let build_with = |limit: &str, extra: &[&str]| {
+        Command::new(BIN)
+            .arg(dir.join("src.md"))
+            .args([
+                "--quiet",
+                "--summary",
+                "json",
+                "--format",
+                "code-snippets",
+                "--max-file-size",
+                limit,
+            ])
+            .args(extra)
+            .arg("-o")
+            .arg(dir.join("out/all.code-snippets"))
+            .output()
+            .unwrap()
+    };
+    let build =
+        |limit: &str, check: bool| build_with(limit, if check { &["--check"] } else { &[] });
+    let summary = |output: &std::process::Output| -> serde_json::Value {
+        let stderr = String::from_utf8(output.stderr.clone()).unwrap();
+        serde_json::from_str(stderr.lines().last().unwrap()).unwrap()
+    };
+    let first = build("1000", false);
+    assert_eq!(first.status.code(), Some(0));
+    assert_eq!(summary(&first)["splits"][0]["parts"], 5);
+    assert!(
+        !String::from_utf8(first.stdout.clone())
+            .unwrap()
+            .contains("split into"),
+        "拆分的概要应当只出现在构建统计中"
+    );
+    let parts = read_tree(&dir.join("out"));
+    let names: Vec<String> = parts
+        .keys()
+        .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(
+        names,
+        (1..=5)
+            .map(|i| format!("all.{}.code-snippets", i))
+            .collect::<Vec<String>>()
+    );
+    assert!(parts.values().all(|part| part.len() <= 1000));
+    assert_eq!(build("1000", true).status.code(), Some(0));
+    assert_eq!(build("1000", false).status.code(), Some(0));
+    assert!(
+        read_tree(&dir.join("out")) == parts,
+        "重新生成时各部分不应改变"
+    );
+
+    let changed = build("100000", true);
+    assert_eq!(changed.status.code(), Some(3));
+    let stdout = String::from_utf8(changed.stdout).unwrap();
+    assert!(stdout.contains("all.code-snippets: missing"));
+    assert_eq!(stdout.matches("stale, would be removed").count(), 5);
+    let merged = build("100000", false);
+    assert_eq!(merged.status.code(), Some(0));
+    assert_eq!(summary(&merged)["removed"].as_array().unwrap().len(), 5);
+    assert_eq!(
+        read_tree(&dir.join("out")).len(),
+        1,
+        "不再拆分时应当删除旧的各部分"
+    );
+
+    // 沿用已有文件的缩进时，各部分的大小应按该缩进计算
+    let dest = dir.join("out/all.code-snippets");
+    let wide: String = std::fs::read_to_string(&dest)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let indent = line.len() - line.trim_start().len();
+            format!("{}{}\n", " ".repeat(indent * 4), line.trim_start())
+        })
+        .collect();
+    std::fs::write(&dest, wide).unwrap();
+    let matched = build_with("1000", &["--match-existing-style"]);
+    assert_eq!(matched.status.code(), Some(0));
+    let parts = read_tree(&dir.join("out"));
+    assert!(parts.len() > 5);
+    assert!(parts.values().all(|part| part.len() <= 1000));
+    assert!(parts
+        .values()
+        .all(|part| String::from_utf8_lossy(part).contains("\n        \"")));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+#[test]
+fn test_build_check() {
+    let dest = std::env::temp_dir().join(format!("mdppet-check-{}.json", std::process::id()));
+    let build = |src: &str, check: bool| {
+        let mut command = Command::new(BIN);
+        command.args([src, "-o"]).arg(&dest);
+        if check {
+            command.arg("--check");
+        }
+        command.output().unwrap()
+    };
+    let output = build("tests/test_markdown_check.md", true);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(!dest.exists(), "--check 不应写入");
+
+    assert_eq!(
+        build("tests/test_markdown_check.md", false).status.code(),
+        Some(0)
+    );
+    let output = build("tests/test_markdown_check.md", true);
+    assert_eq!(output.status.code(), Some(0));
+
+    let written = std::fs::read_to_string(&dest).unwrap();
+    let output = build("tests/test_markdown_check_changed.md", true);
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("out of date"));
+    assert!(stdout.contains("- removed"));
+    assert!(stdout.contains("~ changed"));
+    assert!(stdout.contains("+ added"));
+    assert_eq!(std::fs::read_to_string(&dest).unwrap(), written);
+    std::fs::remove_file(&dest).unwrap();
+}
+#[test]
+fn test_workspace_targets() {
+    let dir = std::env::temp_dir().join(format!("mdppet-workspace-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for name in ["mdppet.toml", "snippets.md"] {
+        std::fs::copy(format!("tests/workspace/{}", name), dir.join(name)).unwrap();
+    }
+    let build = |args: &[&str]| {
+        Command::new(BIN)
+            .arg("build")
+            .args(args)
+            .args(["--summary", "json", "--config"])
+            .arg(dir.join("mdppet.toml"))
+            .output()
+            .unwrap()
+    };
+    let output = build(&["vscode"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(dir.join("vscode/rust.code-snippets").exists());
+    assert!(!dir.join("zed").exists(), "只应构建指定的目标");
+    assert_eq!(build(&["zed", "--check"]).status.code(), Some(3));
+    assert!(!dir.join("zed").exists(), "--check 不应创建输出目录");
+
+    let output = build(&[]);
+    assert_eq!(output.status.code(), Some(0));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let summary: serde_json::Value = serde_json::from_str(stderr.lines().last().unwrap()).unwrap();
+    assert_eq!(summary["sources"], 1);
+    assert_eq!(summary["snippets"], 3, "共享的源文件只应解析一次");
+    let outputs: Vec<(&str, u64)> = summary["outputs"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|o| {
+            (
+                o["target"].as_str().unwrap(),
+                o["snippets"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(outputs, vec![("vscode", 2), ("zed", 1), ("zed", 1)]);
+    let vscode = std::fs::read_to_string(dir.join("vscode/rust.code-snippets")).unwrap();
+    assert!(vscode.contains("\"test\"") && !vscode.contains("\"def\""));
+    let zed = std::fs::read_to_string(dir.join("zed/rust.json")).unwrap();
+    assert!(zed.contains("\"let\"") && !zed.contains("\"test\""));
+    assert!(dir.join("zed/python.json").exists());
+
+    assert_eq!(build(&["--check"]).status.code(), Some(0));
+    std::fs::write(dir.join("zed/python.json"), "{}").unwrap();
+    let output = build(&["--check"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("python.json: out of date"));
+
+    let output = build(&["nvim"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("no target named `nvim`, the defined targets are: vscode, zed"));
+
+    let output = build(&["zed", "--max-file-size", "1000"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("target `zed`: --max-snippets-per-file and --max-file-size only apply to a single output file"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+#[test]
+fn test_deprecated_modes() {
+    let dest = std::env::temp_dir().join(format!("mdppet-deprecated-{}.json", std::process::id()));
+    let build = |mode: &str| {
+        let status = Command::new(BIN)
+            .args([
+                "tests/test_markdown_deprecated.md",
+                "--quiet",
+                "--deprecated",
+                mode,
+                "-o",
+            ])
+            .arg(&dest)
+            .status()
+            .unwrap();
+        assert_eq!(status.code(), Some(0));
+        let text = std::fs::read_to_string(&dest).unwrap();
+        // 跳过第一行的生成标记
+        serde_json::from_str::<serde_json::Value>(text.split_once('\n').unwrap().1).unwrap()
+    };
+    let skipped = build("skip");
+    assert_eq!(skipped.as_object().unwrap().len(), 1);
+    assert!(
+        skipped.get("print").is_none(),
+        "skip 不应写入弃用的 Snippet"
+    );
+
+    let annotated = build("annotate");
+    assert_eq!(annotated.as_object().unwrap().len(), 3);
+    assert_eq!(
+        annotated["print"]["description"],
+        serde_json::json!(["打印，不换行 (deprecated)"])
+    );
+    assert_eq!(
+        annotated["old"]["description"],
+        serde_json::json!(["(deprecated)"])
+    );
+    assert_eq!(
+        annotated["println"]["description"],
+        serde_json::json!(["打印一行"])
+    );
+
+    let included = build("include");
+    assert_eq!(included.as_object().unwrap().len(), 3);
+    assert_eq!(
+        included["print"]["description"],
+        serde_json::json!(["打印，不换行"])
+    );
+    assert!(included["old"].get("description").is_none());
+    std::fs::remove_file(&dest).unwrap();
+}
+#[test]
+fn test_lenient() {
+    let dest = std::env::temp_dir().join(format!("mdppet-lenient-{}.json", std::process::id()));
+    let build = |lenient: bool| {
+        let mut command = Command::new(BIN);
+        command
+            .args([
+                "tests/test_markdown_empty_fields.md",
+                "--message-format",
+                "json",
+                "-o",
+            ])
+            .arg(&dest);
+        if lenient {
+            command.arg("--lenient");
+        }
+        command.output().unwrap()
+    };
+    let output = build(false);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(r#""code":"empty-prefix""#), "{}", stderr);
+    assert!(stderr.contains(r#""code":"empty-body""#), "{}", stderr);
+
+    let output = build(true);
+    assert_eq!(output.status.code(), Some(0), "--lenient 应当只给出警告");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr.matches(r#""severity":"warning""#).count(), 2);
+    let text = std::fs::read_to_string(&dest).unwrap();
+    let written: serde_json::Value =
+        serde_json::from_str(text.split_once('\n').unwrap().1).unwrap();
+    let keys: Vec<&String> = written.as_object().unwrap().keys().collect();
+    assert_eq!(keys, vec!["ok"], "出错的 Snippet 应当被丢弃");
+    std::fs::remove_file(&dest).unwrap();
+}
+#[test]
+fn test_match_existing_style() {
+    let dest =
+        std::env::temp_dir().join(format!("mdppet-style-{}.code-snippets", std::process::id()));
+    let fixture = std::fs::read_to_string("tests/vscode/snippets.code-snippets").unwrap();
+    std::fs::write(&dest, &fixture).unwrap();
+    let output = Command::new(BIN)
+        .args(["tests/vscode/snippets.md", "--format", "code-snippets"])
+        .args(["--match-existing-style", "--quiet", "-o"])
+        .arg(&dest)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        std::fs::read_to_string(&dest).unwrap(),
+        fixture,
+        "应沿用原文件的缩进和转义"
+    );
+    std::fs::remove_file(&dest).unwrap();
+}
+#[test]
+fn test_directory_respects_gitignore() {
+    let dir = std::env::temp_dir().join(format!("mdppet-ignore-{}", std::process::id()));
+    let dest = dir.join("out.json");
+    std::fs::create_dir_all(dir.join("notes").join("node_modules")).unwrap();
+    std::fs::write(dir.join("notes").join(".gitignore"), "node_modules/\n").unwrap();
+    std::fs::write(
+        dir.join("notes").join("kept.md"),
+        "# kept/kept/rust\n\n```rust\nkept\n```\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("notes").join("node_modules").join("vendored.md"),
+        "# vendored/vendored/rust\n\n```rust\nvendored\n```\n",
+    )
+    .unwrap();
+    let build = |extra: &[&str]| {
+        Command::new(BIN)
+            .arg(dir.join("notes"))
+            .arg("-o")
+            .arg(&dest)
+            .args(["--force", "-v"])
+            .args(extra)
+            .output()
+            .unwrap()
+    };
+
+    let output = build(&[]);
+    assert_eq!(output.status.code(), Some(0));
+    let written = std::fs::read_to_string(&dest).unwrap();
+    assert!(written.contains("\"kept\""));
+    assert!(
+        !written.contains("vendored"),
+        "被 .gitignore 排除的目录不应被读取"
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("node_modules: ignored by `node_modules/`"));
+
+    assert_eq!(build(&["--no-ignore"]).status.code(), Some(0));
+    assert!(std::fs::read_to_string(&dest).unwrap().contains("vendored"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+#[test]
+fn test_summary_json() {
+    let dest = std::env::temp_dir().join(format!("mdppet-summary-{}.json", std::process::id()));
+    let output = Command::new(BIN)
+        .args([
+            "tests/test_markdown_check.md",
+            "tests/test_markdown_no_description.md",
+            "--summary",
+            "json",
+            "--quiet",
+            "-o",
+        ])
+        .arg(&dest)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let summary: serde_json::Value = serde_json::from_str(stderr.lines().last().unwrap()).unwrap();
+    assert_eq!(summary["sources"], 2);
+    assert_eq!(summary["snippets"], 5);
+    assert_eq!(summary["warnings"], 0);
+    assert_eq!(summary["errors"], 0);
+    assert_eq!(summary["outputs"][0]["file"], dest.display().to_string());
+    assert_eq!(summary["outputs"][0]["snippets"], 5);
+    assert!(summary["elapsed_ms"].is_u64());
+    std::fs::remove_file(&dest).unwrap();
+}
+/// `--message-format json` 中一行的结构，与 `Diagnostic` 对应
+#[derive(Deserialize, Debug)]
+struct Diagnostic {
+    severity: String,
+    code: String,
+    file: String,
+    line: Option<usize>,
+    column: Option<usize>,
+    identifier: Option<String>,
+    message: String,
+}
+#[test]
+fn test_message_format_json() {
+    let dest = std::env::temp_dir().join(format!("mdppet-json-{}.json", std::process::id()));
+    let output = Command::new(BIN)
+        .args([
+            "tests/test_markdown_errors.md",
+            "--message-format",
+            "json",
+            "--messages-on-stdout",
+            "--quiet",
+            "-o",
+        ])
+        .arg(&dest)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stderr.is_empty(), "诊断信息应当只输出到标准输出");
+    let diagnostics: Vec<Diagnostic> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(diagnostics.len(), 3);
+    assert!(diagnostics.iter().all(|d| d.severity == "error"));
+    assert!(diagnostics
+        .iter()
+        .all(|d| d.file == "tests/test_markdown_errors.md" && d.column == Some(1)));
+    let lines: Vec<Option<usize>> = diagnostics.iter().map(|d| d.line).collect();
+    assert_eq!(lines, vec![Some(9), Some(23), Some(33)]);
+    assert_eq!(diagnostics[0].code, "parse-error");
+    assert_eq!(diagnostics[2].code, "undefined-variable");
+    assert_eq!(diagnostics[2].identifier.as_deref(), Some("undefined"));
+    assert!(diagnostics[2].message.contains("`{{nope}}`"));
+    assert!(!dest.exists());
+}
+#[test]
+fn test_emit_sourcemap() {
+    let dir = std::env::temp_dir().join(format!("mdppet-sourcemap-{}", std::process::id()));
+    let map = dir.join("map.json");
+    let status = Command::new(BIN)
+        .args([
+            "tests/test_markdown_sourcemap.md",
+            "--quiet",
+            "--format",
+            "zed",
+            "--rename",
+            "greet=hello",
+            "--out-dir",
+        ])
+        .arg(&dir)
+        .arg("--emit-sourcemap")
+        .arg(&map)
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(0));
+    let map: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&map).unwrap()).unwrap();
+    let output = |name: &str| dir.join(name).display().to_string();
+    let location = |file: &str, heading: usize, start: usize, end: usize| {
+        serde_json::json!({
+            "file": file,
+            "heading_line": heading,
+            "body_start_line": start,
+            "body_end_line": end,
+        })
+    };
+    let src = "tests/test_markdown_sourcemap.md";
+    let rust = &map[output("rust.json")];
+    let python = &map[output("python.json")];
+    assert!(rust.get("greet").is_none(), "应当使用改名之后的标识符");
+    assert_eq!(rust["hello"], location(src, 3, 8, 9));
+    assert_eq!(python["lic-python"], location(src, 12, 15, 15));
+    assert_eq!(rust["lic-rust"], location(src, 12, 19, 19));
+    assert_eq!(python["log"], location(src, 22, 25, 25));
+    assert_eq!(rust["log"], location(src, 22, 25, 25));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // 不同源文件中的同名 Snippet 写入各自的输出，位置表中各自指向自己的定义
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("one.md"), "# x/x/rust\n\n```rust\none\n```\n").unwrap();
+    std::fs::write(dir.join("two.md"), "\n# x/x/rust\n\n```rust\ntwo\n```\n").unwrap();
+    let build = || {
+        let output = Command::new(BIN)
+            .arg(dir.join("one.md"))
+            .arg(dir.join("two.md"))
+            .arg("--verbose")
+            .arg("--out-dir")
+            .arg(dir.join("out"))
+            .arg("--emit-sourcemap")
+            .arg(dir.join("map.json"))
+            .arg("--relative-to")
+            .arg(&dir)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0));
+        let map: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("map.json")).unwrap()).unwrap();
+        (map, String::from_utf8(output.stderr).unwrap())
+    };
+    let (map, _) = build();
+    assert_eq!(map["out/one.json"]["x"], location("one.md", 1, 4, 4));
+    assert_eq!(map["out/two.json"]["x"], location("two.md", 2, 5, 5));
+
+    // 所有输出都已是最新时仍应写出位置表
+    std::fs::remove_file(dir.join("map.json")).unwrap();
+    let (incremental, stderr) = build();
+    assert!(stderr.contains("it is up to date"));
+    assert_eq!(incremental, map);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+#[test]
+fn test_deterministic_source_order() {
+    let sources = [
+        "tests/determinism/a.md",
+        "tests/determinism/b.md",
+        "tests/determinism/nested/c.md",
+        "tests/determinism/nested/d.md",
+    ];
+    let orders: [[usize; 4]; 3] = [[0, 1, 2, 3], [3, 1, 0, 2], [2, 3, 1, 0]];
+    // 每次构建写入同一个目录，位置表中的输出路径才会相同
+    let dir = std::env::temp_dir().join(format!("mdppet-order-{}", std::process::id()));
+    let mut builds: Vec<BTreeMap<PathBuf, Vec<u8>>> = Vec::new();
+    for order in orders.iter() {
+        let shuffled: Vec<&str> = order.iter().map(|&i| sources[i]).collect();
+        std::fs::create_dir_all(&dir).unwrap();
+        let merged = Command::new(BIN)
+            .args(&shuffled)
+            .args(["--quiet", "--format", "code-snippets", "-o"])
+            .arg(dir.join("all.code-snippets"))
+            .arg("--emit-sourcemap")
+            .arg(dir.join("map.json"))
+            .status()
+            .unwrap();
+        assert_eq!(merged.code(), Some(0));
+        let routed = Command::new(BIN)
+            .args(&shuffled)
+            .args(["--quiet", "--format", "zed", "--out-dir"])
+            .arg(dir.join("zed"))
+            .status()
+            .unwrap();
+        assert_eq!(routed.code(), Some(0));
+        builds.push(read_tree(&dir));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    assert_eq!(
+        builds[0].len(),
+        5,
+        "应当写出合并的输出、位置表和三个 zed 文件"
+    );
+    assert!(
+        builds.iter().all(|build| build == &builds[0]),
+        "源文件的顺序不应影响输出"
+    );
+}
+#[test]
+fn test_out_dir_collision() {
+    let dir = std::env::temp_dir().join(format!("mdppet-collision-{}", std::process::id()));
+    for sub in ["x", "y"] {
+        std::fs::create_dir_all(dir.join(sub)).unwrap();
+        std::fs::write(
+            dir.join(sub).join("s.md"),
+            format!("# {}/{}/rust\n\n```rust\n{}\n```\n", sub, sub, sub),
+        )
+        .unwrap();
+    }
+    let output = Command::new(BIN)
+        .arg(dir.join("x/s.md"))
+        .arg(dir.join("y/s.md"))
+        .arg("--out-dir")
+        .arg(dir.join("out"))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("would both be written to"));
+    assert!(!dir.join("out/s.json").exists(), "冲突时不应写入");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+#[test]
+fn test_tmsnippet_shared_output() {
+    let dir = std::env::temp_dir().join(format!("mdppet-tmsnippet-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("s.md"),
+        "# a/a/rust @file shared.tmSnippet\n\n```rust\na\n```\n\n# b/b/rust @file shared.tmSnippet\n\n```rust\nb\n```\n",
+    )
+    .unwrap();
+    let output = Command::new(BIN)
+        .arg(dir.join("s.md"))
+        .args(["--format", "tmsnippet", "--out-dir"])
+        .arg(dir.join("out"))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("`a`, `b` are routed to it"));
+    assert!(
+        !dir.join("out/shared.tmSnippet").exists(),
+        "不应写出含有多个 plist 的文件"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+#[test]
+fn test_deterministic_jobs() {
+    let dir = std::env::temp_dir().join(format!("mdppet-jobs-{}", std::process::id()));
+    let build = |jobs: &str| {
+        let output = Command::new(BIN)
+            .args(["tests/determinism", "--quiet", "--jobs", jobs, "--out-dir"])
+            .arg(dir.join("out"))
+            .arg("--emit-sourcemap")
+            .arg(dir.join("map.json"))
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0));
+        let files = read_tree(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        (files, output.stderr)
+    };
+    let (single, single_stderr) = build("1");
+    let (parallel, parallel_stderr) = build("8");
+    assert_eq!(single.len(), 5);
+    assert!(single == parallel, "--jobs 不应影响输出");
+    assert_eq!(
+        String::from_utf8(single_stderr).unwrap(),
+        String::from_utf8(parallel_stderr).unwrap(),
+        "--jobs 不应影响诊断信息"
+    );
+}